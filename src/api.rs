@@ -0,0 +1,354 @@
+//! Cloudflare REST API calls, for the checks flarectl doesn't expose.
+
+use crate::http;
+use serde_json::Value;
+use std::fmt;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const API_BASE: &str = "https://api.cloudflare.com/client/v4";
+/// Longest a single retry waits, whatever a 429 asks for
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(30);
+
+/// How `send` retries transient failures, from `--retries` and `--backoff`.
+/// Every helper here ends in `send`, including those run on the worker
+/// threads of `list --check` and `purge --parallel`, so the policy is set
+/// once at startup rather than passed down through each of them.
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    /// Wait before the first retry; doubled for each one after
+    pub backoff: Duration,
+}
+
+pub const DEFAULT_RETRIES: u32 = 2;
+pub const DEFAULT_BACKOFF_MS: u64 = 500;
+
+static RETRY_POLICY: Mutex<RetryPolicy> =
+    Mutex::new(RetryPolicy { retries: DEFAULT_RETRIES, backoff: Duration::from_millis(DEFAULT_BACKOFF_MS) });
+
+pub fn configure_retries(policy: RetryPolicy) {
+    *RETRY_POLICY.lock().expect("retry policy lock") = policy;
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The request never got a response
+    Http(http::Error),
+    /// Cloudflare answered with `success: false` or a non-2xx status.
+    /// `retry_after` is how long a 429 asked us to wait, when it said.
+    Api { status: u16, messages: Vec<String>, retry_after: Option<Duration> },
+    /// The response body wasn't the JSON envelope we expected
+    InvalidResponse(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "{}", e),
+            Error::Api { status, messages, .. } if messages.is_empty() => write!(f, "HTTP {}", status),
+            Error::Api { status, messages, .. } => write!(f, "{} (HTTP {})", messages.join("; "), status),
+            Error::InvalidResponse(e) => write!(f, "unexpected response from Cloudflare: {}", e),
+        }
+    }
+}
+
+/// Full URL for an API path like `zones/<id>/purge_cache`
+pub fn url(path: &str) -> String {
+    format!("{}/{}", API_BASE, path.trim_start_matches('/'))
+}
+
+/// What `send` does with a 429
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RateLimited {
+    /// Wait (as long as Cloudflare asks) and retry, within the retry policy
+    Retry,
+    /// Hand it straight back, for a caller running its own rate-limit loop;
+    /// retrying here too would multiply the two loops' attempts
+    Return,
+}
+
+/// Send an authenticated request and return the raw response, whatever its
+/// status once retries run out. Network errors, 5xx and (unless
+/// `rate_limited` says otherwise) 429 are retried with exponential backoff
+/// and jitter; anything else (401, 403...) is returned straight away, as
+/// trying again wouldn't change the answer.
+pub fn send(method: &str, token: &str, path: &str, body: Option<&Value>, rate_limited: RateLimited) -> Result<http::Response, Error> {
+    let mut req = http::Request::new(method, &url(path)).header("Authorization", &format!("Bearer {}", token));
+    if let Some(body) = body {
+        req = req.header("Content-Type", "application/json").body(body.to_string());
+    }
+    let policy = *RETRY_POLICY.lock().expect("retry policy lock");
+    with_retries(policy, rate_limited, || req.send()).map_err(Error::Http)
+}
+
+/// Make `attempt` until it gets an answer worth keeping or `policy` runs out
+fn with_retries(
+    policy: RetryPolicy,
+    rate_limited: RateLimited,
+    mut attempt: impl FnMut() -> Result<http::Response, http::Error>,
+) -> Result<http::Response, http::Error> {
+    let mut retry = 0;
+    loop {
+        let result = attempt();
+        let retryable = match &result {
+            Ok(response) => (response.status == 429 && rate_limited == RateLimited::Retry) || response.status >= 500,
+            Err(e) => is_transient(e),
+        };
+        if !retryable || retry >= policy.retries {
+            return result;
+        }
+        let backoff = policy.backoff.saturating_mul(1 << retry.min(16));
+        let asked = result.as_ref().ok().filter(|r| r.status == 429).and_then(retry_after);
+        let jitter_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| u64::from(d.subsec_millis()));
+        let jitter = Duration::from_millis(jitter_ms % (policy.backoff.as_millis() as u64).max(1));
+        thread::sleep((asked.unwrap_or(backoff) + jitter).min(MAX_RETRY_WAIT));
+        retry += 1;
+    }
+}
+
+/// Whether a failed transfer might work a moment later. A missing curl or
+/// a certificate that doesn't verify won't.
+fn is_transient(e: &http::Error) -> bool {
+    matches!(e, http::Error::Transfer { .. }) && e.stage() != http::Stage::Tls
+}
+
+/// Send an authenticated request and return the envelope's `result`
+pub fn request(method: &str, token: &str, path: &str, body: Option<&Value>, rate_limited: RateLimited) -> Result<Value, Error> {
+    let response = send(method, token, path, body, rate_limited)?;
+    let json: Value = serde_json::from_str(&response.body)
+        .map_err(|e| Error::InvalidResponse(format!("HTTP {}: {}", response.status, e)))?;
+
+    let success = json.get("success").and_then(Value::as_bool).unwrap_or(false);
+    if !success || !(200..300).contains(&response.status) {
+        let messages = json
+            .get("errors")
+            .and_then(Value::as_array)
+            .map(|errors| {
+                errors
+                    .iter()
+                    .filter_map(|e| e.get("message").and_then(Value::as_str))
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let retry_after = (response.status == 429).then(|| retry_after(&response)).flatten();
+        return Err(Error::Api { status: response.status, messages, retry_after });
+    }
+    Ok(json.get("result").cloned().unwrap_or(Value::Null))
+}
+
+/// An unauthenticated request to the API root, to tell "can't reach
+/// Cloudflare" apart from "Cloudflare refused the token". Any HTTP answer
+/// counts as reachable; returns its status and how long it took.
+pub fn probe(timeout: Duration) -> Result<(u16, Duration), http::Error> {
+    let started = Instant::now();
+    let response = http::Request::get(&url("")).timeout(timeout).send()?;
+    Ok((response.status, started.elapsed()))
+}
+
+pub fn get(token: &str, path: &str) -> Result<Value, Error> {
+    request("GET", token, path, None, RateLimited::Retry)
+}
+
+pub struct TokenStatus {
+    /// `active`, `disabled` or `expired`
+    pub status: String,
+    pub expires_on: Option<String>,
+}
+
+impl TokenStatus {
+    pub fn is_active(&self) -> bool {
+        self.status == "active"
+    }
+}
+
+pub fn verify_token(token: &str) -> Result<TokenStatus, Error> {
+    let result = get(token, "user/tokens/verify")?;
+    let field = |name: &str| result.get(name).and_then(Value::as_str).map(str::to_string);
+    Ok(TokenStatus {
+        status: field("status").unwrap_or_else(|| "unknown".to_string()),
+        expires_on: field("expires_on"),
+    })
+}
+
+pub struct Zone {
+    pub id: String,
+    pub name: String,
+    /// `active`, `pending`, `initializing`, `moved`, `deleted` or `deactivated`
+    pub status: String,
+    /// e.g. `Free Website`
+    pub plan: Option<String>,
+    /// Name of the account that owns the zone
+    pub account: Option<String>,
+    pub account_id: Option<String>,
+}
+
+impl Zone {
+    /// A zone known only by its id, for when it's given rather than looked up
+    pub fn from_id(id: &str, name: &str) -> Zone {
+        Zone { id: id.to_string(), name: name.to_string(), status: "unknown".to_string(), plan: None, account: None, account_id: None }
+    }
+}
+
+/// Whether `id` looks like a Cloudflare zone id: 32 hex characters
+pub fn is_zone_id(id: &str) -> bool {
+    id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Look up a zone by name
+pub fn zone(token: &str, zone: &str, rate_limited: RateLimited) -> Result<Zone, Error> {
+    let result = request("GET", token, &format!("zones?name={}", zone), None, rate_limited)?;
+    result
+        .as_array()
+        .and_then(|zones| zones.first())
+        .and_then(parse_zone)
+        .ok_or_else(|| Error::Api { status: 404, messages: vec![format!("zone {} not found", zone)], retry_after: None })
+}
+
+/// The zones a token can see, up to `limit` (Cloudflare allows 50 per page)
+pub fn zones(token: &str, limit: usize) -> Result<Vec<Zone>, Error> {
+    let result = get(token, &format!("zones?per_page={}", limit))?;
+    let zones = result.as_array().ok_or_else(|| Error::InvalidResponse("zones is not a list".to_string()))?;
+    Ok(zones.iter().filter_map(parse_zone).collect())
+}
+
+fn parse_zone(zone: &Value) -> Option<Zone> {
+    let field = |name: &str| zone.get(name).and_then(Value::as_str).map(str::to_string);
+    let nested = |name: &str, key: &str| zone.get(name).and_then(|v| v.get(key)).and_then(Value::as_str).map(str::to_string);
+    Some(Zone {
+        id: field("id")?,
+        name: field("name").unwrap_or_default(),
+        status: field("status").unwrap_or_else(|| "unknown".to_string()),
+        plan: nested("plan", "name"),
+        account: nested("account", "name"),
+        account_id: nested("account", "id"),
+    })
+}
+
+/// Look up a zone's id from its name
+pub fn zone_id(token: &str, name: &str) -> Result<String, Error> {
+    zone(token, name, RateLimited::Retry).map(|z| z.id)
+}
+
+/// Most hosts, prefixes or URLs one purge request may list; Cloudflare
+/// rejects longer lists outright
+pub const PURGE_BATCH_LIMIT: usize = 30;
+
+/// What a purge clears
+#[derive(Clone)]
+pub enum PurgeTarget {
+    Everything,
+    /// Everything cached for these hostnames
+    Hosts(Vec<String>),
+    /// URLs starting with these `host/path` prefixes (Enterprise zones)
+    Prefixes(Vec<String>),
+    /// These exact URLs
+    Files(Vec<String>),
+    /// Responses carrying these `Cache-Tag` values (Enterprise zones)
+    Tags(Vec<String>),
+}
+
+impl PurgeTarget {
+    /// The target split into requests Cloudflare will accept, in order
+    pub fn batches(&self) -> Vec<PurgeTarget> {
+        let split = |items: &[String], make: fn(Vec<String>) -> PurgeTarget| {
+            items.chunks(PURGE_BATCH_LIMIT).map(|chunk| make(chunk.to_vec())).collect()
+        };
+        match self {
+            PurgeTarget::Everything => vec![PurgeTarget::Everything],
+            PurgeTarget::Hosts(hosts) => split(hosts, PurgeTarget::Hosts),
+            PurgeTarget::Prefixes(prefixes) => split(prefixes, PurgeTarget::Prefixes),
+            PurgeTarget::Files(files) => split(files, PurgeTarget::Files),
+            PurgeTarget::Tags(tags) => split(tags, PurgeTarget::Tags),
+        }
+    }
+}
+
+impl fmt::Display for PurgeTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PurgeTarget::Everything => write!(f, "everything"),
+            PurgeTarget::Hosts(hosts) => write!(f, "hosts {}", hosts.join(", ")),
+            PurgeTarget::Prefixes(prefixes) => write!(f, "prefixes {}", prefixes.join(", ")),
+            PurgeTarget::Files(files) if files.len() <= 3 => write!(f, "files {}", files.join(", ")),
+            PurgeTarget::Files(files) => write!(f, "{} files", files.len()),
+            PurgeTarget::Tags(tags) => write!(f, "tags {}", tags.join(", ")),
+        }
+    }
+}
+
+/// Purge a zone's cache. A 429 comes straight back: purges run their own
+/// rate-limit loop, which reports each wait.
+pub fn purge(token: &str, zone_id: &str, target: &PurgeTarget) -> Result<(), Error> {
+    let body = match target {
+        PurgeTarget::Everything => serde_json::json!({ "purge_everything": true }),
+        PurgeTarget::Hosts(hosts) => serde_json::json!({ "hosts": hosts }),
+        PurgeTarget::Prefixes(prefixes) => serde_json::json!({ "prefixes": prefixes }),
+        PurgeTarget::Files(files) => serde_json::json!({ "files": files }),
+        PurgeTarget::Tags(tags) => serde_json::json!({ "tags": tags }),
+    };
+    request("POST", token, &format!("zones/{}/purge_cache", zone_id), Some(&body), RateLimited::Return).map(|_| ())
+}
+
+/// The wait a rate-limited response asks for: `Retry-After` in seconds, or
+/// failing that `X-RateLimit-Reset`, which is either seconds or a Unix time
+fn retry_after(response: &http::Response) -> Option<Duration> {
+    let seconds = |name: &str| response.header(name).and_then(|v| v.trim().parse::<u64>().ok());
+    if let Some(secs) = seconds("Retry-After") {
+        return Some(Duration::from_secs(secs));
+    }
+    let reset = seconds("X-RateLimit-Reset")?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    // Anything past a day is a timestamp rather than a countdown
+    Some(Duration::from_secs(if reset > 86_400 { reset.saturating_sub(now) } else { reset }))
+}
+
+/// Contents of the records in a zone matching `kind` and fully-qualified `name`
+pub fn dns_record_contents(token: &str, zone_id: &str, kind: &str, name: &str) -> Result<Vec<String>, Error> {
+    let result = get(token, &format!("zones/{}/dns_records?type={}&name={}", zone_id, kind, name))?;
+    let records = result.as_array().ok_or_else(|| Error::InvalidResponse("dns_records is not a list".to_string()))?;
+    Ok(records
+        .iter()
+        .filter_map(|r| r.get("content").and_then(Value::as_str))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Names of the zones a token can see, up to `limit`
+pub fn zone_names(token: &str, limit: usize) -> Result<Vec<String>, Error> {
+    zones(token, limit).map(|zones| zones.into_iter().map(|z| z.name).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: RetryPolicy = RetryPolicy { retries: 2, backoff: Duration::ZERO };
+
+    /// How many attempts `with_retries` makes against a transport that
+    /// always answers `status`
+    fn attempts(status: u16, rate_limited: RateLimited) -> u32 {
+        let mut attempts = 0;
+        let result = with_retries(POLICY, rate_limited, || {
+            attempts += 1;
+            Ok(http::Response::with_status(status))
+        });
+        assert_eq!(result.unwrap().status, status);
+        attempts
+    }
+
+    #[test]
+    fn rate_limits_are_retried_only_when_asked() {
+        assert_eq!(attempts(429, RateLimited::Retry), 3);
+        assert_eq!(attempts(429, RateLimited::Return), 1);
+    }
+
+    #[test]
+    fn server_errors_are_retried_either_way() {
+        assert_eq!(attempts(503, RateLimited::Retry), 3);
+        assert_eq!(attempts(503, RateLimited::Return), 3);
+        assert_eq!(attempts(403, RateLimited::Retry), 1);
+    }
+}
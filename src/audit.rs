@@ -0,0 +1,258 @@
+//! Audit trail of what cf-switch changed, as JSON lines in
+//! `~/.cf-switch-audit.jsonl` (opt in with `"audit_log": true`), and the
+//! filtering behind `cf-switch log`. Where to write is settled when the
+//! config loads and the acting profile when credentials resolve, while
+//! entries are recorded well away from either, by the purge and DNS helpers
+//! among others; so both are kept here from then on.
+
+use crate::ci;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static LOG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+static ACTING_PROFILE: Mutex<Option<String>> = Mutex::new(None);
+static CI_BUILD: Mutex<Option<ci::Build>> = Mutex::new(None);
+
+pub fn path() -> PathBuf {
+    dirs::home_dir()
+        .expect("Could not find home directory")
+        .join(".cf-switch-audit.jsonl")
+}
+
+/// Where to append entries; `None` turns recording off
+pub fn configure(path: Option<PathBuf>) {
+    *LOG_PATH.lock().expect("audit path lock") = path;
+}
+
+/// The profile whose credentials this command uses, for entries that don't name one
+pub fn acting_as(profile: Option<&str>) {
+    *ACTING_PROFILE.lock().expect("audit profile lock") = profile.map(str::to_string);
+}
+
+/// The CI build this command runs for, attached to every entry from now on
+pub fn triggered_by(build: Option<ci::Build>) {
+    *CI_BUILD.lock().expect("audit ci lock") = build;
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    /// Unix time
+    pub time: u64,
+    /// `switch`, `add`, `edit`, `remove`, `restore`, `purge` or `dns`
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    /// Cloudflare ids of what was acted on, when the API resolved them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<String>,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// The CI build that ran the command (`purge --ci`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ci: Option<ci::Build>,
+}
+
+impl Entry {
+    pub fn new(action: &str, ok: bool) -> Self {
+        Entry {
+            time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            action: action.to_string(),
+            profile: ACTING_PROFILE.lock().expect("audit profile lock").clone(),
+            zone: None,
+            zone_id: None,
+            account_id: None,
+            ok,
+            detail: None,
+            ci: CI_BUILD.lock().expect("audit ci lock").clone(),
+        }
+    }
+
+    pub fn profile(mut self, profile: &str) -> Self {
+        self.profile = Some(profile.to_string());
+        self
+    }
+
+    pub fn zone(mut self, zone: &str) -> Self {
+        self.zone = Some(zone.to_string());
+        self
+    }
+
+    pub fn ids(mut self, zone_id: &str, account_id: Option<&str>) -> Self {
+        self.zone_id = Some(zone_id.to_string());
+        self.account_id = account_id.map(str::to_string);
+        self
+    }
+
+    pub fn detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.trim().to_string());
+        self
+    }
+}
+
+/// Append an entry if logging is on. A log that can't be written shouldn't
+/// fail the command it describes, so errors only warn.
+pub fn record(entry: Entry) {
+    let Some(path) = LOG_PATH.lock().expect("audit path lock").clone() else {
+        return;
+    };
+    let line = serde_json::to_string(&entry).expect("Failed to serialize audit entry");
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut f| writeln!(f, "{}", line));
+    if let Err(e) = written {
+        writeln!(io::stderr(), "Warning: could not write to the audit log {}: {}", path.display(), e).ok();
+    }
+}
+
+/// Every entry in the log with its line as written, skipping lines that
+/// don't parse. A missing log reads as empty.
+pub fn read(path: &Path) -> io::Result<Vec<(String, Entry)>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok().map(|entry| (line.to_string(), entry)))
+        .collect())
+}
+
+/// `cf-switch log` criteria; unset ones match everything
+#[derive(Default)]
+pub struct Filter {
+    pub since: Option<u64>,
+    /// Exclusive
+    pub until: Option<u64>,
+    pub profile: Option<String>,
+    pub zone: Option<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.since.is_none_or(|since| entry.time >= since)
+            && self.until.is_none_or(|until| entry.time < until)
+            && self.profile.as_ref().is_none_or(|p| entry.profile.as_ref() == Some(p))
+            && self.zone.as_ref().is_none_or(|z| entry.zone.as_ref().is_some_and(|e| e.eq_ignore_ascii_case(z)))
+    }
+}
+
+/// Parse a `--since`/`--until` value: a Unix timestamp, a UTC date
+/// (`2026-10-14`) or date and time (`2026-10-14T09:30`, seconds optional,
+/// RFC 3339 fractions and offsets accepted), or an age like `30m`, `12h` or
+/// `7d`. A bare date given with `end_of_day` means the end of that day, so
+/// `--until 2026-10-14` includes the 14th.
+pub fn parse_time(value: &str, end_of_day: bool) -> Result<u64, String> {
+    let value = value.trim();
+    let invalid = || format!("'{}' isn't a date (YYYY-MM-DD[THH:MM[:SS]]), Unix time or age like 12h", value);
+    if let Ok(secs) = value.parse::<u64>() {
+        return Ok(secs);
+    }
+    if let Some(age) = parse_duration(value) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        return Ok(now.saturating_sub(age));
+    }
+    let (date, time) = match value.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    let numbers = |s: &str, sep: char| s.split(sep).map(|n| n.parse::<u64>().ok()).collect::<Option<Vec<u64>>>();
+    let [year, month, day] = numbers(date, '-').ok_or_else(invalid)?[..] else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return Err(invalid());
+    }
+    let midnight = days_from_civil(year, month, day) * 86_400;
+    let Some(time) = time else {
+        return Ok(if end_of_day { midnight + 86_400 } else { midnight });
+    };
+    // `Z`, or an offset like +02:00 to subtract to get UTC
+    let (time, offset) = match time.rfind(['+', '-']) {
+        Some(i) => {
+            let [hours, minutes] = numbers(&time[i + 1..], ':').ok_or_else(invalid)?[..] else {
+                return Err(invalid());
+            };
+            let secs = (hours * 3_600 + minutes * 60) as i64;
+            (&time[..i], if time[i..].starts_with('-') { -secs } else { secs })
+        }
+        None => (time.trim_end_matches(['Z', 'z']), 0),
+    };
+    // Fractions of a second don't matter here
+    let time = time.split_once('.').map_or(time, |(whole, _)| whole);
+    let (hour, minute, second) = match numbers(time, ':').ok_or_else(invalid)?[..] {
+        [h, m] => (h, m, 0),
+        [h, m, s] => (h, m, s),
+        _ => return Err(invalid()),
+    };
+    if hour > 23 || minute > 59 || second > 59 {
+        return Err(invalid());
+    }
+    Ok((midnight + hour * 3_600 + minute * 60 + second).saturating_add_signed(-offset))
+}
+
+/// Seconds in a duration like `90s`, `30m`, `12h`, `7d` or `2w`
+pub fn parse_duration(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let unit = value.chars().last().filter(|c| "smhdw".contains(*c))?;
+    let amount = value[..value.len() - 1].parse::<u64>().ok()?;
+    let per_unit = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        _ => 604_800,
+    };
+    amount.checked_mul(per_unit)
+}
+
+/// A duration in its largest whole unit, like `parse_duration` takes: `3d`, `5h`, `12m`
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        86_400.. => format!("{}d", secs / 86_400),
+        3_600.. => format!("{}h", secs / 3_600),
+        60.. => format!("{}m", secs / 60),
+        _ => format!("{}s", secs),
+    }
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in UTC
+pub fn format_time(secs: u64) -> String {
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    let rest = secs % 86_400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, rest / 3_600, rest % 3_600 / 60, rest % 60)
+}
+
+/// Days since 1970-01-01 for a date on or after it (Howard Hinnant's algorithm)
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
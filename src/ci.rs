@@ -0,0 +1,123 @@
+//! What CI build is running cf-switch, from the variables the common
+//! providers set, for `purge --ci`: the repository picks the zone through the
+//! config's `ci_zones`, and the commit and pull request go in the audit log.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Build {
+    /// `github`, `gitlab`, `circleci`, `bitbucket` or `buildkite`
+    pub provider: String,
+    /// `owner/name`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Pull (or merge) request number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pull_request: Option<String>,
+    /// Link to the build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+fn var(name: &str) -> Option<String> {
+    std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+/// `owner/name` from a slug or a clone URL (https or ssh, with or without `.git`)
+fn repo_slug(value: &str) -> Option<String> {
+    let path = value.trim_end_matches('/').trim_end_matches(".git");
+    let mut parts = path.rsplit(['/', ':']).filter(|p| !p.is_empty());
+    let name = parts.next()?;
+    let owner = parts.next()?;
+    Some(format!("{}/{}", owner, name))
+}
+
+/// The build this runs in, if any provider's variables are set
+pub fn detect() -> Option<Build> {
+    let build = |provider: &str, repo: Option<String>| Build {
+        provider: provider.to_string(),
+        repo: repo.as_deref().and_then(repo_slug),
+        commit: None,
+        branch: None,
+        pull_request: None,
+        url: None,
+    };
+    if var("GITHUB_ACTIONS").is_some() {
+        // Pull request builds run on refs/pull/<n>/merge
+        let pull_request = var("GITHUB_REF").and_then(|r| r.strip_prefix("refs/pull/")?.split('/').next().map(str::to_string));
+        let url = match (var("GITHUB_SERVER_URL"), var("GITHUB_REPOSITORY"), var("GITHUB_RUN_ID")) {
+            (Some(server), Some(repo), Some(run)) => Some(format!("{}/{}/actions/runs/{}", server, repo, run)),
+            _ => None,
+        };
+        return Some(Build {
+            commit: var("GITHUB_SHA"),
+            branch: var("GITHUB_HEAD_REF").or_else(|| var("GITHUB_REF_NAME")),
+            pull_request,
+            url,
+            ..build("github", var("GITHUB_REPOSITORY"))
+        });
+    }
+    if var("GITLAB_CI").is_some() {
+        return Some(Build {
+            commit: var("CI_COMMIT_SHA"),
+            branch: var("CI_MERGE_REQUEST_SOURCE_BRANCH_NAME").or_else(|| var("CI_COMMIT_REF_NAME")),
+            pull_request: var("CI_MERGE_REQUEST_IID"),
+            url: var("CI_PIPELINE_URL"),
+            ..build("gitlab", var("CI_PROJECT_PATH"))
+        });
+    }
+    if var("CIRCLECI").is_some() {
+        let repo = var("CIRCLE_PROJECT_USERNAME").zip(var("CIRCLE_PROJECT_REPONAME")).map(|(owner, name)| format!("{}/{}", owner, name));
+        return Some(Build {
+            commit: var("CIRCLE_SHA1"),
+            branch: var("CIRCLE_BRANCH"),
+            pull_request: var("CIRCLE_PULL_REQUEST").and_then(|url| url.rsplit('/').next().map(str::to_string)),
+            url: var("CIRCLE_BUILD_URL"),
+            ..build("circleci", repo)
+        });
+    }
+    if var("BITBUCKET_BUILD_NUMBER").is_some() {
+        return Some(Build {
+            commit: var("BITBUCKET_COMMIT"),
+            branch: var("BITBUCKET_BRANCH"),
+            pull_request: var("BITBUCKET_PR_ID"),
+            url: var("BITBUCKET_GIT_HTTP_ORIGIN")
+                .zip(var("BITBUCKET_BUILD_NUMBER"))
+                .map(|(origin, number)| format!("{}/addon/pipelines/home#!/results/{}", origin, number)),
+            ..build("bitbucket", var("BITBUCKET_REPO_FULL_NAME"))
+        });
+    }
+    if var("BUILDKITE").is_some() {
+        return Some(Build {
+            commit: var("BUILDKITE_COMMIT"),
+            branch: var("BUILDKITE_BRANCH"),
+            pull_request: var("BUILDKITE_PULL_REQUEST").filter(|pr| pr != "false"),
+            url: var("BUILDKITE_BUILD_URL"),
+            ..build("buildkite", var("BUILDKITE_REPO"))
+        });
+    }
+    None
+}
+
+impl Build {
+    /// One line for messages, e.g. `github acme/site @ 1a2b3c4 (PR #12)`
+    pub fn describe(&self) -> String {
+        let mut text = self.provider.clone();
+        if let Some(repo) = &self.repo {
+            text.push_str(&format!(" {}", repo));
+        }
+        if let Some(commit) = &self.commit {
+            text.push_str(&format!(" @ {}", commit.chars().take(7).collect::<String>()));
+        }
+        match (&self.pull_request, &self.branch) {
+            (Some(pr), _) => text.push_str(&format!(" (PR #{})", pr)),
+            (None, Some(branch)) => text.push_str(&format!(" ({})", branch)),
+            (None, None) => {}
+        }
+        text
+    }
+}
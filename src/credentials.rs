@@ -0,0 +1,297 @@
+//! Where a profile's token lives. Plaintext tokens sit in the config file
+//! itself (or, as an `op://` reference, in 1Password); the other backends
+//! keep only a discriminator in the config and fetch the secret on demand
+//! through a `CredentialStore`.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+const KEYCHAIN_SERVICE: &str = "cf-switch";
+const OP_REFERENCE_PREFIX: &str = "op://";
+
+/// Secrets already read from 1Password by this process, by reference
+static OP_CACHE: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Token stored in ~/.cf-switch.json
+    #[default]
+    Plaintext,
+    /// Token read from CF_SWITCH_TOKEN_<PROFILE> at use time
+    Env,
+    /// Token kept in the OS keychain (macOS `security`, Linux `secret-tool`)
+    Keychain,
+}
+
+impl Backend {
+    pub fn is_plaintext(&self) -> bool {
+        *self == Backend::Plaintext
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backend::Plaintext => "plaintext",
+            Backend::Env => "env",
+            Backend::Keychain => "keychain",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound(String),
+    ReadOnly(&'static str),
+    Tool { tool: &'static str, message: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(what) => write!(f, "{} not found", what),
+            Error::ReadOnly(backend) => write!(f, "the {} backend is read-only", backend),
+            Error::Tool { tool, message } => write!(f, "{}: {}", tool, message),
+        }
+    }
+}
+
+pub trait CredentialStore {
+    fn get(&self, name: &str) -> Result<String, Error>;
+    fn set(&self, name: &str, secret: &str) -> Result<(), Error>;
+    fn delete(&self, name: &str) -> Result<(), Error>;
+}
+
+/// The store for an external backend, or `None` for plaintext tokens that
+/// live inline in the profile
+pub fn store(backend: Backend) -> Option<Box<dyn CredentialStore>> {
+    match backend {
+        Backend::Plaintext => None,
+        Backend::Env => Some(Box::new(EnvStore)),
+        Backend::Keychain => Some(Box::new(KeychainStore)),
+    }
+}
+
+/// Reads `CF_SWITCH_TOKEN_<NAME>`, with the profile name upper-cased and
+/// anything that isn't alphanumeric turned into `_`
+pub struct EnvStore;
+
+impl EnvStore {
+    pub fn var_name(name: &str) -> String {
+        let suffix: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        format!("CF_SWITCH_TOKEN_{}", suffix)
+    }
+}
+
+impl CredentialStore for EnvStore {
+    fn get(&self, name: &str) -> Result<String, Error> {
+        let var = Self::var_name(name);
+        std::env::var(&var)
+            .ok()
+            .filter(|v| !v.is_empty())
+            .ok_or(Error::NotFound(format!("environment variable {}", var)))
+    }
+
+    fn set(&self, _name: &str, _secret: &str) -> Result<(), Error> {
+        Err(Error::ReadOnly("env"))
+    }
+
+    fn delete(&self, _name: &str) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+pub struct KeychainStore;
+
+impl KeychainStore {
+    fn tool() -> &'static str {
+        if cfg!(target_os = "macos") {
+            "security"
+        } else {
+            "secret-tool"
+        }
+    }
+
+    fn run(args: &[&str], stdin: Option<&str>) -> Result<String, Error> {
+        let tool = Self::tool();
+        let mut child = Command::new(tool)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::Tool { tool, message: e.to_string() })?;
+        let mut pipe = child.stdin.take().expect("stdin is piped");
+        if let Some(input) = stdin {
+            pipe.write_all(input.as_bytes())
+                .map_err(|e: io::Error| Error::Tool { tool, message: e.to_string() })?;
+        }
+        drop(pipe);
+        let output = child
+            .wait_with_output()
+            .map_err(|e| Error::Tool { tool, message: e.to_string() })?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).trim_end_matches('\n').to_string())
+        } else {
+            Err(Error::Tool {
+                tool,
+                message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            })
+        }
+    }
+}
+
+impl CredentialStore for KeychainStore {
+    fn get(&self, name: &str) -> Result<String, Error> {
+        let secret = if cfg!(target_os = "macos") {
+            Self::run(&["find-generic-password", "-s", KEYCHAIN_SERVICE, "-a", name, "-w"], None)
+        } else {
+            Self::run(&["lookup", "service", KEYCHAIN_SERVICE, "account", name], None)
+        };
+        match secret {
+            Ok(s) if !s.is_empty() => Ok(s),
+            _ => Err(Error::NotFound(format!("keychain entry for '{}'", name))),
+        }
+    }
+
+    fn set(&self, name: &str, secret: &str) -> Result<(), Error> {
+        if cfg!(target_os = "macos") {
+            // `security` only takes the password as an argument
+            Self::run(&["add-generic-password", "-U", "-s", KEYCHAIN_SERVICE, "-a", name, "-w", secret], None)?;
+        } else {
+            let label = format!("cf-switch token for {}", name);
+            Self::run(&["store", "--label", &label, "service", KEYCHAIN_SERVICE, "account", name], Some(secret))?;
+        }
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> Result<(), Error> {
+        if cfg!(target_os = "macos") {
+            Self::run(&["delete-generic-password", "-s", KEYCHAIN_SERVICE, "-a", name], None)?;
+        } else {
+            Self::run(&["clear", "service", KEYCHAIN_SERVICE, "account", name], None)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a plaintext token is a 1Password secret reference
+/// (`op://vault/item/field`) to resolve when the profile is used
+pub fn is_op_reference(token: &str) -> bool {
+    token.starts_with(OP_REFERENCE_PREFIX)
+}
+
+/// Read a 1Password secret reference with `op read`. The value is kept in
+/// memory for the rest of the process and never written anywhere.
+pub fn read_op_reference(reference: &str) -> Result<String, Error> {
+    let mut cache = OP_CACHE.lock().expect("op cache lock");
+    if let Some(secret) = cache.as_ref().and_then(|c| c.get(reference)) {
+        return Ok(secret.clone());
+    }
+    let tool = "op";
+    let output = Command::new(tool)
+        .args(["read", "--no-newline", reference])
+        // op may need to ask for a sign-in or biometric unlock
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|e| Error::Tool {
+            tool,
+            message: if e.kind() == io::ErrorKind::NotFound {
+                "the 1Password CLI isn't installed or isn't on PATH".to_string()
+            } else {
+                e.to_string()
+            },
+        })?;
+    if !output.status.success() {
+        return Err(Error::Tool { tool, message: String::from_utf8_lossy(&output.stderr).trim().to_string() });
+    }
+    let secret = String::from_utf8_lossy(&output.stdout).trim_end_matches(['\r', '\n']).to_string();
+    if secret.is_empty() {
+        return Err(Error::NotFound(format!("a value at {}", reference)));
+    }
+    cache.get_or_insert_with(HashMap::new).insert(reference.to_string(), secret.clone());
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// A backend kept in memory, standing in for the keychain
+    #[derive(Default)]
+    struct MemoryStore(RefCell<HashMap<String, String>>);
+
+    impl CredentialStore for MemoryStore {
+        fn get(&self, name: &str) -> Result<String, Error> {
+            self.0.borrow().get(name).cloned().ok_or_else(|| Error::NotFound(format!("entry for '{}'", name)))
+        }
+
+        fn set(&self, name: &str, secret: &str) -> Result<(), Error> {
+            self.0.borrow_mut().insert(name.to_string(), secret.to_string());
+            Ok(())
+        }
+
+        fn delete(&self, name: &str) -> Result<(), Error> {
+            self.0.borrow_mut().remove(name).map(|_| ()).ok_or_else(|| Error::NotFound(format!("entry for '{}'", name)))
+        }
+    }
+
+    #[test]
+    fn store_round_trips_through_the_trait() {
+        let store: Box<dyn CredentialStore> = Box::new(MemoryStore::default());
+        assert!(matches!(store.get("prod"), Err(Error::NotFound(_))));
+        store.set("prod", "secret-1").unwrap();
+        assert_eq!(store.get("prod").unwrap(), "secret-1");
+        store.set("prod", "secret-2").unwrap();
+        assert_eq!(store.get("prod").unwrap(), "secret-2");
+        store.set("staging", "secret-3").unwrap();
+        store.delete("prod").unwrap();
+        assert!(matches!(store.get("prod"), Err(Error::NotFound(_))));
+        assert_eq!(store.get("staging").unwrap(), "secret-3");
+        assert!(matches!(store.delete("prod"), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn env_store_reads_its_variable() {
+        // A name no other test uses, since the environment is shared between threads
+        let name = "creds-test.env";
+        assert_eq!(EnvStore::var_name(name), "CF_SWITCH_TOKEN_CREDS_TEST_ENV");
+        assert!(matches!(EnvStore.get(name), Err(Error::NotFound(what)) if what.contains("CF_SWITCH_TOKEN_CREDS_TEST_ENV")));
+        std::env::set_var("CF_SWITCH_TOKEN_CREDS_TEST_ENV", "");
+        assert!(matches!(EnvStore.get(name), Err(Error::NotFound(_))));
+        std::env::set_var("CF_SWITCH_TOKEN_CREDS_TEST_ENV", "from-env");
+        assert_eq!(EnvStore.get(name).unwrap(), "from-env");
+        std::env::remove_var("CF_SWITCH_TOKEN_CREDS_TEST_ENV");
+    }
+
+    #[test]
+    fn env_store_is_read_only() {
+        assert!(matches!(EnvStore.set("prod", "secret"), Err(Error::ReadOnly("env"))));
+        // Nothing to delete: the variable belongs to the environment
+        assert!(EnvStore.delete("prod").is_ok());
+    }
+
+    #[test]
+    fn plaintext_has_no_store() {
+        assert!(store(Backend::Plaintext).is_none());
+        assert!(store(Backend::Env).is_some());
+        assert!(store(Backend::Keychain).is_some());
+    }
+
+    #[test]
+    fn recognizes_1password_references() {
+        assert!(is_op_reference("op://Work/Cloudflare/token"));
+        assert!(!is_op_reference("abc123"));
+        assert!(!is_op_reference("OP://x/y/z"));
+    }
+}
@@ -0,0 +1,210 @@
+//! Sort flarectl and API failures into a few categories a user can act on.
+//! Matching is on Cloudflare error codes where flarectl quotes the response
+//! body, and on error text otherwise.
+
+use crate::{api, http};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ErrorKind {
+    AuthError,
+    ZoneNotFound,
+    RateLimited,
+    NetworkError,
+    FlarectlMissing,
+}
+
+const AUTH_SIGNATURES: &[&str] = &[
+    "http status 401",
+    "http status 403",
+    "invalid request headers",
+    "authentication error",
+    "invalid api token",
+    "unauthorized",
+];
+const ZONE_SIGNATURES: &[&str] = &["zone could not be found", "zone not found", "could not route to", "invalid zone identifier"];
+const RATE_LIMIT_SIGNATURES: &[&str] = &["http status 429", "rate limit", "too many requests"];
+const NETWORK_SIGNATURES: &[&str] = &[
+    "no such host",
+    "connection refused",
+    "i/o timeout",
+    "dial tcp",
+    "tls handshake",
+    "network is unreachable",
+    "context deadline exceeded",
+    "proxyconnect",
+    "through proxy",
+];
+
+/// Cloudflare error codes meaning the credentials were refused
+const AUTH_CODES: &[u64] = &[6003, 9103, 9109, 10000];
+/// Cloudflare error codes meaning the zone doesn't exist for this account
+const ZONE_CODES: &[u64] = &[1001, 7003];
+
+/// Cloudflare API error codes quoted in flarectl output. flarectl includes the
+/// response body (`..."code":81057,...`) in its errors, and unlike the message
+/// text the codes don't depend on wording or locale.
+pub fn cloudflare_error_codes(output: &str) -> Vec<u64> {
+    let pattern = regex::Regex::new(r#""code"\s*:\s*(\d+)"#).expect("valid error code regex");
+    pattern
+        .captures_iter(output)
+        .filter_map(|c| c[1].parse().ok())
+        .collect()
+}
+
+impl ErrorKind {
+    /// Categorize flarectl's stderr/stdout, by error code where it has one
+    pub fn classify(output: &str) -> Option<ErrorKind> {
+        let codes = cloudflare_error_codes(output);
+        if codes.iter().any(|c| AUTH_CODES.contains(c)) {
+            return Some(ErrorKind::AuthError);
+        }
+        if codes.iter().any(|c| ZONE_CODES.contains(c)) {
+            return Some(ErrorKind::ZoneNotFound);
+        }
+        let output = output.to_lowercase();
+        let matches = |signatures: &[&str]| signatures.iter().any(|s| output.contains(s));
+        if matches(AUTH_SIGNATURES) {
+            Some(ErrorKind::AuthError)
+        } else if matches(ZONE_SIGNATURES) {
+            Some(ErrorKind::ZoneNotFound)
+        } else if matches(RATE_LIMIT_SIGNATURES) {
+            Some(ErrorKind::RateLimited)
+        } else if matches(NETWORK_SIGNATURES) {
+            Some(ErrorKind::NetworkError)
+        } else if output.contains("executable file not found") {
+            Some(ErrorKind::FlarectlMissing)
+        } else {
+            None
+        }
+    }
+
+    /// Categorize an error from a direct API call
+    pub fn from_api(error: &api::Error) -> Option<ErrorKind> {
+        match error {
+            api::Error::Http(http::Error::Transfer { .. }) => Some(ErrorKind::NetworkError),
+            api::Error::Http(http::Error::Spawn(_)) => None,
+            api::Error::Api { status: 401 | 403, .. } => Some(ErrorKind::AuthError),
+            api::Error::Api { status: 429, .. } => Some(ErrorKind::RateLimited),
+            api::Error::Api { messages, .. } => ErrorKind::classify(&messages.join("\n")),
+            api::Error::InvalidResponse(_) => None,
+        }
+    }
+
+    /// One line naming the likely cause
+    pub fn summary(self) -> &'static str {
+        match self {
+            ErrorKind::AuthError => "Cloudflare rejected the credentials; the token looks invalid or expired.",
+            ErrorKind::ZoneNotFound => "The zone wasn't found for these credentials.",
+            ErrorKind::RateLimited => "Cloudflare is rate limiting these requests.",
+            ErrorKind::NetworkError => "Couldn't reach the Cloudflare API.",
+            ErrorKind::FlarectlMissing => "flarectl isn't installed or isn't on PATH.",
+        }
+    }
+
+    /// What to do about it, for `--explain`
+    pub fn explanation(self) -> &'static str {
+        match self {
+            ErrorKind::AuthError => {
+                "The token was refused by Cloudflare. Tokens can expire, be revoked or be rolled \
+                 from the dashboard, and API keys stop working if the account email doesn't match. \
+                 Check the token with 'cf-switch use <name> --verify'; if it's no longer active, \
+                 create a new one at https://dash.cloudflare.com/profile/api-tokens and re-add \
+                 the profile with it."
+            }
+            ErrorKind::ZoneNotFound => {
+                "Cloudflare has no zone by that name that these credentials can see. Check the \
+                 spelling (use the apex domain, e.g. example.com rather than www.example.com), \
+                 that you're on the right profile, and that the token's Zone Resources include \
+                 this zone."
+            }
+            ErrorKind::RateLimited => {
+                "The Cloudflare API allows a limited number of requests per five minutes per \
+                 user, and cache purges have their own lower limits. Wait a minute and try again; \
+                 if scripts run many commands back to back, space them out."
+            }
+            ErrorKind::NetworkError => {
+                "The request never got an answer from api.cloudflare.com. Check your internet \
+                 connection, VPN, the http_proxy and ca_cert settings in the config (behind a \
+                 corporate proxy), and https://www.cloudflarestatus.com for ongoing incidents."
+            }
+            ErrorKind::FlarectlMissing => {
+                "cf-switch drives Cloudflare's flarectl for purges and DNS changes. Install it \
+                 with 'brew install cloudflare/cloudflare/flarectl' or \
+                 'go install github.com/cloudflare/cloudflare-go/cmd/flarectl@latest', and make \
+                 sure it's on your PATH."
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn classifies_by_cloudflare_error_code() {
+        let auth = r#"error from makeRequest: HTTP status 400: content "{"success":false,"errors":[{"code":9109,"message":"Invalid access token"}]}""#;
+        assert_eq!(ErrorKind::classify(auth), Some(ErrorKind::AuthError));
+        let zone = r#"{"success":false,"errors":[{"code": 7003,"message":"Could not route to /zones/abc, perhaps your object identifier is invalid?"}]}"#;
+        assert_eq!(ErrorKind::classify(zone), Some(ErrorKind::ZoneNotFound));
+    }
+
+    #[test]
+    fn codes_win_over_text() {
+        // The message mentions a rate limit, but code 10000 is an auth failure
+        let output = r#"{"errors":[{"code":10000,"message":"Authentication error, rate limit info unavailable"}]}"#;
+        assert_eq!(ErrorKind::classify(output), Some(ErrorKind::AuthError));
+    }
+
+    #[test]
+    fn classifies_by_error_text() {
+        let cases = [
+            ("error from makeRequest: HTTP status 403: Forbidden", ErrorKind::AuthError),
+            ("Invalid request headers (6003)", ErrorKind::AuthError),
+            ("Zone could not be found", ErrorKind::ZoneNotFound),
+            ("HTTP status 429: Too Many Requests", ErrorKind::RateLimited),
+            ("You have exceeded the rate limit", ErrorKind::RateLimited),
+            ("dial tcp: lookup api.cloudflare.com: no such host", ErrorKind::NetworkError),
+            ("Get \"https://api.cloudflare.com/client/v4/zones\": context deadline exceeded", ErrorKind::NetworkError),
+            ("proxyconnect tcp: dial tcp 10.0.0.1:3128: connection refused", ErrorKind::NetworkError),
+            ("exec: \"flarectl\": executable file not found in $PATH", ErrorKind::FlarectlMissing),
+        ];
+        for (output, kind) in cases {
+            assert_eq!(ErrorKind::classify(output), Some(kind), "{}", output);
+        }
+    }
+
+    #[test]
+    fn unrecognized_output_is_unclassified() {
+        assert_eq!(ErrorKind::classify(""), None);
+        assert_eq!(ErrorKind::classify("record already exists"), None);
+        assert_eq!(ErrorKind::classify(r#"{"errors":[{"code":81057,"message":"Record already exists."}]}"#), None);
+    }
+
+    #[test]
+    fn extracts_every_error_code() {
+        let output = r#"{"errors":[{"code":1001,"message":"a"},{"code" : 9103,"message":"b"}]}"#;
+        assert_eq!(cloudflare_error_codes(output), [1001, 9103]);
+    }
+
+    #[test]
+    fn classifies_api_errors() {
+        let api_error = |status: u16, messages: &[&str]| api::Error::Api {
+            status,
+            messages: messages.iter().map(|m| m.to_string()).collect(),
+            retry_after: None,
+        };
+        assert_eq!(ErrorKind::from_api(&api_error(401, &[])), Some(ErrorKind::AuthError));
+        assert_eq!(ErrorKind::from_api(&api_error(403, &["whatever"])), Some(ErrorKind::AuthError));
+        assert_eq!(
+            ErrorKind::from_api(&api::Error::Api { status: 429, messages: Vec::new(), retry_after: Some(Duration::from_secs(5)) }),
+            Some(ErrorKind::RateLimited)
+        );
+        assert_eq!(ErrorKind::from_api(&api_error(400, &["Invalid zone identifier"])), Some(ErrorKind::ZoneNotFound));
+        assert_eq!(ErrorKind::from_api(&api_error(500, &["Internal error"])), None);
+        let transfer = http::Error::Transfer { code: 6, message: "Could not resolve host: api.cloudflare.com".to_string() };
+        assert_eq!(ErrorKind::from_api(&api::Error::Http(transfer)), Some(ErrorKind::NetworkError));
+        assert_eq!(ErrorKind::from_api(&api::Error::InvalidResponse("not json".to_string())), None);
+    }
+}
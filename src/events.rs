@@ -0,0 +1,21 @@
+//! `--output json` for the commands that work through several steps (purge,
+//! including --watch, and dns-from-file): each step as one JSON object per
+//! line on stdout as it happens, for a UI or CI job to follow, while the
+//! usual messages still go to stderr. Whether to emit is `Ctx::events`.
+
+use serde_json::{json, Value};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Print `{"event": event, "time": ..., ...fields}`. Each line is written and
+/// flushed whole, so threads don't interleave.
+pub fn emit(event: &str, fields: Value) {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let mut line = json!({ "event": event, "time": time });
+    if let (Some(line), Value::Object(fields)) = (line.as_object_mut(), fields) {
+        line.extend(fields);
+    }
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", line).ok();
+    stdout.flush().ok();
+}
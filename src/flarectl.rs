@@ -0,0 +1,86 @@
+//! Which flarectl is installed, so a command that needs something newer can
+//! say so up front instead of failing on an unknown flag. The version is
+//! asked for once per run, the first time a gated feature is about to be
+//! used; a build that doesn't report one (e.g. `dev`) is assumed current.
+
+use std::fmt;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Version { major, minor, patch }
+    }
+
+    /// The first `X.Y` or `X.Y.Z` (optionally `v`-prefixed, pre-release
+    /// suffix ignored) in `text`, e.g. `flarectl version v0.86.0`
+    pub fn parse(text: &str) -> Option<Version> {
+        text.split_whitespace().find_map(|word| {
+            let word = word.trim_start_matches('v');
+            let core = word.split(['-', '+']).next()?;
+            let mut parts = core.split('.').map(|p| p.parse::<u32>().ok());
+            let major = parts.next()??;
+            let minor = parts.next()??;
+            let patch = parts.next().unwrap_or(Some(0))?;
+            Some(Version::new(major, minor, patch))
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// What cf-switch asks of flarectl that older releases don't have
+#[derive(Clone, Copy)]
+pub enum Feature {
+    /// Scoped API tokens through CF_API_TOKEN (older releases only read a global key)
+    TokenAuth,
+    /// `dns create --proxy`
+    ProxiedRecords,
+}
+
+impl Feature {
+    /// The oldest release cf-switch knows to have it
+    pub fn since(self) -> Version {
+        match self {
+            Feature::TokenAuth => Version::new(0, 11, 0),
+            Feature::ProxiedRecords => Version::new(0, 8, 0),
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Feature::TokenAuth => "API token auth (CF_API_TOKEN)",
+            Feature::ProxiedRecords => "proxied DNS records (dns create --proxy)",
+        }
+    }
+}
+
+static INSTALLED: OnceLock<Option<Version>> = OnceLock::new();
+
+/// The installed flarectl's version, if it runs and reports one
+pub fn version() -> Option<Version> {
+    *INSTALLED.get_or_init(|| {
+        let output = Command::new("flarectl").arg("--version").stdin(Stdio::null()).output().ok()?;
+        let text = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+        Version::parse(&text)
+    })
+}
+
+/// Ok, or the installed version that's too old for `feature`
+pub fn check(feature: Feature) -> Result<(), Version> {
+    match version() {
+        Some(installed) if installed < feature.since() => Err(installed),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,298 @@
+//! Minimal HTTP client built on the `curl` binary, mirroring how flarectl is
+//! driven as a subprocess. Request options (including auth headers) are fed
+//! to curl on stdin via `--config -`, so secrets never appear in argv.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Connection settings from the config, applied to every request. A proxy, CA
+/// bundle or pin belongs to the network this machine is on rather than to
+/// any one call, so they're set when the config loads (`configure`) instead
+/// of being handed to each `Request`.
+#[derive(Clone, Default)]
+pub struct Settings {
+    /// Proxy URL, e.g. http://proxy.corp:3128
+    pub proxy: Option<String>,
+    /// PEM bundle to trust instead of the system CAs
+    pub ca_cert: Option<String>,
+    /// Public keys the API's certificate must have, in curl's
+    /// `sha256//<base64>;...` form (see `parse_pins`)
+    pub pin: Option<String>,
+}
+
+static SETTINGS: Mutex<Settings> = Mutex::new(Settings { proxy: None, ca_cert: None, pin: None });
+
+/// Longest any request (or flarectl run) may take, unless it asks for less
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+static TIMEOUT: Mutex<Duration> = Mutex::new(Duration::from_secs(DEFAULT_TIMEOUT_SECS));
+
+pub fn configure_timeout(timeout: Duration) {
+    *TIMEOUT.lock().expect("http timeout lock") = timeout;
+}
+
+pub fn timeout() -> Duration {
+    *TIMEOUT.lock().expect("http timeout lock")
+}
+
+pub fn configure(settings: Settings) {
+    *SETTINGS.lock().expect("http settings lock") = settings;
+}
+
+pub fn settings() -> Settings {
+    SETTINGS.lock().expect("http settings lock").clone()
+}
+
+/// curl's exit code for a certificate whose public key isn't pinned
+const PIN_MISMATCH_CODE: i32 = 90;
+
+/// Pins as given on the command line or in the config: base64 SHA-256
+/// hashes of the certificate's public key, each optionally `sha256//`
+/// prefixed, separated by `;` or `,` so a rotation can pin old and new.
+/// Returns them in the form curl takes.
+pub fn parse_pins(value: &str) -> Result<String, String> {
+    let pins: Vec<&str> = value.split([';', ',']).map(str::trim).filter(|p| !p.is_empty()).collect();
+    if pins.is_empty() {
+        return Err("no pin given".to_string());
+    }
+    let mut parsed = Vec::new();
+    for pin in pins {
+        let hash = pin.strip_prefix("sha256//").unwrap_or(pin);
+        let base64 = |c: char| c.is_ascii_alphanumeric() || c == '+' || c == '/';
+        // 32 bytes of base64: 43 characters and one `=` of padding
+        if hash.len() != 44 || !hash.ends_with('=') || !hash[..43].chars().all(base64) {
+            return Err(format!("'{}' isn't a base64 SHA-256 hash (44 characters ending in '=')", pin));
+        }
+        parsed.push(format!("sha256//{}", hash));
+    }
+    Ok(parsed.join(";"))
+}
+
+/// curl exit codes for a proxy that can't be resolved, reached or tunneled through
+const PROXY_FAILURE_CODES: &[i32] = &[5, 7, 56, 97];
+
+pub struct Request {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+    timeout: Option<Duration>,
+}
+
+pub struct Response {
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// curl itself couldn't be started
+    Spawn(io::Error),
+    /// curl ran but the transfer failed (DNS, connect, TLS, timeout...)
+    Transfer { code: i32, message: String },
+}
+
+/// The step of a connection a transfer failed at, from curl's exit code
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stage {
+    Dns,
+    Connect,
+    Tls,
+    /// No answer in time; could be any step
+    Timeout,
+    Other,
+}
+
+impl Error {
+    pub fn stage(&self) -> Stage {
+        match self {
+            Error::Spawn(_) => Stage::Other,
+            Error::Transfer { code, .. } => match code {
+                5 | 6 => Stage::Dns,
+                7 | 56 | 97 => Stage::Connect,
+                35 | 53 | 54 | 58 | 59 | 60 | 64 | 66 | 77 | 80 | 82 | 83 | 90 | 91 => Stage::Tls,
+                28 => Stage::Timeout,
+                _ => Stage::Other,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Spawn(e) => write!(f, "failed to run curl: {}", e),
+            Error::Transfer { code, message } => write!(f, "{} (curl exit {})", message, code),
+        }
+    }
+}
+
+impl Request {
+    pub fn new(method: &str, url: &str) -> Self {
+        Request {
+            method: method.to_uppercase(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: None,
+            timeout: None,
+        }
+    }
+
+    pub fn get(url: &str) -> Self {
+        Request::new("GET", url)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: String) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    /// Give up sooner than the global timeout
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Render this request as a curl config file
+    fn curl_config(&self) -> String {
+        let mut config = String::new();
+        let mut line = |key: &str, value: &str| {
+            config.push_str(&format!("{} = \"{}\"\n", key, quote(value)));
+        };
+        line("url", &self.url);
+        line("request", &self.method);
+        for (name, value) in &self.headers {
+            line("header", &format!("{}: {}", name, value));
+        }
+        if let Some(body) = &self.body {
+            line("data-binary", body);
+        }
+        let max_time = self.timeout.map_or(timeout(), |own| own.min(timeout()));
+        line("max-time", &max_time.as_secs_f64().to_string());
+        let settings = settings();
+        if let Some(proxy) = &settings.proxy {
+            line("proxy", proxy);
+        }
+        if let Some(ca_cert) = &settings.ca_cert {
+            line("cacert", ca_cert);
+        }
+        if let Some(pin) = &settings.pin {
+            line("pinnedpubkey", pin);
+        }
+        config
+    }
+
+    pub fn send(&self) -> Result<Response, Error> {
+        let mut child = Command::new("curl")
+            .args(["--silent", "--show-error", "--include", "--config", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(Error::Spawn)?;
+        child
+            .stdin
+            .take()
+            .expect("curl stdin is piped")
+            .write_all(self.curl_config().as_bytes())
+            .map_err(Error::Spawn)?;
+        let output = child.wait_with_output().map_err(Error::Spawn)?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let code = output.status.code().unwrap_or(-1);
+            let mut message = curl_error_message(&stderr);
+            if code == PIN_MISMATCH_CODE {
+                message = "the server's certificate doesn't match the pinned public key; not connecting".to_string();
+            }
+            if let Some(proxy) = settings().proxy.filter(|_| PROXY_FAILURE_CODES.contains(&code)) {
+                message = format!("{} (through proxy {})", message, without_userinfo(&proxy));
+            }
+            return Err(Error::Transfer { code, message });
+        }
+        Ok(parse_response(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+impl Response {
+    /// Case-insensitive header lookup
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// A bodiless response, for standing in for curl in tests
+    #[cfg(test)]
+    pub fn with_status(status: u16) -> Response {
+        Response { status, headers: Vec::new(), body: String::new() }
+    }
+}
+
+/// Strip curl's `curl: (6) ` prefix from its error output
+fn curl_error_message(stderr: &str) -> String {
+    let message = stderr.trim().trim_start_matches("curl: ");
+    match message.strip_prefix('(').and_then(|m| m.split_once(") ")) {
+        Some((code, rest)) if code.chars().all(|c| c.is_ascii_digit()) => rest.to_string(),
+        _ => message.to_string(),
+    }
+}
+
+/// A proxy URL with any `user:password@` removed, for messages
+pub fn without_userinfo(url: &str) -> String {
+    match url.split_once("://") {
+        Some((scheme, rest)) => format!("{}://{}", scheme, rest.rsplit_once('@').map_or(rest, |(_, host)| host)),
+        None => url.rsplit_once('@').map_or(url, |(_, host)| host).to_string(),
+    }
+}
+
+/// Escape a value for a double-quoted curl config string
+fn quote(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t")
+}
+
+/// Split `curl --include` output into status, headers and body. Interim
+/// responses (100 Continue, proxy CONNECT) come first, so keep the last block.
+fn parse_response(raw: &str) -> Response {
+    let mut rest = raw;
+    let mut head = "";
+    while rest.starts_with("HTTP/") {
+        let (block, after) = match rest.find("\r\n\r\n") {
+            Some(i) => (&rest[..i], &rest[i + 4..]),
+            None => (rest, ""),
+        };
+        head = block;
+        rest = after;
+    }
+
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(0);
+    let headers = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(n, v)| (n.trim().to_string(), v.trim().to_string()))
+        .collect();
+    Response {
+        status,
+        headers,
+        body: rest.to_string(),
+    }
+}
@@ -1,248 +1,5745 @@
-use clap::{Parser, Subcommand};
-use colored::Colorize;
+mod api;
+mod audit;
+mod ci;
+mod credentials;
+mod diagnose;
+mod events;
+mod flarectl;
+mod http;
+mod mask;
+mod outcome;
+mod pager;
+mod profile;
+mod select;
+mod sitemap;
+mod theme;
+#[cfg(feature = "tui")]
+mod tui;
+mod vault;
+
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use credentials::{Backend, CredentialStore};
+use diagnose::ErrorKind;
+use notify::{RecursiveMode, Watcher};
+use profile::ProfileBuilder;
+use colored::{ColoredString, Colorize};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::io::{self, Write};
-use std::path::PathBuf;
-use std::process::Command;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
 
 #[derive(Parser)]
 #[command(name = "cf-switch")]
 #[command(about = "Cloudflare profile switcher for flarectl", long_about = None)]
 struct Cli {
+    /// Treat warnings (unknown config fields, failed token checks) as errors
+    #[arg(long, global = true)]
+    strict: bool,
+    /// Use this config file instead of ~/.cf-switch.json
+    #[arg(long = "config", global = true, env = "CF_SWITCH_CONFIG", value_name = "PATH")]
+    config_file: Option<PathBuf>,
+    /// Use this profile's credentials for one command, without switching to it
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+    /// Print the flarectl commands purge, add-lamdera-app or dns-from-file would run, for a placeholder profile, without running them (no config needed)
+    #[arg(long, global = true, conflicts_with = "profile")]
+    profile_stub: bool,
+    /// After a failure, explain the likely cause and how to fix it
+    #[arg(long, global = true)]
+    explain: bool,
+    /// Replace any known token with *** in messages and subprocess output
+    #[arg(long, global = true)]
+    mask_in_logs: bool,
+    /// Print tokens in full wherever one is shown (overrides the config's redaction)
+    #[arg(long, global = true, conflicts_with = "redact")]
+    reveal: bool,
+    /// Hide tokens entirely wherever one is shown, last four characters included
+    #[arg(long, global = true)]
+    redact: bool,
+    /// Don't touch the config or env file; print what would be written instead
+    #[arg(long, global = true)]
+    no_write: bool,
+    /// Ignore any project .cf-switch.json and use only the global config
+    #[arg(long, global = true)]
+    global: bool,
+    /// When to color output: auto (when stderr is a terminal, unless NO_COLOR is set), always or never
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+    /// Same as --color never
+    #[arg(long, global = true, conflicts_with = "color")]
+    no_color: bool,
+    /// Mark messages with ASCII ([ok], ->, [on]) instead of Unicode symbols
+    #[arg(long, global = true, env = "CF_SWITCH_ASCII", value_parser = clap::builder::FalseyValueParser::new())]
+    ascii: bool,
+    /// Indent JSON output for reading (it's one line per value by default)
+    #[arg(long, global = true, conflicts_with = "compact")]
+    pretty: bool,
+    /// Print JSON on one line, including `config show`, which is indented otherwise
+    #[arg(long, global = true)]
+    compact: bool,
+    /// How often Cloudflare API calls retry network errors, 5xx and 429 responses
+    #[arg(long, global = true, value_name = "N", default_value_t = api::DEFAULT_RETRIES)]
+    retries: u32,
+    /// Milliseconds to wait before the first retry; doubled for each one after
+    #[arg(long, global = true, value_name = "MS", default_value_t = api::DEFAULT_BACKOFF_MS)]
+    backoff: u64,
+    /// Seconds any Cloudflare API request or flarectl run may take before giving up
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = http::DEFAULT_TIMEOUT_SECS, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: u64,
+    /// Only talk to the Cloudflare API if its certificate's public key has this base64 SHA-256 hash (over the config's `tls_pin`)
+    #[arg(long, global = true, value_name = "SHA256")]
+    pin: Option<String>,
+    /// When toggling, check the next profile's token with Cloudflare first
+    #[arg(long)]
+    verify: bool,
+    /// When toggling, write the env file here instead of ~/.cloudflare.env
+    #[arg(long, value_name = "PATH")]
+    env_out: Option<PathBuf>,
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+/// Per-invocation settings derived from global flags
+#[derive(Clone)]
+struct Ctx {
+    strict: bool,
+    explain: bool,
+    no_write: bool,
+    config_path: PathBuf,
+    /// Project config layered over `config_path`, if one was found
+    local_config_path: Option<PathBuf>,
+    /// `--profile`: credentials to use instead of the current profile's
+    profile: Option<String>,
+    /// `--profile-stub`: print flarectl commands instead of running them
+    profile_stub: bool,
+    /// `--pretty` (true) or `--compact` (false); unset leaves it to the command
+    pretty_json: Option<bool>,
+    /// `--reveal` or `--redact`, over the config's `redaction`
+    redaction: Option<mask::Redaction>,
+    /// Whether a switch prints its `source` line; off when stdout belongs to
+    /// a command run after it
+    print_source: bool,
+    /// Whether a switch counts toward the profile's `use_count`
+    count_use: bool,
+    /// `--pin`, over the config's `tls_pin`
+    pin: Option<String>,
+    /// The `--json` result this command reports, if it was asked for one
+    outcome: Option<outcome::Outcome>,
+    /// `--output json`: report each step as a JSON event on stdout
+    events: bool,
+    /// What status messages are marked with; ASCII under `--ascii`
+    symbols: &'static theme::Symbols,
+}
+
+impl Ctx {
+    /// JSON for stdout: compact unless --pretty, or indented by default for
+    /// output meant mainly for reading
+    fn json_string(&self, value: &serde_json::Value, pretty_by_default: bool) -> String {
+        if self.pretty_json.unwrap_or(pretty_by_default) {
+            serde_json::to_string_pretty(value).expect("Failed to serialize JSON")
+        } else {
+            value.to_string()
+        }
+    }
+
+    /// Emit an `--output json` event, if this command is emitting them
+    fn event(&self, event: &str, fields: serde_json::Value) {
+        if self.events {
+            events::emit(event, fields);
+        }
+    }
+
+    /// Print the `--json` success result, if this command reports one
+    fn succeed(&self, extra: serde_json::Value) {
+        if let Some(outcome) = &self.outcome {
+            outcome.succeed(self.pretty_json.unwrap_or(false), extra);
+        }
+    }
+
+    /// Print the `--json` failure result, if this command reports one (see
+    /// `Outcome::fail` for the codes), and exit 1
+    fn fail(&self, code: &str, message: &str) -> ! {
+        if let Some(outcome) = &self.outcome {
+            outcome.fail(self.pretty_json.unwrap_or(false), code, message);
+        }
+        std::process::exit(1);
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// List all profiles
-    List,
+    List {
+        /// Show zone and description for each profile
+        #[arg(short, long)]
+        verbose: bool,
+        /// Only show profiles whose name or email contains this text, or matches /regex/
+        #[arg(short, long)]
+        filter: Option<String>,
+        /// Print profiles under a header per group
+        #[arg(short, long, value_enum)]
+        group_by: Option<GroupBy>,
+        /// Verify every listed token with Cloudflare and show its status
+        #[arg(long)]
+        check: bool,
+        /// Print the profiles as a JSON list on stdout (tokens left out)
+        #[arg(long, conflicts_with_all = ["verbose", "group_by"])]
+        json: bool,
+        /// With --json, print only this part, e.g. '.[0].zone' (strings unquoted)
+        #[arg(long, value_name = "PATH", requires = "json")]
+        select: Option<String>,
+        /// Only show the active profile; exit 1 if none is active
+        #[arg(long)]
+        active_only: bool,
+        /// Warn about profiles holding the same token (a copy-paste slip, unless made with clone)
+        #[arg(long)]
+        detect_duplicates: bool,
+        /// Only show profiles whose email is at this domain (or a subdomain of it)
+        #[arg(long)]
+        domain: Option<String>,
+        /// Print name, email, zone and active ("-" when empty) tab-separated on stdout, one profile per line
+        #[arg(long, conflicts_with_all = ["verbose", "group_by", "json"])]
+        oneline: bool,
+        /// Show at most this many profiles (after --offset)
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+        /// Skip this many profiles, in the --sort order
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        offset: usize,
+        /// Order to list profiles in
+        #[arg(long, value_enum, default_value_t = ListSort::Name)]
+        sort: ListSort,
+        /// Print straight to the terminal even when the list is taller than it
+        #[arg(long)]
+        no_pager: bool,
+        /// Only show profiles never switched to (or, with --days, not in that long)
+        #[arg(long)]
+        unused: bool,
+        /// With --unused, also show profiles last switched to more than this many days ago
+        #[arg(long, value_name = "N", requires = "unused")]
+        days: Option<u64>,
+        /// Ask Cloudflare which zones each token can see, with their status and plan
+        #[arg(long, conflicts_with = "oneline")]
+        zones_resolved: bool,
+    },
     /// Add a new profile
     Add {
         /// Profile name
         name: String,
-        /// Cloudflare account email
+        /// Cloudflare account email; needed for a global API key (prompted for if omitted), optional for API tokens
         #[arg(short, long)]
-        email: String,
-        /// API Token (recommended) or API Key
+        email: Option<String>,
+        /// API Token (recommended) or API Key; prompted for (hidden) if omitted; not used with --backend env
         #[arg(short, long)]
-        token: String,
+        token: Option<String>,
+        /// Read the token from this environment variable instead of --token
+        #[arg(long, value_name = "VAR", conflicts_with = "token")]
+        token_env: Option<String>,
+        /// Take the credentials and zone from an env file's CF_API_* and CF_ZONE (default ~/.cloudflare.env); flags given too win
+        #[arg(long, value_name = "PATH", num_args = 0..=1, conflicts_with_all = ["token", "token_env"])]
+        from_env_file: Option<Option<PathBuf>>,
+        /// Where to keep the token
+        #[arg(long, value_enum, default_value_t = Backend::Plaintext)]
+        backend: Backend,
+        /// Whether --token is a scoped API token or the global API key (guessed from its shape if omitted)
+        #[arg(long, value_enum)]
+        auth: Option<AuthMode>,
         /// Default zone for this profile (e.g., example.com)
         #[arg(short, long)]
         zone: Option<String>,
+        /// Free-form note, e.g. what the token is scoped to
+        #[arg(long = "desc")]
+        description: Option<String>,
+        /// Cloudflare account ID this profile belongs to
+        #[arg(long)]
+        account_id: Option<String>,
+        /// Tag for grouping (repeatable)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Check the token with Cloudflare, and pick up its zone if it can only see one
+        #[arg(long)]
+        verify: bool,
+        /// Save to the project .cf-switch.json (created here if there isn't one), overriding a global profile of the same name
+        #[arg(long)]
+        local: bool,
+        /// Forget the profile (and its token) once --ttl has passed
+        #[arg(long, requires = "ttl")]
+        ephemeral: bool,
+        /// Lifetime of an --ephemeral profile, e.g. 30m, 8h or 2d
+        #[arg(long, value_name = "DURATION", requires = "ephemeral")]
+        ttl: Option<String>,
+        /// Print the outcome as a JSON object on stdout, with an error code on failure
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        mutation: MutationArgs,
+    },
+    /// Change fields of an existing profile and show what changed
+    Edit {
+        /// Profile to edit
+        name: String,
+        /// New account email
+        #[arg(short, long)]
+        email: Option<String>,
+        /// New API token or API key; not used with --backend env
+        #[arg(short, long)]
+        token: Option<String>,
+        /// Read the new token from this environment variable instead of --token
+        #[arg(long, value_name = "VAR", conflicts_with = "token")]
+        token_env: Option<String>,
+        /// Whether the token is a scoped API token or the global API key
+        #[arg(long, value_enum)]
+        auth: Option<AuthMode>,
+        /// New default zone ("" clears it)
+        #[arg(short, long)]
+        zone: Option<String>,
+        /// New note ("" clears it)
+        #[arg(long = "desc")]
+        description: Option<String>,
+        /// New account ID ("" clears it)
+        #[arg(long)]
+        account_id: Option<String>,
+        /// Replace the tags (repeatable; a single --tag "" clears them)
+        #[arg(long = "tag")]
+        tags: Option<Vec<String>>,
+        /// false makes purging everything on a zone need --force and the zone name typed in
+        #[arg(long, value_name = "BOOL")]
+        allow_purge_everything: Option<bool>,
+        /// Print the outcome and changes as a JSON object instead of a colored diff
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        mutation: MutationArgs,
+    },
+    /// Copy a profile's credentials into a new profile
+    Clone {
+        /// Profile to copy from
+        source: String,
+        /// Name for the new profile
+        name: String,
+        /// Default zone for the new profile (defaults to the source's)
+        #[arg(short, long)]
+        zone: Option<String>,
+        #[command(flatten)]
+        mutation: MutationArgs,
+    },
+    /// Give a profile another name to switch to it by
+    Alias {
+        /// Profile to add the alias to
+        profile: String,
+        /// The new name; can't be a profile name or another profile's alias
+        alias: String,
+        #[command(flatten)]
+        mutation: MutationArgs,
+    },
+    /// Remove a profile alias
+    Unalias {
+        alias: String,
+        #[command(flatten)]
+        mutation: MutationArgs,
     },
     /// Remove a profile
     Remove {
-        /// Profile name to remove
-        name: String,
+        /// Profile name to remove (prompts interactively if omitted)
+        #[arg(conflicts_with = "unused")]
+        name: Option<String>,
+        /// Remove every profile never switched to (or, with --days, not in that long), except the active one
+        #[arg(long)]
+        unused: bool,
+        /// With --unused, also remove profiles last switched to more than this many days ago
+        #[arg(long, value_name = "N", requires = "unused")]
+        days: Option<u64>,
+        /// Leave the env file alone even if it holds the removed profile's credentials
+        #[arg(long)]
+        keep_env: bool,
+        /// Print the outcome as a JSON object on stdout, with an error code on failure
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        mutation: MutationArgs,
+    },
+    /// Bring back a removed profile
+    Restore {
+        /// Profile to restore (the most recently removed if omitted)
+        name: Option<String>,
+    },
+    /// Inspect recently removed profiles
+    Trash {
+        #[command(subcommand)]
+        action: TrashAction,
     },
     /// Switch to a specific profile
     Use {
-        /// Profile name to activate
+        /// Profile name to activate (prompts interactively if omitted)
+        name: Option<String>,
+        /// Check the token with Cloudflare before switching
+        #[arg(long)]
+        verify: bool,
+        /// Print the profile's credentials instead of switching (leaves the env file and current profile alone)
+        #[arg(long, value_enum)]
+        format: Option<OutputFormat>,
+        /// Shell syntax for --format env (defaults to $SHELL)
+        #[arg(long, requires = "format", value_parser = ["bash", "zsh", "fish"])]
+        shell: Option<String>,
+        /// Variable names for --format, as a tool expects them (defaults to the config's env_var_names)
+        #[arg(long, value_enum, value_name = "TOOL", requires = "format")]
+        name_template: Option<EnvVarPreset>,
+        /// Pick the profile whose account_id (or zone) matches the nearest wrangler.toml
+        #[arg(long, conflicts_with = "name")]
+        from_wrangler: bool,
+        /// Pick the profile whose zone matches the git origin remote's owner or repo name
+        #[arg(long, conflicts_with_all = ["name", "from_wrangler"])]
+        from_git: bool,
+        /// Write the env file here instead of ~/.cloudflare.env, e.g. ./.cloudflare.env for direnv
+        #[arg(long, value_name = "PATH", conflicts_with = "format")]
+        env_out: Option<PathBuf>,
+        /// Print the outcome as a JSON object on stdout instead of the source line
+        #[arg(long, conflicts_with = "format")]
+        json: bool,
+        /// After switching, run this command with the profile's variables set and exit with its status
+        #[arg(long, value_name = "COMMAND", num_args = 1.., allow_hyphen_values = true, conflicts_with_all = ["format", "json"])]
+        and_run: Option<Vec<String>>,
+    },
+    /// Skip ahead (or back, with a negative count) in the toggle order
+    Next {
+        /// How many profiles to advance
+        #[arg(default_value_t = 1, allow_negative_numbers = true)]
+        count: i64,
+        /// Check the target profile's token with Cloudflare first
+        #[arg(long)]
+        verify: bool,
+        /// Write the env file here instead of ~/.cloudflare.env
+        #[arg(long, value_name = "PATH")]
+        env_out: Option<PathBuf>,
+    },
+    /// Switch back to the profile that was active before the current one
+    Back {
+        /// Check the previous profile's token with Cloudflare first
+        #[arg(long)]
+        verify: bool,
+    },
+    /// Show current active profile (exits 1 if none is active, 2 if it no longer exists)
+    Current {
+        /// Print the active profile as JSON on stdout (null when none is active)
+        #[arg(long)]
+        json: bool,
+        /// With --json, print only this field, e.g. '.zone' (strings unquoted)
+        #[arg(long, value_name = "PATH", requires = "json")]
+        select: Option<String>,
+        /// Exit 1 if this shell's CF_API_* variables don't match the active profile (a stale session)
+        #[arg(long)]
+        verify_env: bool,
+    },
+    /// Keep checking profiles for approaching expiry and warn as each gets close (Ctrl-C to stop)
+    WatchExpiry {
+        /// How often to check, e.g. 30s, 5m or 1h
+        #[arg(long, default_value = "5m")]
+        interval: String,
+        /// Warn once a profile or token has less than this left
+        #[arg(long, default_value = "1d")]
+        within: String,
+        /// Also ask Cloudflare when each profile's token expires (one API call per profile per check)
+        #[arg(long)]
+        api: bool,
+    },
+    /// Show the audit log (set "audit_log": true in the config to record one); --profile narrows it to one profile
+    Log {
+        /// Only entries at or after this time: YYYY-MM-DD[THH:MM[:SS]] (UTC), Unix time, or an age like 12h or 7d
+        #[arg(long, value_name = "TIME")]
+        since: Option<String>,
+        /// Only entries before this time (a bare date includes that whole day)
+        #[arg(long, value_name = "TIME")]
+        until: Option<String>,
+        /// Only entries for this zone
+        #[arg(short, long)]
+        zone: Option<String>,
+        /// Print the matching entries as JSON lines on stdout
+        #[arg(long)]
+        json: bool,
+    },
+    /// Summarize the configured profiles
+    Stats {
+        /// Print the summary as JSON on stdout
+        #[arg(long)]
+        json: bool,
+        /// Only count profiles whose email is at this domain (or a subdomain of it)
+        #[arg(long)]
+        domain: Option<String>,
+    },
+    /// Rewrite the env file from the current profile without switching
+    Sync,
+    /// Check the config file for problems without contacting Cloudflare
+    Validate,
+    /// Encrypt the config file with a passphrase (asked for, or $CF_SWITCH_PASSPHRASE)
+    Encrypt,
+    /// Turn an encrypted config file back into plain JSON
+    Decrypt,
+    /// Check that Cloudflare's API can be reached from here (DNS, TLS, latency)
+    Doctor,
+    /// Check every profile's token, expiry, zones and auth mode; exit 1 if any fails
+    Health {
+        /// Print the report as JSON on stdout
+        #[arg(long)]
+        json: bool,
+        /// Profiles checked at once
+        #[arg(long, value_name = "N", default_value_t = LIST_CHECK_CONCURRENCY as u16, value_parser = clap::value_parser!(u16).range(1..=16))]
+        concurrency: u16,
+        /// Seconds one profile's checks may take altogether (default: twice --timeout)
+        #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+        profile_timeout: Option<u64>,
+        /// Fail tokens that expire within this many days
+        #[arg(long, value_name = "DAYS", default_value_t = 14)]
+        expiry_days: u64,
+    },
+    /// Inspect the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Browse profiles full-screen, switching, adding, removing and purging from the keyboard
+    #[cfg(feature = "tui")]
+    Tui,
+    /// Print just the absolute path of a file cf-switch uses, for scripts
+    Path {
+        #[command(subcommand)]
+        file: PathFile,
+    },
+    /// Set the order the toggle cycles through profiles (interactive without --order)
+    Reorder {
+        /// Comma-separated profile names, e.g. prod,staging,dev
+        #[arg(long, value_delimiter = ',', conflicts_with = "reset")]
+        order: Option<Vec<String>>,
+        /// Go back to alphabetical order
+        #[arg(long)]
+        reset: bool,
+        /// Visit the most-used profiles first (by how often each is switched to)
+        #[arg(long, conflicts_with_all = ["order", "reset"])]
+        sort_by_usage: bool,
+        #[command(flatten)]
+        mutation: MutationArgs,
+    },
+    /// Create DNS records listed in a file (one `type, name, content[, proxied]` per line)
+    DnsFromFile {
+        /// Records file
+        file: PathBuf,
+        /// Zone to create the records in (defaults to the profile's zone)
+        #[arg(short, long)]
+        zone: Option<String>,
+        /// Read CF_API_* credentials from this file instead of the current profile
+        #[arg(long, value_name = "PATH")]
+        env_file: Option<PathBuf>,
+        #[command(flatten)]
+        bulk: BulkArgs,
+        /// json also reports each record as it's done, one JSON object per line on stdout
+        #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputMode::Text)]
+        output: OutputMode,
+    },
+    /// Print the profiles (never tokens) as a JSON list that `pull` can read
+    Export {
+        /// Also mask emails and mark the file as redacted, for sharing in a support request
+        #[arg(long)]
+        redacted: bool,
+    },
+    /// Add the team's shared profiles (no secrets) from a URL or file, asking for each token
+    Pull {
+        /// URL (or local file) serving a JSON list of {name, email, zone, description, account_id, tags}
+        url: String,
+        /// Namespace the pulled profiles, e.g. --prefix acme turns 'prod' into 'acme-prod'
+        #[arg(long, value_name = "PREFIX")]
+        prefix: Option<String>,
+        #[command(flatten)]
+        mutation: MutationArgs,
+    },
+    /// Create a profile from a .env file's CF_API_* and CF_ZONE variables
+    ImportEnv {
+        /// Path to the .env file
+        path: PathBuf,
+        /// Name for the new profile
         name: String,
+        /// Namespace the profile, e.g. --prefix acme turns 'prod' into 'acme-prod'
+        #[arg(long, value_name = "PREFIX")]
+        prefix: Option<String>,
+        #[command(flatten)]
+        mutation: MutationArgs,
+    },
+    /// Print or install shell completion scripts
+    #[command(args_conflicts_with_subcommands = true)]
+    Completions {
+        /// Shell to generate completions for
+        shell: Option<Shell>,
+        /// Also complete profile names from the live config (fish only)
+        #[arg(long)]
+        dynamic: bool,
+        #[command(subcommand)]
+        action: Option<CompletionsAction>,
+    },
+
+    /// Add the cfs wrapper to your shell's rc file
+    Init {
+        /// Shell to set up (defaults to $SHELL)
+        #[arg(long)]
+        shell: Option<Shell>,
+        /// Include the directory-based auto-switch hook
+        #[arg(long)]
+        auto: bool,
+        /// Show what would be appended without touching the rc file
+        #[arg(long)]
+        dry_run: bool,
     },
-    /// Show current active profile
-    Current,
     /// Print shell hook for automatic sourcing
-    Hook,
+    Hook {
+        /// How the wrapper applies cf-switch output (defaults to eval for bash/zsh, source for fish)
+        #[arg(long, value_enum)]
+        eval_style: Option<EvalStyle>,
+        /// Also switch automatically when entering a directory with a .cf-profile file
+        #[arg(long)]
+        auto: bool,
+    },
     /// Purge cache for a zone (uses profile's default zone if not specified)
     Purge {
-        /// Zone to purge (e.g., 50bestspa.com) - optional if profile has default zone
-        zone: Option<String>,
+        /// Zones to purge (e.g., 50bestspa.com) - optional if profile has default zone
+        #[arg(value_name = "ZONE")]
+        zones: Vec<String>,
+        /// Allow purging a zone that matches one of the config's protected_patterns
+        #[arg(long)]
+        i_know_what_im_doing: bool,
+        /// Purge even if the zone was purged within the cooldown window or isn't active on Cloudflare
+        #[arg(short, long)]
+        force: bool,
+        /// Take the zone from a .cf-zone, wrangler.toml or package.json in or above the cwd
+        #[arg(long, conflicts_with = "zones")]
+        zone_from_cwd: bool,
+        /// Read CF_API_* credentials from this file instead of the current profile
+        #[arg(long, value_name = "PATH")]
+        env_file: Option<PathBuf>,
+        /// After purging, poll a URL until Cloudflare reports it as uncached
+        #[arg(long)]
+        wait: bool,
+        /// URL to poll with --wait (defaults to https://<zone>/)
+        #[arg(long, value_name = "URL", requires = "wait")]
+        wait_url: Option<String>,
+        /// Keep running and purge whenever files under this directory change
+        #[arg(long, value_name = "DIR", conflicts_with = "wait")]
+        watch: Option<PathBuf>,
+        /// Purge through the Cloudflare API instead of flarectl, backing off when rate limited
+        #[arg(long)]
+        api: bool,
+        /// With --api, purge up to N zones at once (at most 8)
+        #[arg(long, value_name = "N", default_value_t = 1, requires = "api", conflicts_with = "watch")]
+        parallel: usize,
+        /// With --api, purge only these hostnames in the zone instead of everything (comma-separated or repeated)
+        #[arg(long, value_name = "HOST", value_delimiter = ',', requires = "api", conflicts_with_all = ["prefixes", "watch"])]
+        hosts: Vec<String>,
+        /// With --api, purge only URLs under these prefixes, e.g. example.com/blog (Enterprise zones)
+        #[arg(long, value_name = "PREFIX", value_delimiter = ',', requires = "api", conflicts_with = "watch")]
+        prefixes: Vec<String>,
+        /// With --api, purge only these URLs; '-' reads them from stdin, one per line
+        #[arg(long, value_name = "URL", value_delimiter = ',', requires = "api", conflicts_with_all = ["hosts", "prefixes", "watch"])]
+        files: Vec<String>,
+        /// With --api, purge only responses with these Cache-Tag values (Enterprise zones; checked first)
+        #[arg(long, value_name = "TAG", value_delimiter = ',', requires = "api", conflicts_with_all = ["hosts", "prefixes", "files", "watch"])]
+        tags: Vec<String>,
+        /// With --api, purge the URLs in this sitemap.xml (URL or file) that are in the zone; follows a sitemap index
+        #[arg(long, value_name = "URL", requires = "api", conflicts_with_all = ["hosts", "prefixes", "files", "tags", "watch"])]
+        sitemap: Option<String>,
+        /// Skip zones already purged at or after this time (RFC 3339 or Unix time), e.g. a deploy's
+        #[arg(long, value_name = "TIME", conflicts_with = "watch")]
+        if_newer: Option<String>,
+        /// Like --if-newer, taking the time from this file's modification time
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["if_newer", "watch"])]
+        if_newer_than_file: Option<PathBuf>,
+        /// Show what each purge will clear (the zone's plan and account, or the URLs) and ask first
+        #[arg(long, conflicts_with = "watch")]
+        confirm: bool,
+        /// With --api, purge the zone with this id (32 hex characters) instead of looking its name up
+        #[arg(long, value_name = "ID", requires = "api", conflicts_with_all = ["zone_from_cwd", "watch"])]
+        zone_id: Option<String>,
+        /// json also reports each step as it happens, one JSON object per line on stdout
+        #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputMode::Text)]
+        output: OutputMode,
+        /// Refuse to fall back to the profile's default zone; a zone must be given
+        #[arg(long)]
+        require_zone: bool,
+        /// In a CI build, take the zone from the config's ci_zones for its repository and log the commit; no effect outside CI
+        #[arg(long, conflicts_with = "zone_from_cwd")]
+        ci: bool,
+        #[command(flatten)]
+        bulk: BulkArgs,
     },
     /// Add Lamdera app DNS record (CNAME @ -> apps.lamdera.app)
     AddLamderaApp {
         /// Domain to configure (e.g., myapp.com)
         domain: Option<String>,
+        /// Take the domain from a .cf-zone, wrangler.toml or package.json in or above the cwd
+        #[arg(long, conflicts_with = "domain")]
+        zone_from_cwd: bool,
+        /// Read CF_API_* credentials from this file instead of the current profile
+        #[arg(long, value_name = "PATH")]
+        env_file: Option<PathBuf>,
+        /// Afterwards, fetch https://<domain>/ and report whether Cloudflare serves it
+        #[arg(long)]
+        probe: bool,
+        /// Also add a proxied www CNAME
+        #[arg(long)]
+        with_www: bool,
+        /// Where the www record points
+        #[arg(long, value_enum, value_name = "TARGET", default_value_t = WwwTarget::Apex, requires = "with_www")]
+        www_to: WwwTarget,
+        /// Refuse to fall back to the profile's default zone; a domain must be given
+        #[arg(long)]
+        require_zone: bool,
+    },
+    /// Send an authenticated request to any Cloudflare API endpoint and print the response
+    Api {
+        /// HTTP method, e.g. GET, POST, PATCH, DELETE
+        method: String,
+        /// Path under https://api.cloudflare.com/client/v4/, e.g. zones?name=example.com
+        path: String,
+        /// JSON request body
+        #[arg(short, long, value_name = "JSON")]
+        data: Option<String>,
+        /// Print the response body as received instead of re-serializing it (see --pretty)
+        #[arg(long)]
+        raw: bool,
+        /// Describe the request on stderr (the token is masked)
+        #[arg(short, long)]
+        verbose: bool,
+        /// Read CF_API_* credentials from this file instead of the current profile
+        #[arg(long, value_name = "PATH")]
+        env_file: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CompletionsAction {
+    /// Write the completion script to the shell's conventional location
+    Install {
+        /// Shell to install for (defaults to $SHELL)
+        #[arg(long)]
+        shell: Option<Shell>,
+        /// Overwrite an existing completion script
+        #[arg(long)]
+        force: bool,
+        /// Also complete profile names from the live config (fish only)
+        #[arg(long)]
+        dynamic: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print where the config and env file live
+    Path,
+    /// Print the config as JSON, with tokens masked (--reveal prints them in full)
+    Show,
+    /// Upgrade the config file to the current schema, keeping a backup (also done on load when needed)
+    Migrate,
+}
+
+#[derive(Subcommand)]
+enum PathFile {
+    /// The env file to source in this shell, e.g. source "$(cf-switch path env)"
+    Env {
+        /// Shell whose env file to give (defaults to $SHELL)
+        #[arg(long, value_parser = ["bash", "zsh", "fish", "nu"])]
+        shell: Option<String>,
     },
+    /// The global config file, after --config, CF_SWITCH_CONFIG and XDG_CONFIG_HOME
+    Config,
+}
+
+#[derive(Subcommand)]
+enum TrashAction {
+    /// List removed profiles that `restore` can bring back, newest first
+    List,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// Shell export statements for eval
+    Env,
+    /// Bare KEY=value lines for Makefile `include` or `docker run --env-file`
+    ShellExport,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum GroupBy {
+    /// Cloudflare account ID
+    Account,
+    /// Domain part of the profile email
+    EmailDomain,
+    /// Profile tags (a profile can appear under several)
+    Tag,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputMode {
+    /// Messages for people, on stderr
+    Text,
+    /// The same messages, plus newline-delimited JSON events on stdout
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ListSort {
+    /// Alphabetically
+    Name,
+    /// Most switched-to first (--verbose shows the counts)
+    Usage,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ColorChoice {
+    /// Color when stderr is a terminal, unless NO_COLOR is set (CLICOLOR_FORCE forces it)
+    Auto,
+    /// Color even when piped, e.g. into less -R
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum WwwTarget {
+    /// www.<domain> -> <domain>
+    Apex,
+    /// www.<domain> -> apps.lamdera.app
+    Lamdera,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum EvalStyle {
+    /// Capture the output and eval it
+    Eval,
+    /// Stream the output into source
+    Source,
 }
 
 #[derive(Serialize, Deserialize, Default)]
 struct Config {
+    /// Shape of this file, for `migrate_config`; 0 means from before versioning
+    #[serde(default)]
+    schema_version: u32,
     profiles: HashMap<String, Profile>,
     current: Option<String>,
+    /// The profile that was active before `current`, for `back`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous: Option<String>,
+    /// Zone patterns that refuse a full purge without an explicit override
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    protected_patterns: Vec<String>,
+    /// Make purge and add-lamdera-app always take a zone, as --require-zone does
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    require_zone: bool,
+    /// Seconds to wait before the same zone can be purged again (default 10)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    purge_cooldown_secs: Option<u64>,
+    /// Proxy for Cloudflare API calls and flarectl, e.g. http://proxy.corp:3128
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    http_proxy: Option<String>,
+    /// PEM CA bundle to trust for those connections (for TLS-intercepting proxies)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ca_cert: Option<String>,
+    /// Public key hash(es) the Cloudflare API's certificate must have, as --pin takes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tls_pin: Option<String>,
+    /// How often `purge --api` retries after being rate limited (default 3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    purge_max_retries: Option<u32>,
+    /// Shells to write an env file for on every switch (default: bash only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    env_formats: Vec<EnvFormat>,
+    /// Variable names to export, per tool (default: flarectl's CF_API_*)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    env_var_names: Vec<EnvVarNaming>,
+    /// Comment written above the variables in shell env files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    env_header: Option<String>,
+    /// Comment written below the variables in shell env files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    env_footer: Option<String>,
+    /// Explicit toggle order; profiles not listed follow alphabetically
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    order: Vec<String>,
+    /// Toggle through profiles most-used first, ignoring `order`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    order_by_usage: bool,
+    /// Make a bare `cf-switch` in a terminal open a type-to-filter picker
+    /// instead of moving to the next profile
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    interactive_toggle: bool,
+    /// Zone `purge --ci` purges for each repository (`owner/name`) when none is given
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    ci_zones: BTreeMap<String, String>,
+    /// Unix timestamp of the last successful purge, per zone
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    last_purge: HashMap<String, u64>,
+    /// Append what each command changes to ~/.cf-switch-audit.jsonl
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    audit_log: bool,
+    /// Checksum of each env file as cf-switch last wrote it, by path
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    env_hash: HashMap<String, String>,
+    /// How much of a token to print: none, last4 (default) or full
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    redaction: Option<mask::Redaction>,
+    /// Recently removed profiles, oldest first, for `restore`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    trash: Vec<(String, Profile)>,
+    /// A config file that failed to parse and was loaded as empty; saving
+    /// would replace everything in it, so `try_save_config` refuses
+    #[serde(skip)]
+    unparsed: Option<PathBuf>,
+    /// Profiles that came from (and are saved back to) a project config
+    #[serde(skip)]
+    local: Option<LocalProfiles>,
+}
+
+/// Bookkeeping for a project `.cf-switch.json` merged over the global config
+struct LocalProfiles {
+    path: PathBuf,
+    names: HashSet<String>,
+    /// Global profiles hidden by a local one of the same name, kept for saving
+    shadowed: HashMap<String, Profile>,
+}
+
+const DEFAULT_PURGE_COOLDOWN_SECS: u64 = 10;
+/// How many removed profiles `restore` can bring back
+const TRASH_SIZE: usize = 5;
+const DEFAULT_PURGE_MAX_RETRIES: u32 = 3;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum EnvFormat {
+    Bash,
+    Zsh,
+    Fish,
+    Nu,
+}
+
+/// Built-in variable naming for the tools people usually feed credentials to
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum EnvVarPreset {
+    /// CF_API_TOKEN, or CF_API_EMAIL + CF_API_KEY
+    Flarectl,
+    /// The Cloudflare provider's CLOUDFLARE_* plus TF_VAR_cloudflare_account_id/zone
+    Terraform,
+    /// CLOUDFLARE_API_TOKEN (or _EMAIL + _API_KEY) and CLOUDFLARE_ACCOUNT_ID
+    Wrangler,
 }
 
+/// Variable name per profile field; unmapped fields aren't exported
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+struct EnvVarNames {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    /// The secret, for API token profiles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    /// The secret, for global API key profiles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    account_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    zone: Option<String>,
+}
+
+/// An `env_var_names` entry: a preset name or a custom mapping
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+enum EnvVarNaming {
+    Preset(EnvVarPreset),
+    Custom(EnvVarNames),
+}
+
+impl EnvVarPreset {
+    fn names(self) -> EnvVarNames {
+        let name = |n: &str| Some(n.to_string());
+        match self {
+            EnvVarPreset::Flarectl => EnvVarNames {
+                email: name("CF_API_EMAIL"),
+                token: name("CF_API_TOKEN"),
+                key: name("CF_API_KEY"),
+                ..Default::default()
+            },
+            EnvVarPreset::Terraform => EnvVarNames {
+                email: name("CLOUDFLARE_EMAIL"),
+                token: name("CLOUDFLARE_API_TOKEN"),
+                key: name("CLOUDFLARE_API_KEY"),
+                account_id: name("TF_VAR_cloudflare_account_id"),
+                zone: name("TF_VAR_cloudflare_zone"),
+            },
+            EnvVarPreset::Wrangler => EnvVarNames {
+                email: name("CLOUDFLARE_EMAIL"),
+                token: name("CLOUDFLARE_API_TOKEN"),
+                key: name("CLOUDFLARE_API_KEY"),
+                account_id: name("CLOUDFLARE_ACCOUNT_ID"),
+                zone: None,
+            },
+        }
+    }
+}
+
+impl EnvFormat {
+    /// Shell name as understood by `export_line` (and matched against $SHELL)
+    fn shell(self) -> &'static str {
+        match self {
+            EnvFormat::Bash | EnvFormat::Zsh => "bash",
+            EnvFormat::Fish => "fish",
+            EnvFormat::Nu => "nu",
+        }
+    }
+
+    /// bash and zsh share ~/.cloudflare.env; fish gets ~/.cloudflare.fish and
+    /// nushell ~/.cloudflare.nuon
+    fn path(self) -> PathBuf {
+        let home = || dirs::home_dir().expect("Could not find home directory");
+        match self {
+            EnvFormat::Bash | EnvFormat::Zsh => env_path(),
+            EnvFormat::Fish => home().join(".cloudflare.fish"),
+            EnvFormat::Nu => home().join(".cloudflare.nuon"),
+        }
+    }
+}
+
+impl Config {
+    fn is_local(&self, name: &str) -> bool {
+        self.local.as_ref().is_some_and(|l| l.names.contains(name))
+    }
+
+    /// Make `name` a project profile saved to `path`, shadowing any global one
+    fn mark_local(&mut self, path: &Path, name: &str) {
+        let local = self.local.get_or_insert_with(|| LocalProfiles {
+            path: path.to_path_buf(),
+            names: HashSet::new(),
+            shadowed: HashMap::new(),
+        });
+        if let Some(global) = self.profiles.remove(name) {
+            if !local.names.contains(name) {
+                local.shadowed.insert(name.to_string(), global);
+            }
+        }
+        local.names.insert(name.to_string());
+    }
+
+    /// Variable naming for the env file, flarectl's unless configured
+    fn env_var_names(&self) -> Vec<EnvVarNames> {
+        if self.env_var_names.is_empty() {
+            return vec![EnvVarPreset::Flarectl.names()];
+        }
+        self.env_var_names
+            .iter()
+            .map(|naming| match naming {
+                EnvVarNaming::Preset(preset) => preset.names(),
+                EnvVarNaming::Custom(names) => names.clone(),
+            })
+            .collect()
+    }
+
+    /// Remember the checksums of env files about to be written; only kept
+    /// once the config is saved
+    fn record_env_files(&mut self, files: &[(PathBuf, String)]) {
+        for (path, content) in files {
+            self.env_hash.insert(path.display().to_string(), checksum(content));
+        }
+    }
+
+    /// Keep a removed profile for `restore`. Returns the ones pushed out of a
+    /// full trash, whose secrets can now be deleted from their backends.
+    fn trash_profile(&mut self, name: &str, profile: Profile) -> Vec<(String, Profile)> {
+        self.trash.retain(|(trashed, _)| trashed != name);
+        self.trash.push((name.to_string(), profile));
+        let overflow = self.trash.len().saturating_sub(TRASH_SIZE);
+        self.trash.drain(..overflow).collect()
+    }
+
+    /// The profile `alias` belongs to
+    fn alias_owner(&self, alias: &str) -> Option<&String> {
+        self.profiles.iter().find(|(_, p)| p.aliases.iter().any(|a| a == alias)).map(|(name, _)| name)
+    }
+
+    /// Point `previous` and `order` entries for `old` at `new`, or drop them
+    /// when `new` is `None` because the profile is gone, so neither dangles.
+    /// `current` is left to the caller, which also has the env files to fix.
+    fn rename_references(&mut self, old: &str, new: Option<&str>) {
+        if self.previous.as_deref() == Some(old) {
+            self.previous = new.map(str::to_string);
+        }
+        match new {
+            Some(new) => self.order.iter_mut().filter(|name| *name == old).for_each(|name| *name = new.to_string()),
+            None => self.order.retain(|name| name != old),
+        }
+    }
+
+    /// Env file formats to write, one per distinct file
+    fn env_formats(&self) -> Vec<EnvFormat> {
+        let mut formats: Vec<EnvFormat> = Vec::new();
+        for format in &self.env_formats {
+            if !formats.iter().any(|f| f.path() == format.path()) {
+                formats.push(*format);
+            }
+        }
+        if formats.is_empty() {
+            formats.push(EnvFormat::Bash);
+        }
+        formats
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
 struct Profile {
+    /// Cloudflare account login. Global API key auth sends it with the key;
+    /// API tokens don't need it, so for them it's optional and only a label.
+    #[serde(default)]
     email: String,
+    /// The token itself for plaintext profiles; empty for other backends
+    #[serde(default)]
     token: String,
+    #[serde(default, skip_serializing_if = "Backend::is_plaintext")]
+    backend: Backend,
+    /// Whether `token` is a scoped API token or the global API key; inferred when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auth: Option<AuthMode>,
     #[serde(default)]
     zone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    account_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// Other names the profile answers to wherever a profile is named
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    aliases: Vec<String>,
+    /// Unix time after which an ephemeral profile is removed on the next run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    /// How many times this profile has been switched to
+    #[serde(default, skip_serializing_if = "is_zero")]
+    use_count: u64,
+    /// Unix time it was last switched to. Unset for profiles never switched
+    /// to, and for ones last switched to before this was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_used: Option<u64>,
+    /// `Some(false)` makes purging a whole zone need --force and the zone
+    /// name typed in; unset allows it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    allow_purge_everything: Option<bool>,
 }
 
-fn config_path() -> PathBuf {
-    dirs::home_dir()
-        .expect("Could not find home directory")
-        .join(".cf-switch.json")
+/// Why `list --unused` and `remove --unused` pick a profile
+enum Unused {
+    Never,
+    /// Days since it was last switched to
+    Idle(u64),
 }
 
-fn env_path() -> PathBuf {
-    dirs::home_dir()
-        .expect("Could not find home directory")
-        .join(".cloudflare.env")
+impl Profile {
+    /// Whether the profile is unused: never switched to, or with `days`,
+    /// not in more than that many days. A profile with switches but no
+    /// time was last used before times were recorded, so isn't known to be idle.
+    fn unused(&self, days: Option<u64>) -> Option<Unused> {
+        match self.last_used {
+            None if self.use_count == 0 => Some(Unused::Never),
+            None => None,
+            Some(at) => {
+                let idle = now_secs().saturating_sub(at) / 86_400;
+                days.filter(|days| idle > *days).map(|_| Unused::Idle(idle))
+            }
+        }
+    }
 }
 
-fn load_config() -> Config {
-    let path = config_path();
-    if path.exists() {
-        let content = fs::read_to_string(&path).expect("Failed to read config file");
-        serde_json::from_str(&content).unwrap_or_default()
-    } else {
-        Config::default()
+impl std::fmt::Display for Unused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unused::Never => write!(f, "never used"),
+            Unused::Idle(days) => write!(f, "unused for {} days", days),
+        }
     }
 }
 
-fn save_config(config: &Config) {
-    let path = config_path();
-    let content = serde_json::to_string_pretty(config).expect("Failed to serialize config");
-    fs::write(&path, content).expect("Failed to write config file");
+fn is_zero(n: &u64) -> bool {
+    *n == 0
 }
 
-fn write_env_file(profile: &Profile, name: &str) {
-    let path = env_path();
-    let content = format!(
-        "# Cloudflare credentials - profile: {}\nexport CF_API_EMAIL=\"{}\"\nexport CF_API_KEY=\"{}\"\nexport CF_API_TOKEN=\"{}\"\n",
-        name, profile.email, profile.token, profile.token
-    );
-    fs::write(&path, content).expect("Failed to write env file");
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum AuthMode {
+    /// Scoped API token, sent as CF_API_TOKEN
+    Token,
+    /// Global API key, sent as CF_API_KEY together with CF_API_EMAIL
+    Key,
+}
+
+impl std::fmt::Display for AuthMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AuthMode::Token => "token",
+            AuthMode::Key => "key",
+        })
+    }
+}
+
+impl AuthMode {
+    /// What a secret's shape says it is: global API keys are 37 lowercase hex
+    /// characters, tokens are 40 base64-ish ones. `None` for anything else.
+    fn guess(secret: &str) -> Option<AuthMode> {
+        if secret.len() == 37 && secret.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c)) {
+            Some(AuthMode::Key)
+        } else if secret.len() == 40 && secret.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            Some(AuthMode::Token)
+        } else {
+            None
+        }
+    }
+}
+
+impl Profile {
+    /// The configured auth mode, or a guess from the secret's shape
+    /// (a token when the shape says nothing)
+    fn auth_mode(&self) -> AuthMode {
+        self.auth.unwrap_or_else(|| AuthMode::guess(&self.token).unwrap_or(AuthMode::Token))
+    }
+
+    /// The email for messages, or a stand-in when a token profile has none
+    fn email_label(&self) -> &str {
+        if self.email.is_empty() {
+            "no email"
+        } else {
+            &self.email
+        }
+    }
+
+    /// Lowercased domain part of the email
+    fn email_domain(&self) -> Option<String> {
+        self.email.rsplit_once('@').map(|(_, domain)| domain.trim().to_lowercase()).filter(|d| !d.is_empty())
+    }
+
+    /// Whether a purge of everything on a zone may go ahead without extra checks
+    fn allows_purge_everything(&self) -> bool {
+        self.allow_purge_everything.unwrap_or(true)
+    }
+
+    /// Whether the email is at `domain` or one of its subdomains
+    fn in_email_domain(&self, domain: &str) -> bool {
+        let domain = domain.trim().trim_start_matches('@').to_lowercase();
+        self.email_domain().is_some_and(|d| d == domain || d.ends_with(&format!(".{}", domain)))
+    }
 }
 
 /// Print to stderr (for user-facing messages)
 macro_rules! msg {
     ($($arg:tt)*) => {
-        writeln!(io::stderr(), $($arg)*).ok();
+        let line = mask::scrub(&format!($($arg)*));
+        if !pager::capture(&line) {
+            writeln!(io::stderr(), "{}", line).ok();
+        }
     };
 }
 
-/// Print to stdout (for shell commands to be eval'd)
-fn cmd(s: &str) {
-    println!("{}", s);
+/// `--dry-run` and `--yes`, shared by the commands that change profiles
+#[derive(Args)]
+struct MutationArgs {
+    /// Show what would change without changing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Don't ask before destructive changes
+    #[arg(short, long)]
+    yes: bool,
 }
 
-fn detect_shell() -> String {
-    std::env::var("SHELL")
-        .unwrap_or_default()
-        .rsplit('/')
-        .next()
-        .unwrap_or("bash")
-        .to_string()
+impl MutationArgs {
+    /// Whether to go ahead with `action` ("remove profile 'x'"). Under
+    /// --dry-run it's only described; destructive actions ask first when
+    /// interactive, and declining exits.
+    fn confirm(&self, ctx: &Ctx, action: &str, destructive: bool) -> bool {
+        if self.dry_run {
+            msg!("{} Would {}", "[dry-run]".dimmed(), action);
+            return false;
+        }
+        if destructive && !self.yes && io::stdin().is_terminal() {
+            let prompt = format!("{}{}?", action[..1].to_uppercase(), &action[1..]);
+            if !prompt_or_exit(ctx, dialoguer::Confirm::new().with_prompt(prompt).default(false).interact()) {
+                msg!("Cancelled.");
+                ctx.fail("cancelled", "declined at the prompt");
+            }
+        }
+        true
+    }
 }
 
-fn output_source_command() {
-    let env_file = env_path().display().to_string();
-    cmd(&format!("source {}", env_file));
+/// `--fail-fast` and `--keep-going`, shared by the commands that work through a list
+#[derive(Args)]
+struct BulkArgs {
+    /// Stop at the first item that fails, leaving the rest unattempted
+    #[arg(long, conflicts_with = "keep_going")]
+    fail_fast: bool,
+    /// Carry on past failures and report them at the end (the default)
+    #[arg(long)]
+    keep_going: bool,
 }
 
-fn switch_to_profile(config: &mut Config, name: &str) -> bool {
-    if let Some(profile) = config.profiles.get(name).cloned() {
-        write_env_file(&profile, name);
-        config.current = Some(name.to_string());
-        save_config(config);
-        msg!("{} {} ({})", "ON".green().bold(), name.cyan().bold(), profile.email);
-        output_source_command();
-        true
-    } else {
-        msg!("{} Profile '{}' not found.", "Error:".red().bold(), name);
-        false
-    }
+/// How a bulk command's items went. Every failure is counted; under
+/// --fail-fast the first one stops the run and what's left is counted as
+/// not attempted. Either way any failure makes the command exit 1.
+struct BulkResults {
+    fail_fast: bool,
+    failed: usize,
+    not_attempted: usize,
 }
 
-fn main() {
-    let cli = Cli::parse();
+impl BulkResults {
+    fn new(args: &BulkArgs) -> Self {
+        BulkResults { fail_fast: args.fail_fast, failed: 0, not_attempted: 0 }
+    }
 
-    match cli.command {
-        // No command = toggle to next profile
-        None => {
-            let mut config = load_config();
-            if config.profiles.is_empty() {
-                msg!("{}", "No profiles configured.".yellow());
-                msg!("Add one with: cf-switch add <name> -e <email> -t <token>");
-                return;
-            }
+    /// Count one item's outcome
+    fn record(&mut self, ok: bool) {
+        if !ok {
+            self.failed += 1;
+        }
+    }
 
-            // Get sorted profile names for consistent ordering
-            let mut names: Vec<_> = config.profiles.keys().cloned().collect();
-            names.sort();
+    /// Whether to leave the remaining items alone
+    fn stopping(&self) -> bool {
+        self.fail_fast && self.failed > 0
+    }
 
-            // Find next profile
-            let next_name = match &config.current {
-                Some(current) => {
-                    let current_idx = names.iter().position(|n| n == current).unwrap_or(0);
-                    let next_idx = (current_idx + 1) % names.len();
-                    names[next_idx].clone()
-                }
-                None => names[0].clone(),
-            };
+    /// Count an item left alone because of --fail-fast
+    fn skip(&mut self) {
+        self.not_attempted += 1;
+    }
 
-            switch_to_profile(&mut config, &next_name);
+    /// ", N not attempted" for the end-of-run summary, if any were
+    fn not_attempted_note(&self) -> String {
+        if self.not_attempted > 0 {
+            format!(", {} not attempted", self.not_attempted)
+        } else {
+            String::new()
         }
+    }
 
-        Some(Commands::List) => {
-            let config = load_config();
-            if config.profiles.is_empty() {
-                msg!("{}", "No profiles configured.".yellow());
-                msg!("Add one with: cf-switch add <name> -e <email> -t <token>");
-                return;
-            }
-            msg!("{}", "Cloudflare Profiles:".bold());
-            let mut names: Vec<_> = config.profiles.keys().collect();
-            names.sort();
-            for name in names {
-                let profile = &config.profiles[name];
-                let marker = if config.current.as_ref() == Some(name) {
-                    "ON".green().bold()
-                } else {
-                    "  ".normal()
-                };
-                msg!("{} {} ({})", marker, name.cyan(), profile.email);
-            }
+    /// Say why the run ended early, if it did
+    fn report_stop(&self) {
+        if self.not_attempted > 0 {
+            msg!("{} Stopped after the first failure (--fail-fast); {} left unattempted.", "!".yellow().bold(), self.not_attempted);
         }
+    }
+}
 
-        Some(Commands::Add { name, email, token, zone }) => {
-            let mut config = load_config();
-            if config.profiles.contains_key(&name) {
-                msg!("{} Profile '{}' already exists.", "Error:".red().bold(), name);
-                std::process::exit(1);
-            }
-            config.profiles.insert(name.clone(), Profile { email, token, zone: zone.clone() });
-            save_config(&config);
-            if let Some(z) = zone {
-                msg!("{} Added profile '{}' with zone '{}'", "✓".green(), name.cyan(), z);
-            } else {
-                msg!("{} Added profile '{}'", "✓".green(), name.cyan());
-            }
+/// `--config`/`CF_SWITCH_CONFIG` if given, then `$XDG_CONFIG_HOME/cf-switch/config.json`
+/// if it exists, then `~/.cf-switch.json`
+fn config_path(explicit: Option<PathBuf>) -> PathBuf {
+    if let Some(path) = explicit {
+        return path;
+    }
+    if let Some(xdg) = std::env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        let path = PathBuf::from(xdg).join("cf-switch").join("config.json");
+        if path.exists() {
+            return path;
         }
+    }
+    dirs::home_dir()
+        .expect("Could not find home directory")
+        .join(".cf-switch.json")
+}
 
-        Some(Commands::Remove { name }) => {
-            let mut config = load_config();
-            if config.profiles.remove(&name).is_some() {
-                if config.current.as_ref() == Some(&name) {
-                    config.current = None;
-                }
-                save_config(&config);
-                msg!("{} Removed profile '{}'", "✓".green(), name);
-            } else {
-                msg!("{} Profile '{}' not found.", "Error:".red().bold(), name);
-                std::process::exit(1);
+fn env_path() -> PathBuf {
+    dirs::home_dir()
+        .expect("Could not find home directory")
+        .join(".cloudflare.env")
+}
+
+/// The global config with the project config's profiles merged over it.
+/// Only `profiles` is taken from the project file; everything else (current
+/// profile, cooldowns, toggle order...) stays global.
+fn load_config(ctx: &Ctx) -> Config {
+    let mut config = read_config_file(ctx, &ctx.config_path);
+    if let Some(path) = &ctx.local_config_path {
+        let mut local = LocalProfiles { path: path.clone(), names: HashSet::new(), shadowed: HashMap::new() };
+        let project = read_config_file(ctx, path);
+        config.unparsed = config.unparsed.take().or(project.unparsed);
+        for (name, profile) in project.profiles {
+            if let Some(global) = config.profiles.insert(name.clone(), profile) {
+                local.shadowed.insert(name.clone(), global);
             }
+            local.names.insert(name);
         }
+        config.local = Some(local);
+    }
+    let pin = ctx.pin.as_ref().or(config.tls_pin.as_ref()).map(|pin| {
+        http::parse_pins(pin).unwrap_or_else(|e| {
+            msg!("{} Invalid TLS pin: {}", "Error:".red().bold(), e);
+            std::process::exit(1);
+        })
+    });
+    http::configure(http::Settings { proxy: config.http_proxy.clone(), ca_cert: config.ca_cert.clone(), pin });
+    audit::configure(Some(audit::path()).filter(|_| config.audit_log && !ctx.no_write));
+    prune_expired_profiles(ctx, &mut config);
+    config
+}
 
-        Some(Commands::Use { name }) => {
-            let mut config = load_config();
-            if !switch_to_profile(&mut config, &name) {
-                std::process::exit(1);
+/// Remove ephemeral profiles whose TTL has passed, with their stored
+/// secrets, clearing the env files if the active one goes. Runs on every
+/// load, so an expired profile can't be used even once more.
+fn prune_expired_profiles(ctx: &Ctx, config: &mut Config) {
+    let now = now_secs();
+    let mut expired: Vec<String> = config
+        .profiles
+        .iter()
+        .filter(|(_, profile)| profile.expires_at.is_some_and(|at| at <= now))
+        .map(|(name, _)| name.clone())
+        .collect();
+    if expired.is_empty() {
+        return;
+    }
+    expired.sort();
+    for name in &expired {
+        let profile = config.profiles.remove(name).expect("expired profile exists");
+        config.rename_references(name, None);
+        if let Some(store) = credentials::store(profile.backend).filter(|_| !ctx.no_write) {
+            if let Err(e) = store.delete(name) {
+                msg!("{} Could not delete the token from the {} backend: {}", "Warning:".yellow().bold(), profile.backend, e);
             }
         }
-
-        Some(Commands::Current) => {
-            let config = load_config();
-            match config.current {
-                Some(name) => {
+        msg!("{} Ephemeral profile '{}' expired and was removed", "Warning:".yellow().bold(), name);
+        audit::record(audit::Entry::new("remove", true).profile(name).detail("expired"));
+    }
+    let cleared = config.current.as_ref().filter(|current| expired.contains(current)).map(|_| cleared_env_files(config));
+    if let Some(files) = &cleared {
+        config.current = None;
+        config.record_env_files(files);
+    }
+    if let Err(e) = try_save_config(ctx, config) {
+        msg!("{} Failed to save the config: {}", "Warning:".yellow().bold(), e);
+        return;
+    }
+    if let Some(files) = cleared {
+        if let Err(e) = try_write_env_files(ctx, &files) {
+            msg!("{} Failed to clear {}: {}", "Warning:".yellow().bold(), env_file_list(config), e);
+        } else {
+            msg!("{} Cleared credentials from {}", ctx.symbols.ok.green(), env_file_list(config));
+        }
+    }
+}
+
+fn read_config_file(ctx: &Ctx, path: &Path) -> Config {
+    if !path.exists() {
+        return Config::default();
+    }
+    let content = config_text_or_exit(ctx, path);
+    // Project configs only hold profiles, which are versioned with the global file
+    let content = if path == ctx.config_path { migrate_config_text(ctx, path, content, false) } else { content };
+
+    match parse_config(&content) {
+        Ok((config, unknown)) => {
+            for profile in config.profiles.values().chain(config.trash.iter().map(|(_, p)| p)) {
+                mask::register(&profile.token);
+            }
+            if !unknown.is_empty() {
+                let label = if ctx.strict { "Error:".red().bold() } else { "Warning:".yellow().bold() };
+                for field in &unknown {
+                    msg!("{} Unknown field '{}' in {}", label, field, path.display());
+                }
+                if ctx.strict {
+                    std::process::exit(1);
+                }
+                msg!("These fields are ignored and will be dropped on the next save (use --strict to fail instead).");
+            }
+            config
+        }
+        Err(e) => {
+            let label = if ctx.strict { "Error:".red().bold() } else { "Warning:".yellow().bold() };
+            msg!("{} Invalid config file {}: {}", label, path.display(), e);
+            if ctx.strict {
+                std::process::exit(1);
+            }
+            msg!("Carrying on without it; nothing will be saved until it's fixed (see: cf-switch validate).");
+            Config { unparsed: Some(path.to_path_buf()), ..Config::default() }
+        }
+    }
+}
+
+/// The config schema this build reads and writes. Bump it, with a step in
+/// `MIGRATIONS`, whenever a field changes shape in a way serde defaults
+/// can't absorb.
+const SCHEMA_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` turns a version-n config into version n+1
+const MIGRATIONS: [fn(&mut serde_json::Value); SCHEMA_VERSION as usize] = [
+    // 0 -> 1: files from before versioning. Every field added until then
+    // has a default, so they only need the version recorded.
+    |_| {},
+];
+
+/// Upgrade config JSON to `SCHEMA_VERSION`, backing the file up as
+/// `<path>.v<old>.bak` and rewriting it when a step changed anything (or
+/// whenever `always_write`, for `config migrate`). A file from a newer
+/// cf-switch is refused, since saving it would drop what this one can't read.
+fn migrate_config_text(ctx: &Ctx, path: &Path, content: String, always_write: bool) -> String {
+    // Invalid JSON is reported by the caller's parse
+    let Ok(mut value) = serde_json::from_str::<serde_json::Value>(&content) else {
+        return content;
+    };
+    let from = value.get("schema_version").and_then(serde_json::Value::as_u64).unwrap_or(0);
+    if from > u64::from(SCHEMA_VERSION) {
+        msg!("{} {} is schema version {}, newer than this cf-switch understands ({}).", "Error:".red().bold(), path.display(), from, SCHEMA_VERSION);
+        msg!("Upgrade cf-switch to use it; nothing was changed.");
+        std::process::exit(1);
+    }
+    if from == u64::from(SCHEMA_VERSION) {
+        return content;
+    }
+    let before = value.clone();
+    for step in &MIGRATIONS[from as usize..] {
+        step(&mut value);
+    }
+    if value == before && !always_write {
+        // Nothing to keep a backup for; the next save records the version
+        return content;
+    }
+    value["schema_version"] = SCHEMA_VERSION.into();
+    let migrated = serde_json::to_string_pretty(&value).expect("Failed to serialize config");
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{}.bak", from));
+    let backup = PathBuf::from(backup);
+    let written = if ctx.no_write {
+        msg!("{} would back up {} to {} and migrate it", "[no-write]".dimmed(), path.display(), backup.display());
+        Ok(())
+    } else {
+        fs::copy(path, &backup).and_then(|_| write_atomic(path, &vault::seal(&migrated)))
+    };
+    match written {
+        Ok(()) => {
+            msg!("{} Migrated {} from schema version {} to {} (backup: {})", ctx.symbols.ok.green(), path.display(), from, SCHEMA_VERSION, backup.display());
+        }
+        Err(e) => {
+            msg!("{} Could not migrate {}: {}; using the upgraded config in memory only.", "Warning:".yellow().bold(), path.display(), e);
+        }
+    }
+    migrated
+}
+
+/// A config file's JSON, decrypting it first if `cf-switch encrypt` was used on it
+fn config_text_or_exit(ctx: &Ctx, path: &Path) -> String {
+    let content = fs::read_to_string(path).unwrap_or_else(|e| {
+        msg!("{} Failed to read {}: {}", "Error:".red().bold(), path.display(), e);
+        std::process::exit(1);
+    });
+    if !vault::is_encrypted(&content) {
+        return content;
+    }
+    vault::unlock(&content, passphrase_or_exit(ctx, false)).unwrap_or_else(|e| {
+        msg!("{} Could not decrypt {}: {}", "Error:".red().bold(), path.display(), e);
+        std::process::exit(1);
+    })
+}
+
+/// The config passphrase from $CF_SWITCH_PASSPHRASE, or asked for on a
+/// terminal (twice when setting a new one)
+fn passphrase_or_exit(ctx: &Ctx, new: bool) -> Zeroizing<String> {
+    if let Ok(passphrase) = std::env::var(vault::PASSPHRASE_VAR) {
+        if passphrase.is_empty() {
+            msg!("{} ${} is empty.", "Error:".red().bold(), vault::PASSPHRASE_VAR);
+            std::process::exit(1);
+        }
+        return Zeroizing::new(passphrase);
+    }
+    if !io::stdin().is_terminal() {
+        msg!("{} The config is encrypted; set ${} to unlock it without a terminal.", "Error:".red().bold(), vault::PASSPHRASE_VAR);
+        std::process::exit(1);
+    }
+    let prompt = dialoguer::Password::new().with_prompt(if new { "New config passphrase" } else { "Config passphrase" });
+    let prompt = if new { prompt.with_confirmation("Repeat passphrase", "Passphrases don't match") } else { prompt };
+    Zeroizing::new(prompt_or_exit(ctx, prompt.interact()))
+}
+
+/// Parse config JSON, also returning the keys serde would otherwise drop
+/// silently (e.g. a typo'd field name)
+fn parse_config(content: &str) -> Result<(Config, Vec<String>), serde_json::Error> {
+    let mut unknown = Vec::new();
+    let mut de = serde_json::Deserializer::from_str(content);
+    let config = serde_ignored::deserialize(&mut de, |field| unknown.push(field.to_string()))
+        .and_then(|config: Config| de.end().map(|_| config))?;
+    Ok((config, unknown))
+}
+
+/// Profile names in file order, duplicates included; deserializing into a
+/// map keeps only the last of each
+#[derive(Default)]
+struct ProfileKeys(Vec<String>);
+
+impl<'de> Deserialize<'de> for ProfileKeys {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeysVisitor;
+        impl<'de> serde::de::Visitor<'de> for KeysVisitor {
+            type Value = ProfileKeys;
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a map of profiles")
+            }
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<ProfileKeys, A::Error> {
+                let mut keys = Vec::new();
+                while let Some((key, serde::de::IgnoredAny)) = map.next_entry::<String, serde::de::IgnoredAny>()? {
+                    keys.push(key);
+                }
+                Ok(ProfileKeys(keys))
+            }
+        }
+        deserializer.deserialize_map(KeysVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+struct RawProfileKeys {
+    #[serde(default)]
+    profiles: ProfileKeys,
+}
+
+/// A shared profile as served to `pull`: everything but the secret
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileSkeleton {
+    name: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    zone: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    account_id: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// Key marking `export --redacted` output, so it isn't mistaken for a backup
+const REDACTED_MARKER: &str = "cf_switch_redacted_export";
+/// Stands in for each token in `export --redacted` output
+const REDACTED_TOKEN: &str = "<redacted>";
+
+/// `***@example.com`: the domain says which account it is without saying who
+fn redacted_email(email: &str) -> String {
+    match email.rsplit_once('@') {
+        Some((_, domain)) => format!("***@{}", domain),
+        None => "***".to_string(),
+    }
+}
+
+/// `export`'s JSON: the profiles as `pull` reads them, or with `redacted`,
+/// wrapped with a marker and with masked emails and token placeholders
+fn exported_profiles(config: &Config, redacted: bool) -> serde_json::Value {
+    let mut names: Vec<&String> = config.profiles.keys().collect();
+    names.sort();
+    let profiles: Vec<serde_json::Value> = names
+        .into_iter()
+        .map(|name| {
+            let profile = &config.profiles[name];
+            let mut entry = serde_json::json!({ "name": name });
+            if redacted {
+                entry["token"] = REDACTED_TOKEN.into();
+            }
+            if !profile.email.is_empty() {
+                entry["email"] = if redacted { redacted_email(&profile.email) } else { profile.email.clone() }.into();
+            }
+            if let Some(zone) = &profile.zone {
+                entry["zone"] = zone.clone().into();
+            }
+            if let Some(description) = &profile.description {
+                entry["description"] = description.clone().into();
+            }
+            if let Some(account_id) = &profile.account_id {
+                entry["account_id"] = account_id.clone().into();
+            }
+            if !profile.tags.is_empty() {
+                entry["tags"] = serde_json::json!(profile.tags);
+            }
+            entry
+        })
+        .collect();
+    if !redacted {
+        return serde_json::Value::Array(profiles);
+    }
+    serde_json::json!({
+        REDACTED_MARKER: "Tokens and email names removed for sharing; this is NOT a backup. `cf-switch pull <file>` recreates the profiles and asks for each token.",
+        "profiles": profiles,
+    })
+}
+
+/// Parse and check a `pull` response, describing the first problem found.
+/// `export --redacted` output is accepted too: its placeholders and masked
+/// emails are dropped, so the tokens are asked for like any shared list's.
+/// Also returns whether it was a redacted export.
+fn parse_skeletons(body: &str) -> Result<(Vec<ProfileSkeleton>, bool), String> {
+    let mut json: serde_json::Value = serde_json::from_str(body).map_err(|e| format!("not JSON: {}", e))?;
+    let redacted = json.get(REDACTED_MARKER).is_some();
+    if redacted {
+        json = json["profiles"].take();
+        for entry in json.as_array_mut().into_iter().flatten() {
+            let Some(entry) = entry.as_object_mut() else {
+                continue;
+            };
+            if entry.get("token").and_then(|t| t.as_str()) == Some(REDACTED_TOKEN) {
+                entry.remove("token");
+            }
+            if entry.get("email").and_then(|e| e.as_str()).is_some_and(|e| e.starts_with("***")) {
+                entry.remove("email");
+            }
+        }
+    }
+    let entries = json.as_array().ok_or("expected a JSON list of profiles")?;
+    if entries.iter().any(|e| e.get("token").is_some()) {
+        return Err("it contains tokens; shared profiles must not include secrets".to_string());
+    }
+    let skeletons: Vec<ProfileSkeleton> =
+        serde_json::from_value(json).map_err(|e| format!("unexpected profile shape: {}", e))?;
+    let mut seen = HashSet::new();
+    for skeleton in &skeletons {
+        let name = skeleton.name.trim();
+        if name.is_empty() {
+            return Err("a profile has an empty name".to_string());
+        }
+        if !seen.insert(name) {
+            return Err(format!("profile '{}' is listed twice", name));
+        }
+        // Only key-auth profiles need one, which `pull` can't tell without the secret
+        if !skeleton.email.is_empty() {
+            profile::validated_email(&skeleton.email).map_err(|e| format!("profile '{}': {}", name, e.to_string().trim_end_matches('.')))?;
+        }
+        if let Some(zone) = skeleton.zone.as_deref().filter(|z| !is_valid_hostname(z)) {
+            return Err(format!("profile '{}' has an invalid zone '{}'", name, zone));
+        }
+    }
+    Ok((skeletons, redacted))
+}
+
+/// Whether `zone` is a plausible DNS name: dot-separated labels of letters,
+/// digits and inner hyphens
+fn is_valid_hostname(zone: &str) -> bool {
+    let labels: Vec<&str> = zone.trim_end_matches('.').split('.').collect();
+    zone.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            (1..=63).contains(&label.len())
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        })
+}
+
+/// A zone as typed, reduced to the bare name flarectl and the API expect:
+/// no scheme, path, port or trailing dot, and lowercase. Errors when what's
+/// left isn't a hostname.
+fn normalize_zone(input: &str) -> Result<String, String> {
+    let trimmed = input.trim();
+    let rest = trimmed.split_once("://").map_or(trimmed, |(_, rest)| rest);
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default();
+    let zone = host.trim_end_matches('.').to_lowercase();
+    if !is_valid_hostname(&zone) {
+        return Err(format!("'{}' is not a valid zone name.", input));
+    }
+    Ok(zone)
+}
+
+/// `normalize_zone` for a zone given on the command line. A fixed-up zone
+/// gets a warning saying what was used, which --strict makes an error.
+fn zone_arg_or_exit(ctx: &Ctx, input: &str) -> String {
+    match normalize_zone(input) {
+        Ok(zone) if zone == input => zone,
+        Ok(zone) if ctx.strict => {
+            let message = format!("'{}' isn't a bare zone name; did you mean {}?", input, zone);
+            msg!("{} {}", "Error:".red().bold(), message);
+            ctx.fail("invalid", &message);
+        }
+        Ok(zone) => {
+            msg!("{} Using zone {} for '{}'", "Warning:".yellow().bold(), zone, input);
+            zone
+        }
+        Err(e) => {
+            msg!("{} {}", "Error:".red().bold(), e);
+            ctx.fail("invalid", &e);
+        }
+    }
+}
+
+/// Everything wrong with a config file, without touching the network
+fn config_problems(content: &str) -> Vec<String> {
+    let (config, unknown) = match parse_config(content) {
+        Ok(parsed) => parsed,
+        Err(e) => return vec![format!("invalid JSON or structure: {}", e)],
+    };
+    let mut problems: Vec<String> = unknown.iter().map(|field| format!("unknown field '{}'", field)).collect();
+
+    if let Ok(raw) = serde_json::from_str::<RawProfileKeys>(content) {
+        let mut seen = Vec::new();
+        for key in raw.profiles.0 {
+            if seen.contains(&key) {
+                problems.push(format!("profile '{}' is defined more than once", key));
+            } else {
+                seen.push(key);
+            }
+        }
+    }
+    if let Some(current) = &config.current {
+        if !config.profiles.contains_key(current) {
+            problems.push(format!("current profile '{}' does not exist", current));
+        }
+    }
+    for name in &config.order {
+        if !config.profiles.contains_key(name) {
+            problems.push(format!("order lists '{}', which is not a profile", name));
+        }
+    }
+    let mut aliases: Vec<(&String, &String)> = config.profiles.iter().flat_map(|(name, p)| p.aliases.iter().map(move |a| (a, name))).collect();
+    aliases.sort();
+    for (i, (alias, owner)) in aliases.iter().enumerate() {
+        if config.profiles.contains_key(*alias) {
+            problems.push(format!("profile '{}': alias '{}' is also a profile name", owner, alias));
+        } else if aliases[..i].iter().any(|(earlier, _)| earlier == alias) {
+            problems.push(format!("alias '{}' is given to more than one profile", alias));
+        }
+    }
+    for (repo, zone) in &config.ci_zones {
+        if !is_valid_hostname(zone) {
+            problems.push(format!("ci_zones: '{}' maps to '{}', which is not a valid hostname", repo, zone));
+        }
+    }
+
+    let mut names: Vec<&String> = config.profiles.keys().collect();
+    names.sort();
+    for name in names {
+        let profile = &config.profiles[name];
+        if let Some(zone) = profile.zone.as_deref().filter(|z| !is_valid_hostname(z)) {
+            problems.push(format!("profile '{}': zone '{}' is not a valid hostname", name, zone));
+        }
+        if profile.backend.is_plaintext() && profile.token.trim().is_empty() {
+            problems.push(format!("profile '{}' has no token", name));
+        }
+        if !profile.email.is_empty() && !profile.email.contains('@') {
+            problems.push(format!("profile '{}': email '{}' doesn't look like an email address", name, profile.email));
+        }
+        if profile.email.is_empty() && profile.auth_mode() == AuthMode::Key {
+            problems.push(format!("profile '{}' uses a global API key but has no email", name));
+        }
+    }
+    problems
+}
+
+/// Tells apart the temp files of writes made by the same process
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Create a new file readable only by the user (0600 on unix): everything
+/// cf-switch writes can hold credentials, and the umask default often lets
+/// anyone read it
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// Write via a temp file and rename, so readers never see a half-written
+/// file. The temp file is private from the start and uniquely named, so
+/// concurrent writers don't clobber each other's and a stale one is never reused.
+fn write_atomic(path: &Path, content: &str) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}-{}.tmp", std::process::id(), TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let tmp = PathBuf::from(tmp);
+    // io errors don't say which file; keep the kind so callers can still match on it
+    let with_path = |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+    create_private(&tmp).and_then(|mut file| file.write_all(content.as_bytes())).map_err(|e| {
+        fs::remove_file(&tmp).ok();
+        with_path(e)
+    })?;
+    fs::rename(&tmp, path).map_err(with_path).inspect_err(|_| {
+        fs::remove_file(&tmp).ok();
+    })
+}
+
+const CONFIG_UNWRITABLE_HINT: &str = "Point cf-switch at a writable config file with --config <path> or CF_SWITCH_CONFIG.";
+const ENV_UNWRITABLE_HINT: &str =
+    "Without a writable home directory, load a profile into the shell instead with: eval \"$(cf-switch use <name> --format env)\"";
+
+/// Follow a failed write with `hint` when it's the location that refuses
+/// writes (a read-only filesystem, as in immutable containers, or missing
+/// permissions) rather than something a retry could fix
+fn hint_if_unwritable(e: &io::Error, hint: &str) {
+    if matches!(e.kind(), io::ErrorKind::ReadOnlyFilesystem | io::ErrorKind::PermissionDenied) {
+        msg!("{}", hint);
+    }
+}
+
+/// `write_atomic`, unless `--no-write` is set, in which case just say what would happen
+fn write_output(ctx: &Ctx, path: &Path, content: &str) -> io::Result<()> {
+    if ctx.no_write {
+        msg!("{} would write {} ({} bytes)", "[no-write]".dimmed(), path.display(), content.len());
+        return Ok(());
+    }
+    write_atomic(path, content)
+}
+
+/// Where `add --local` saves: the project config in use, or a new one in the cwd
+fn local_config_for_add_or_exit(ctx: &Ctx) -> PathBuf {
+    if let Some(path) = &ctx.local_config_path {
+        return path.clone();
+    }
+    let path = std::env::current_dir().expect("Failed to read current directory").join(".cf-switch.json");
+    // Only reachable with --global/--config, or from $HOME where this is the global file
+    if path.exists() {
+        let message = format!("{} isn't being used as a project config here; not writing to it.", path.display());
+        msg!("{} {}", "Error:".red().bold(), message);
+        ctx.fail("invalid", &message);
+    }
+    path
+}
+
+/// Walk up from the current directory (stopping short of $HOME, whose
+/// `.cf-switch.json` is the global one) looking for a project config
+fn find_local_config() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    let home = dirs::home_dir();
+    cwd.ancestors()
+        .take_while(|dir| Some(*dir) != home.as_deref())
+        .map(|dir| dir.join(".cf-switch.json"))
+        .find(|path| path.is_file())
+}
+
+fn try_save_config(ctx: &Ctx, config: &Config) -> io::Result<()> {
+    if let Some(path) = &config.unparsed {
+        let message = format!("{} couldn't be parsed, and saving would overwrite it; fix it first", path.display());
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    let mut json = serde_json::to_value(config).expect("Failed to serialize config");
+    json["schema_version"] = SCHEMA_VERSION.into();
+    if let Some(local) = &config.local {
+        // Local profiles go back to the project file, and whatever they shadowed stays global
+        let global_profiles = json["profiles"].as_object_mut().expect("profiles is an object");
+        let mut local_profiles = serde_json::Map::new();
+        for name in &local.names {
+            if let Some(profile) = global_profiles.remove(name) {
+                local_profiles.insert(name.clone(), profile);
+            }
+            if let Some(shadowed) = local.shadowed.get(name) {
+                global_profiles.insert(name.clone(), serde_json::to_value(shadowed).expect("Failed to serialize profile"));
+            }
+        }
+        let content = serde_json::to_string_pretty(&serde_json::json!({ "profiles": local_profiles }))
+            .expect("Failed to serialize config");
+        write_output(ctx, &local.path, &content)?;
+    }
+    let content = serde_json::to_string_pretty(&json).expect("Failed to serialize config");
+    write_output(ctx, &ctx.config_path, &vault::seal(&content))
+}
+
+fn save_config(ctx: &Ctx, config: &Config) {
+    if let Err(e) = try_save_config(ctx, config) {
+        msg!("{} Failed to save the config: {}", "Error:".red().bold(), e);
+        hint_if_unwritable(&e, CONFIG_UNWRITABLE_HINT);
+        ctx.fail("write_failed", &format!("failed to save the config: {}", e));
+    }
+}
+
+/// One field `edit` changed, with tokens masked
+#[derive(Serialize)]
+struct FieldChange {
+    field: &'static str,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+/// How much of a token to print: `--reveal` or `--redact`, else the config's `redaction`
+fn redaction(ctx: &Ctx, config: &Config) -> mask::Redaction {
+    ctx.redaction.or(config.redaction).unwrap_or_default()
+}
+
+fn profile_changes(before: &Profile, after: &Profile, old_secret: &str, new_secret: &str, redaction: mask::Redaction) -> Vec<FieldChange> {
+    let tags = |p: &Profile| Some(p.tags.join(", ")).filter(|t| !t.is_empty());
+    let auth = |p: &Profile| p.auth.map(|a| a.to_string());
+    let secret = |s: &str| Some(mask::redact(s, redaction)).filter(|_| !s.is_empty());
+    let fields = [
+        ("email", Some(before.email.clone()), Some(after.email.clone())),
+        ("token", secret(old_secret), secret(new_secret)),
+        ("auth", auth(before), auth(after)),
+        ("zone", before.zone.clone(), after.zone.clone()),
+        ("description", before.description.clone(), after.description.clone()),
+        ("account_id", before.account_id.clone(), after.account_id.clone()),
+        ("tags", tags(before), tags(after)),
+        (
+            "allow_purge_everything",
+            Some(before.allows_purge_everything().to_string()),
+            Some(after.allows_purge_everything().to_string()),
+        ),
+    ];
+    let token_changed = old_secret != new_secret;
+    fields
+        .into_iter()
+        .filter(|(field, old, new)| if *field == "token" { token_changed } else { old != new })
+        .map(|(field, old, new)| FieldChange { field, old, new })
+        .collect()
+}
+
+fn parse_select_or_exit(path: &str) -> select::Query {
+    select::Query::parse(path).unwrap_or_else(|e| {
+        msg!("{} Invalid --select: {}", "Error:".red().bold(), e);
+        std::process::exit(1);
+    })
+}
+
+/// JSON output on stdout, narrowed by `--select` if given
+fn print_json(ctx: &Ctx, value: &serde_json::Value, select: Option<&select::Query>) {
+    let Some(query) = select else {
+        println!("{}", ctx.json_string(value, false));
+        return;
+    };
+    match query.select(value) {
+        Some(selected) => println!("{}", select::render(selected, ctx.pretty_json == Some(true))),
+        None => {
+            msg!("{} --select matched nothing.", "Error:".red().bold());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `current`'s exit status when no profile is active
+const CURRENT_NONE_EXIT: i32 = 1;
+/// `current`'s exit status when the config's `current` names a profile that
+/// no longer exists
+const CURRENT_MISSING_EXIT: i32 = 2;
+
+/// Names the profile the env file came from, for prompts and scripts
+const PROFILE_VAR: &str = "CF_SWITCH_PROFILE";
+
+/// Variables exported for a profile, in output order. Only the credentials
+/// for its auth mode are set, so tools that prefer an API key never pick up a
+/// scoped token as if it were a global key; fields the profile doesn't have
+/// are left out.
+fn env_vars(profile: &Profile, naming: &[EnvVarNames]) -> Vec<(String, String)> {
+    let mut vars: Vec<(String, String)> = Vec::new();
+    for names in naming {
+        let credentials = match profile.auth_mode() {
+            AuthMode::Token => vec![(&names.token, Some(&profile.token))],
+            AuthMode::Key => vec![(&names.email, Some(&profile.email)), (&names.key, Some(&profile.token))],
+        };
+        let fields = [(&names.account_id, profile.account_id.as_ref()), (&names.zone, profile.zone.as_ref())];
+        for (name, value) in credentials.into_iter().chain(fields) {
+            if let (Some(name), Some(value)) = (name, value) {
+                if !vars.iter().any(|(n, _)| n == name) {
+                    vars.push((name.clone(), value.clone()));
+                }
+            }
+        }
+    }
+    vars
+}
+
+/// Every variable the naming could export, for blanking the ones `env_vars` leaves out
+fn managed_env_vars(naming: &[EnvVarNames]) -> Vec<String> {
+    let mut all: Vec<String> = Vec::new();
+    for names in naming {
+        for name in [&names.email, &names.key, &names.token, &names.account_id, &names.zone].into_iter().flatten() {
+            if !all.contains(name) {
+                all.push(name.clone());
+            }
+        }
+    }
+    all
+}
+
+/// One variable assignment in the given shell's syntax, double-quoted
+fn export_line(shell: &str, key: &str, value: &str) -> String {
+    if shell == "fish" {
+        let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('$', "\\$");
+        format!("set -gx {} \"{}\"", key, escaped)
+    } else {
+        let escaped = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('$', "\\$")
+            .replace('`', "\\`");
+        format!("export {}=\"{}\"", key, escaped)
+    }
+}
+
+/// `KEY=value` with the value verbatim: docker's --env-file and Makefile
+/// `include` both take everything after `=` literally, so there's nothing to
+/// quote. A newline can't be represented at all.
+fn plain_assignment(key: &str, value: &str) -> String {
+    if value.contains(['\n', '\r']) {
+        msg!("{} {} contains a line break and can't be written as KEY=value.", "Error:".red().bold(), key);
+        std::process::exit(1);
+    }
+    format!("{}={}", key, value)
+}
+
+/// What each env file should contain for a profile, by path
+fn env_files(config: &Config, profile: &Profile, name: &str) -> Vec<(PathBuf, String)> {
+    config.env_formats().into_iter().map(|f| (f.path(), env_file_content(config, profile, name, f))).collect()
+}
+
+/// `--env-out`: a single env file at `path`, in the current shell's syntax
+fn env_file_at(config: &Config, profile: &Profile, name: &str, path: &Path) -> (PathBuf, String) {
+    let format = if detect_shell() == "fish" { EnvFormat::Fish } else { EnvFormat::Bash };
+    let path = std::env::current_dir().map_or_else(|_| path.to_path_buf(), |cwd| cwd.join(path));
+    (path, env_file_content(config, profile, name, format))
+}
+
+fn env_file_content(config: &Config, profile: &Profile, name: &str, format: EnvFormat) -> String {
+    let naming = config.env_var_names();
+    let set = env_vars(profile, &naming);
+    // Every managed variable in the same order whatever the auth mode, so the
+    // file diffs cleanly between profiles. The other auth mode's variables
+    // (and fields this profile lacks) are blanked, since an earlier switch may
+    // have left them in the shell (empty exports rather than unset, for fish).
+    let mut vars: Vec<(String, String)> = managed_env_vars(&naming)
+        .into_iter()
+        .map(|key| {
+            let value = set.iter().find(|(v, _)| *v == key).map_or_else(String::new, |(_, value)| value.clone());
+            (key, value)
+        })
+        .collect();
+    vars.push((PROFILE_VAR.to_string(), name.to_string()));
+    let comment = format!("Cloudflare credentials - profile: {}", name);
+    render_env_file(config, format, &comment, &vars)
+}
+
+/// First line of every shell env file, so other tools can tell who owns it
+const ENV_FILE_MARKER: &str = "# managed by cf-switch";
+
+/// `env_header`/`env_footer` as comment lines; lines already starting with
+/// `#` are kept as they are
+fn env_comment_block(text: Option<&str>) -> String {
+    let mut block = String::new();
+    for line in text.unwrap_or("").lines() {
+        let line = line.trim_end();
+        match line {
+            "" => block.push('#'),
+            _ if line.starts_with('#') => block.push_str(line),
+            _ => block.push_str(&format!("# {}", line)),
+        }
+        block.push('\n');
+    }
+    block
+}
+
+/// An env file in the format's syntax. Shell formats get the ownership
+/// marker, the configured header, a comment line, one assignment per
+/// variable and the footer; nushell can't source a file chosen at run time,
+/// so it gets a record for `open | load-env`.
+fn render_env_file(config: &Config, format: EnvFormat, comment: &str, vars: &[(String, String)]) -> String {
+    if format == EnvFormat::Nu {
+        let record: serde_json::Map<String, serde_json::Value> =
+            vars.iter().map(|(key, value)| (key.clone(), value.clone().into())).collect();
+        return format!("{}\n", serde_json::Value::Object(record));
+    }
+    let mut content = format!("{}\n", ENV_FILE_MARKER);
+    content.push_str(&env_comment_block(config.env_header.as_deref()));
+    content.push_str(&format!("# {}\n", comment));
+    for (key, value) in vars {
+        content.push_str(&export_line(format.shell(), key, value));
+        content.push('\n');
+    }
+    content.push_str(&env_comment_block(config.env_footer.as_deref()));
+    content
+}
+
+fn try_write_env_files(ctx: &Ctx, files: &[(PathBuf, String)]) -> io::Result<()> {
+    for (path, content) in files {
+        write_output(ctx, path, content)?;
+    }
+    Ok(())
+}
+
+/// FNV-1a of the content: stable across builds, unlike std's hasher, and
+/// only ever compared with itself
+fn checksum(content: &str) -> String {
+    let hash = content.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Env files whose content no longer matches what cf-switch last wrote,
+/// with what happened to them
+fn modified_env_files(config: &Config) -> Vec<(PathBuf, &'static str)> {
+    config
+        .env_formats()
+        .into_iter()
+        .map(|format| format.path())
+        .filter_map(|path| {
+            let expected = config.env_hash.get(&path.display().to_string())?;
+            match fs::read_to_string(&path) {
+                Ok(content) if checksum(&content) == *expected => None,
+                Ok(_) => Some((path, "was edited")),
+                Err(_) => Some((path, "was deleted")),
+            }
+        })
+        .collect()
+}
+
+/// Warn about env files edited behind cf-switch's back, saying either that
+/// they're about to be rewritten or that they may be stale
+fn warn_modified_env_files(config: &Config, rewriting: bool) {
+    let consequence = if rewriting { "rewriting it" } else { "it may not hold the active profile's credentials" };
+    for (path, what) in modified_env_files(config) {
+        msg!("{} {} {} since cf-switch last wrote it; {}.", "Warning:".yellow().bold(), path.display(), what, consequence);
+    }
+}
+
+/// Why this shell's environment doesn't reflect the active profile, if it
+/// doesn't: a variable the env file sets differs, or credentials are exported
+/// while no profile is active
+fn env_drift(ctx: &Ctx, config: &Config) -> Option<String> {
+    let naming = config.env_var_names();
+    let live = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    let Some(name) = &config.current else {
+        return managed_env_vars(&naming)
+            .into_iter()
+            .find(|var| live(var).is_some())
+            .map(|var| format!("{} is set, but no profile is active.", var));
+    };
+    let Some(profile) = config.profiles.get(name) else {
+        return Some(format!("The active profile '{}' no longer exists.", name));
+    };
+    let profile = with_secret_or_exit(ctx, name, profile);
+    env_vars(&profile, &naming)
+        .into_iter()
+        .find(|(var, value)| live(var).as_deref() != Some(value.as_str()))
+        .map(|(var, _)| format!("{} in this shell doesn't match profile '{}'.", var, name))
+}
+
+/// Env files with empty credentials, so sourcing them drops any previously
+/// exported ones. Uses `export` rather than `unset` so fish can source the
+/// bash file too.
+fn cleared_env_files(config: &Config) -> Vec<(PathBuf, String)> {
+    let vars: Vec<(String, String)> = managed_env_vars(&config.env_var_names())
+        .into_iter()
+        .chain([PROFILE_VAR.to_string()])
+        .map(|key| (key, String::new()))
+        .collect();
+    config
+        .env_formats()
+        .into_iter()
+        .map(|f| (f.path(), render_env_file(config, f, "Cloudflare credentials - no active profile", &vars)))
+        .collect()
+}
+
+/// The env file paths, comma-separated, for messages
+fn env_file_list(config: &Config) -> String {
+    config
+        .env_formats()
+        .iter()
+        .map(|f| f.path().display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn write_env_files(ctx: &Ctx, files: &[(PathBuf, String)]) {
+    if let Err(e) = try_write_env_files(ctx, files) {
+        msg!("{} Failed to write env file: {}", "Error:".red().bold(), e);
+        hint_if_unwritable(&e, ENV_UNWRITABLE_HINT);
+        ctx.fail("write_failed", &format!("failed to write env file: {}", e));
+    }
+}
+
+/// Parse `KEY=value` lines from a dotenv/shell env file. Handles `export `
+/// prefixes, single/double quotes, blank lines and `#` comments.
+fn parse_env_file(content: &str) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        let value = if let Some(inner) = value.strip_prefix('"').and_then(|v| v.rsplit_once('"')) {
+            inner.0.replace("\\\"", "\"").replace("\\\\", "\\")
+        } else if let Some(inner) = value.strip_prefix('\'').and_then(|v| v.rsplit_once('\'')) {
+            inner.0.to_string()
+        } else {
+            // Unquoted values end at an inline comment
+            value.split(" #").next().unwrap_or("").trim().to_string()
+        };
+        vars.insert(key.trim().to_string(), value);
+    }
+    vars
+}
+
+fn read_env_file_or_exit(ctx: &Ctx, path: &Path) -> HashMap<String, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => parse_env_file(&content),
+        Err(e) => {
+            let message = format!("Failed to read {}: {}", path.display(), e);
+            msg!("{} {}", "Error:".red().bold(), message);
+            ctx.fail("invalid", &message);
+        }
+    }
+}
+
+/// The secret in a parsed env file and the auth mode it implies: CF_API_TOKEN
+/// wins, otherwise CF_API_KEY is taken as a global key
+fn env_file_secret(vars: &HashMap<String, String>) -> Option<(String, AuthMode)> {
+    let value = |key: &str| vars.get(key).filter(|v| !v.is_empty()).cloned();
+    value("CF_API_TOKEN")
+        .map(|t| (t, AuthMode::Token))
+        .or_else(|| value("CF_API_KEY").map(|k| (k, AuthMode::Key)))
+}
+
+/// `name` under an import's `--prefix`, joined with `-` unless the prefix
+/// already ends in a separator like `-`, `_` or `.`
+fn prefixed_name(prefix: Option<&str>, name: &str) -> String {
+    match prefix.map(str::trim).filter(|p| !p.is_empty()) {
+        Some(prefix) if prefix.ends_with(|c: char| c.is_alphanumeric()) => format!("{}-{}", prefix, name),
+        Some(prefix) => format!("{}{}", prefix, name),
+        None => name.to_string(),
+    }
+}
+
+/// Pick the credentials for a flarectl-backed command: an explicit env file if
+/// given, then `--profile`, otherwise the current profile. Returns a label for
+/// messages too.
+fn resolve_credentials(ctx: &Ctx, config: &Config, env_file: Option<&Path>) -> (String, Profile) {
+    if env_file.is_some() && ctx.profile.is_some() {
+        msg!("{} --profile and --env-file can't be used together.", "Error:".red().bold());
+        std::process::exit(1);
+    }
+    if let Some(path) = env_file {
+        let vars = read_env_file_or_exit(ctx, path);
+        let Some((token, auth)) = env_file_secret(&vars) else {
+            msg!("{} {} does not set CF_API_TOKEN or CF_API_KEY.", "Error:".red().bold(), path.display());
+            std::process::exit(1);
+        };
+        mask::register(&token);
+        let profile = Profile {
+            email: vars.get("CF_API_EMAIL").cloned().unwrap_or_default(),
+            token,
+            auth: Some(auth),
+            ..Default::default()
+        };
+        return (format!("env file {}", path.display()), profile);
+    }
+
+    if let Some(requested) = &ctx.profile {
+        let Some(name) = match_profile_name(config, requested) else {
+            std::process::exit(1);
+        };
+        let Some(profile) = config.profiles.get(&name) else {
+            msg!("{} Profile '{}' not found.", "Error:".red().bold(), name);
+            std::process::exit(1);
+        };
+        audit::acting_as(Some(&name));
+        return (format!("profile '{}'", name.cyan()), with_secret_or_exit(ctx, &name, profile));
+    }
+
+    let Some(name) = &config.current else {
+        msg!("{}", "No profile currently active. Use 'cf-switch use <profile>' first.".yellow());
+        std::process::exit(1);
+    };
+    let Some(profile) = config.profiles.get(name) else {
+        msg!("{}", "Current profile no longer exists.".yellow());
+        std::process::exit(1);
+    };
+    audit::acting_as(Some(name));
+    (format!("profile '{}'", name.cyan()), with_secret_or_exit(ctx, name, profile))
+}
+
+/// The answer to a dialoguer prompt, exiting quietly if it was aborted
+fn prompt_or_exit<T>(ctx: &Ctx, answer: dialoguer::Result<T>) -> T {
+    answer.unwrap_or_else(|_| {
+        msg!("Cancelled.");
+        ctx.fail("cancelled", "cancelled at the prompt");
+    })
+}
+
+/// `--token-env`: the token from a named variable, as CI systems inject secrets
+fn token_from_env_or_exit(ctx: &Ctx, var: &str) -> String {
+    match std::env::var(var) {
+        Ok(token) if !token.trim().is_empty() => token,
+        Ok(_) => {
+            msg!("{} ${} is empty.", "Error:".red().bold(), var);
+            ctx.fail("invalid", &format!("${} is empty", var));
+        }
+        Err(_) => {
+            msg!("{} ${} is not set (or isn't valid UTF-8).", "Error:".red().bold(), var);
+            ctx.fail("invalid", &format!("${} is not set", var));
+        }
+    }
+}
+
+fn build_profile_or_exit(ctx: &Ctx, builder: ProfileBuilder) -> Profile {
+    builder.build().unwrap_or_else(|e| {
+        msg!("{} {}", "Error:".red().bold(), e);
+        ctx.fail("invalid", &e.to_string());
+    })
+}
+
+/// A copy of the profile with `token` filled in from its credential backend,
+/// and any 1Password reference resolved
+fn with_secret(name: &str, profile: &Profile) -> Result<Profile, credentials::Error> {
+    let mut resolved = profile.clone();
+    if let Some(store) = credentials::store(profile.backend) {
+        resolved.token = store.get(name)?;
+        mask::register(&resolved.token);
+    }
+    if credentials::is_op_reference(&resolved.token) {
+        resolved.token = credentials::read_op_reference(&resolved.token)?;
+        mask::register(&resolved.token);
+    }
+    Ok(resolved)
+}
+
+fn with_secret_or_exit(ctx: &Ctx, name: &str, profile: &Profile) -> Profile {
+    with_secret(name, profile).unwrap_or_else(|e| {
+        let message = format!("Could not load the token for '{}' ({} backend): {}", name, profile.backend, e);
+        msg!("{} {}", "Error:".red().bold(), message);
+        ctx.fail("backend", &message);
+    })
+}
+
+/// Run `command` with the profile's variables in its environment, in place
+/// of the ones it doesn't set, and exit with its status
+fn run_with_profile(ctx: &Ctx, config: &Config, name: &str, command: &[String]) -> ! {
+    let profile = with_secret_or_exit(ctx, name, &config.profiles[name]);
+    let naming = config.env_var_names();
+    let vars = env_vars(&profile, &naming);
+    let mut child = Command::new(&command[0]);
+    child.args(&command[1..]);
+    for var in managed_env_vars(&naming) {
+        if !vars.iter().any(|(name, _)| *name == var) {
+            child.env_remove(var);
+        }
+    }
+    child.envs(vars).env(PROFILE_VAR, name);
+    let status = child.status().unwrap_or_else(|e| {
+        msg!("{} Could not run {}: {}", "Error:".red().bold(), command[0], e);
+        std::process::exit(127);
+    });
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// flarectl arguments that purge everything cached for `zone`
+fn purge_everything_args(zone: &str) -> Vec<String> {
+    ["zone", "purge", "--zone", zone, "--everything"].map(str::to_string).to_vec()
+}
+
+/// flarectl arguments that create `record` in `zone`
+fn dns_create_args(zone: &str, record: &DnsRecord) -> Vec<String> {
+    let mut args = ["dns", "create", "--zone", zone, "--type", &record.kind, "--name", &record.name, "--content", &record.content]
+        .map(str::to_string)
+        .to_vec();
+    if record.proxied {
+        args.push("--proxy".to_string());
+    }
+    args
+}
+
+/// `--profile-stub`: print a flarectl invocation on stdout, quoted for a
+/// POSIX shell. The credentials come from the environment, so none appear.
+fn print_flarectl_plan(args: &[String]) {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c);
+    let words: Vec<String> = args
+        .iter()
+        .map(|arg| {
+            if !arg.is_empty() && arg.chars().all(plain) {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect();
+    println!("{}", mask::scrub(&format!("flarectl {}", words.join(" "))));
+}
+
+/// `--profile-stub` has no default zone to fall back on
+fn stub_zone_or_exit(zone: Option<String>, what: &str) -> String {
+    zone.unwrap_or_else(|| {
+        msg!("{} --profile-stub has no default zone; name the {} explicitly.", "Error:".red().bold(), what);
+        std::process::exit(1);
+    })
+}
+
+/// flarectl invocation authenticated with the given profile's credentials,
+/// clearing whatever the other auth mode would have read from the environment
+fn flarectl_command(profile: &Profile) -> Command {
+    // Fail closed: flarectl has no way to be told about a pin
+    if http::settings().pin.is_some() {
+        msg!("{} A TLS pin is set, which flarectl can't enforce; use --api, or unset tls_pin.", "Error:".red().bold());
+        std::process::exit(1);
+    }
+    if profile.auth_mode() == AuthMode::Token {
+        require_flarectl(flarectl::Feature::TokenAuth);
+    }
+    let mut command = Command::new("flarectl");
+    match profile.auth_mode() {
+        AuthMode::Token => command
+            .env("CF_API_TOKEN", &profile.token)
+            .env_remove("CF_API_EMAIL")
+            .env_remove("CF_API_KEY"),
+        AuthMode::Key => command
+            .env("CF_API_EMAIL", &profile.email)
+            .env("CF_API_KEY", &profile.token)
+            .env_remove("CF_API_TOKEN"),
+    };
+    // flarectl is a Go program, which reads these rather than curl's settings
+    let settings = http::settings();
+    if let Some(proxy) = &settings.proxy {
+        command.env("HTTPS_PROXY", proxy).env("HTTP_PROXY", proxy);
+    }
+    if let Some(ca_cert) = &settings.ca_cert {
+        command.env("SSL_CERT_FILE", ca_cert);
+    }
+    command
+}
+
+struct DnsRecord {
+    kind: String,
+    name: String,
+    content: String,
+    proxied: bool,
+}
+
+impl DnsRecord {
+    fn lamdera_apex() -> Self {
+        DnsRecord {
+            kind: "CNAME".to_string(),
+            name: "@".to_string(),
+            content: "apps.lamdera.app".to_string(),
+            proxied: true,
+        }
+    }
+
+    fn lamdera_www(domain: &str, target: WwwTarget) -> Self {
+        let content = match target {
+            WwwTarget::Apex => domain.to_string(),
+            WwwTarget::Lamdera => "apps.lamdera.app".to_string(),
+        };
+        DnsRecord { kind: "CNAME".to_string(), name: "www".to_string(), content, proxied: true }
+    }
+}
+
+enum DnsOutcome {
+    Created,
+    AlreadyExists,
+    /// flarectl's combined stderr/stdout
+    Failed(String),
+}
+
+impl DnsRecord {
+    /// The record's name as Cloudflare stores it: `@` is the apex, bare labels are relative to the zone
+    fn fqdn(&self, zone: &str) -> String {
+        let name = self.name.trim_end_matches('.');
+        if name == "@" || name.eq_ignore_ascii_case(zone) {
+            zone.to_string()
+        } else if name.to_lowercase().ends_with(&format!(".{}", zone.to_lowercase())) {
+            name.to_string()
+        } else {
+            format!("{}.{}", name, zone)
+        }
+    }
+}
+
+/// Whether the zone already has this record pointing at the same content.
+/// `resolved` saves looking the zone up again when the caller already has.
+fn dns_record_exists(profile: &Profile, zone: &str, resolved: Option<&api::Zone>, record: &DnsRecord) -> Result<bool, api::Error> {
+    let zone_id = match resolved {
+        Some(z) => z.id.clone(),
+        None => api::zone_id(&profile.token, zone)?,
+    };
+    let contents = api::dns_record_contents(&profile.token, &zone_id, &record.kind, &record.fqdn(zone))?;
+    let wanted = record.content.trim_end_matches('.');
+    Ok(contents.iter().any(|c| c.trim_end_matches('.').eq_ignore_ascii_case(wanted)))
+}
+
+/// Cloudflare's "record already exists" error codes (same host, same record, same settings)
+const DNS_RECORD_EXISTS_CODES: &[u64] = &[81053, 81057, 81058];
+
+fn create_dns_record(profile: &Profile, zone: &str, resolved: Option<&api::Zone>, record: &DnsRecord) -> DnsOutcome {
+    // Check first rather than relying on flarectl's error wording; if the
+    // lookup itself fails, fall through and let the create attempt report
+    if let Ok(true) = dns_record_exists(profile, zone, resolved, record) {
+        return DnsOutcome::AlreadyExists;
+    }
+    let entry = |ok: bool| {
+        let entry = audit::Entry::new("dns", ok).zone(zone);
+        match resolved {
+            Some(z) => entry.ids(&z.id, z.account_id.as_deref()),
+            None => entry,
+        }
+    };
+    if record.proxied {
+        require_flarectl(flarectl::Feature::ProxiedRecords);
+    }
+    let result = flarectl_output(flarectl_command(profile).args(dns_create_args(zone, record)));
+    let output = format!("{}{}", String::from_utf8_lossy(&result.stderr), String::from_utf8_lossy(&result.stdout));
+    let codes = diagnose::cloudflare_error_codes(&output);
+    let described = format!("{} {} -> {}", record.kind, record.name, record.content);
+    if result.status.success() && codes.is_empty() {
+        audit::record(entry(true).detail(&described));
+        return DnsOutcome::Created;
+    }
+    // Prefer the API's error code; the message text is only a fallback for
+    // flarectl versions that don't pass the response body through
+    if codes.iter().any(|c| DNS_RECORD_EXISTS_CODES.contains(c)) || (codes.is_empty() && output.contains("already exists")) {
+        DnsOutcome::AlreadyExists
+    } else {
+        audit::record(entry(false).detail(&format!("{}: {}", described, output.trim())));
+        DnsOutcome::Failed(output)
+    }
+}
+
+/// Split a records-file line on commas, keeping commas inside double quotes
+fn split_record_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+/// Parse a records file: one `type, name, content[, proxied]` per line, `#` comments
+fn parse_dns_records(content: &str) -> Result<Vec<DnsRecord>, String> {
+    let mut records = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = split_record_fields(line);
+        let proxied = match fields.get(3).map(|f| f.to_lowercase()).as_deref() {
+            None | Some("") | Some("false") | Some("no") | Some("0") | Some("dns-only") => false,
+            Some("true") | Some("yes") | Some("1") | Some("proxied") => true,
+            Some(other) => return Err(format!("line {}: invalid proxied value '{}'", i + 1, other)),
+        };
+        match fields.as_slice() {
+            [kind, name, content, ..] if fields.len() <= 4 && !kind.is_empty() && !name.is_empty() && !content.is_empty() => {
+                records.push(DnsRecord {
+                    kind: kind.to_uppercase(),
+                    name: name.clone(),
+                    content: content.clone(),
+                    proxied,
+                });
+            }
+            _ => return Err(format!("line {}: expected 'type, name, content[, proxied]'", i + 1)),
+        }
+    }
+    Ok(records)
+}
+
+/// Run flarectl to completion, killing it if it outlasts `--timeout`
+fn flarectl_output(command: &mut Command) -> std::process::Output {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|e| flarectl_missing(e));
+    // Drain the pipes as it runs so a chatty flarectl can't block on a full one
+    let mut out = child.stdout.take().expect("flarectl stdout is piped");
+    let mut err = child.stderr.take().expect("flarectl stderr is piped");
+    let stdout = thread::spawn(move || {
+        let mut buf = Vec::new();
+        out.read_to_end(&mut buf).ok();
+        buf
+    });
+    let stderr = thread::spawn(move || {
+        let mut buf = Vec::new();
+        err.read_to_end(&mut buf).ok();
+        buf
+    });
+    let timeout = http::timeout();
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() >= timeout => {
+                child.kill().ok();
+                child.wait().ok();
+                msg!("{} flarectl didn't finish within {}s; raise --timeout if that's expected.", "Error:".red().bold(), timeout.as_secs());
+                std::process::exit(1);
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(50)),
+            Err(e) => flarectl_missing(e),
+        }
+    };
+    std::process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    }
+}
+
+fn flarectl_missing(e: io::Error) -> ! {
+    msg!("{} Failed to run flarectl: {}", "Error:".red().bold(), e);
+    msg!("{}", ErrorKind::FlarectlMissing.explanation());
+    std::process::exit(1);
+}
+
+/// Stop before running a flarectl too old for `feature`, rather than let it
+/// fail on a flag or variable it doesn't know
+fn require_flarectl(feature: flarectl::Feature) {
+    if let Err(installed) = flarectl::check(feature) {
+        msg!("{} flarectl {} is too old for {}; it needs {} or newer.", "Error:".red().bold(), installed, feature.describe(), feature.since());
+        msg!("Upgrade it (brew upgrade flarectl, or go install github.com/cloudflare/cloudflare-go/cmd/flarectl@latest), or pass --api where the command has it.");
+        std::process::exit(1);
+    }
+}
+
+/// Follow a failure with its likely cause, plus what to do about it under `--explain`
+fn explain_failure(ctx: &Ctx, source: &str, kind: Option<ErrorKind>) {
+    let Some(kind) = kind else {
+        return;
+    };
+    msg!("{} {} (using {})", "!".yellow().bold(), kind.summary(), source);
+    if ctx.explain {
+        msg!("");
+        msg!("{}", kind.explanation());
+    } else {
+        msg!("Run again with --explain for what to do about it.");
+    }
+}
+
+/// Print to stdout (for shell commands to be eval'd). Left out while a
+/// `--json` result is due, so stdout holds only that.
+fn cmd(ctx: &Ctx, s: &str) {
+    if ctx.outcome.is_none() {
+        println!("{}", s);
+    }
+}
+
+fn detect_shell() -> String {
+    std::env::var("SHELL")
+        .unwrap_or_default()
+        .rsplit('/')
+        .next()
+        .unwrap_or("bash")
+        .to_string()
+}
+
+fn shell_or_detected(shell: Option<Shell>) -> Shell {
+    shell.unwrap_or_else(|| {
+        detect_shell().parse().unwrap_or_else(|_| {
+            msg!("{} Could not detect a supported shell, pass --shell.", "Error:".red().bold());
+            std::process::exit(1);
+        })
+    })
+}
+
+/// Conventional per-user location for a shell's completion script
+fn completion_install_path(shell: Shell) -> Option<PathBuf> {
+    let home = dirs::home_dir()?;
+    match shell {
+        Shell::Bash => Some(
+            dirs::data_dir()
+                .unwrap_or_else(|| home.join(".local/share"))
+                .join("bash-completion/completions/cf-switch"),
+        ),
+        Shell::Zsh => Some(home.join(".zsh/completions/_cf-switch")),
+        Shell::Fish => Some(home.join(".config/fish/completions/cf-switch.fish")),
+        _ => None,
+    }
+}
+
+/// Fish completions that ask `cf-switch __complete-profiles` for profile
+/// names each time, appended to clap's static script by `--dynamic`
+const FISH_DYNAMIC_COMPLETIONS: &str = r#"
+# Profile names, read from the live config on each completion
+complete -c cf-switch -n '__fish_seen_subcommand_from use remove edit clone' -f -a '(cf-switch __complete-profiles 2>/dev/null)'
+complete -c cf-switch -l profile -x -a '(cf-switch __complete-profiles 2>/dev/null)'
+# The cfs wrapper takes the same arguments
+complete -c cfs -w cf-switch
+"#;
+
+fn write_completions(shell: Shell, dynamic: bool, out: &mut dyn Write) {
+    clap_complete::generate(shell, &mut Cli::command(), "cf-switch", out);
+    if dynamic {
+        out.write_all(FISH_DYNAMIC_COMPLETIONS.as_bytes()).expect("Failed to write completions");
+    }
+}
+
+/// `--dynamic` is only written for fish, whose completions can run a command
+fn check_dynamic_completions(shell: Shell, dynamic: bool) {
+    if dynamic && shell != Shell::Fish {
+        msg!("{} --dynamic completions are only available for fish, not {}.", "Error:".red().bold(), shell);
+        std::process::exit(1);
+    }
+}
+
+/// Markers around the block `init` appends, so re-running it is a no-op
+const INIT_BEGIN: &str = "# >>> cf-switch >>>";
+const INIT_END: &str = "# <<< cf-switch <<<";
+
+fn rc_file_path(shell: Shell) -> Option<PathBuf> {
+    let home = dirs::home_dir()?;
+    match shell {
+        Shell::Bash => Some(home.join(".bashrc")),
+        Shell::Zsh => Some(std::env::var_os("ZDOTDIR").map(PathBuf::from).unwrap_or(home).join(".zshrc")),
+        Shell::Fish => Some(home.join(".config/fish/config.fish")),
+        _ => None,
+    }
+}
+
+const AUTO_HOOK_POSIX: &str = r#"# Auto-switch: runs `cfs use` when a directory's .cf-profile names a
+# different profile than the last one applied.
+_cfs_auto() {
+    [ "$PWD" = "$_CFS_AUTO_PWD" ] && return
+    _CFS_AUTO_PWD="$PWD"
+    [ -f .cf-profile ] || return
+    local p
+    p="$(head -n 1 .cf-profile | tr -d '[:space:]')"
+    [ -n "$p" ] && [ "$p" != "$_CFS_AUTO_PROFILE" ] || return
+    _CFS_AUTO_PROFILE="$p"
+    cfs use "$p"
+}"#;
+
+const AUTO_HOOK_BASH: &str = r#"case ";$PROMPT_COMMAND;" in
+    *";_cfs_auto;"*) ;;
+    *) PROMPT_COMMAND="_cfs_auto${PROMPT_COMMAND:+;$PROMPT_COMMAND}" ;;
+esac"#;
+
+const AUTO_HOOK_ZSH: &str = r#"autoload -Uz add-zsh-hook
+add-zsh-hook chpwd _cfs_auto
+_cfs_auto"#;
+
+const AUTO_HOOK_FISH: &str = r#"# Auto-switch: runs `cfs use` when a directory's .cf-profile names a
+# different profile than the last one applied.
+function _cfs_auto --on-variable PWD
+    test -f .cf-profile; or return
+    set -l p (head -n 1 .cf-profile | string trim)
+    test -n "$p"; and test "$p" != "$_cfs_auto_profile"; or return
+    set -g _cfs_auto_profile $p
+    cfs use $p
+end
+_cfs_auto"#;
+
+const HOOK_NU: &str = r#"# ~/.config/nushell/config.nu
+# Add "nu" to env_formats in the cf-switch config, so switches also write
+# ~/.cloudflare.nuon for this wrapper to load.
+def --env cfs [...args: string] {
+    for line in (^cf-switch ...$args | lines) {
+        if ($line | str starts-with 'source ') {
+            let path = ($line | str substring 7.. | str trim --char '"')
+            if ($path | str ends-with '.nuon') {
+                open $path | load-env
+            } else {
+                print --stderr $"cfs: add \"nu\" to env_formats in the cf-switch config to load credentials here"
+            }
+        } else {
+            print $line
+        }
+    }
+}"#;
+
+const AUTO_HOOK_NU: &str = r#"# Auto-switch: runs `cfs use` when a directory's .cf-profile names a
+# different profile than the last one applied.
+$env.config.hooks.env_change.PWD = ($env.config.hooks.env_change.PWD? | default [] | append {|_, dir|
+    let file = ($dir | path join '.cf-profile')
+    if ($file | path exists) {
+        let p = (open --raw $file | lines | get 0? | default '' | str trim)
+        if ($p | is-not-empty) and $p != ($env._CFS_AUTO_PROFILE? | default '') {
+            $env._CFS_AUTO_PROFILE = $p
+            cfs use $p
+        }
+    }
+})"#;
+
+/// Shell config snippet defining the `cfs` wrapper (and optionally the
+/// directory auto-switch hook) for the given shell name.
+fn hook_script(shell: &str, eval_style: Option<EvalStyle>, auto: bool) -> String {
+    // nushell has neither eval nor run-time source, so its wrapper loads the env file itself
+    if shell == "nu" {
+        return if auto { format!("{}\n\n{}", HOOK_NU, AUTO_HOOK_NU) } else { HOOK_NU.to_string() };
+    }
+    let is_fish = shell == "fish";
+    let style = eval_style.unwrap_or(if is_fish { EvalStyle::Source } else { EvalStyle::Eval });
+    let mut lines = vec![if is_fish { "# ~/.config/fish/config.fish" } else { "# ~/.bashrc or ~/.zshrc" }];
+    match style {
+        EvalStyle::Eval => {
+            lines.push("# eval: captures the whole output first, then evaluates it as one string.");
+            lines.push("# Works in any POSIX shell; output has to survive a round-trip through a string.");
+        }
+        EvalStyle::Source => {
+            lines.push("# source: reads cf-switch output as a script without an intermediate string.");
+            lines.push("# Avoids eval quoting pitfalls; in bash/zsh it relies on process substitution.");
+        }
+    }
+    match (is_fish, style) {
+        (true, EvalStyle::Eval) => lines.extend(["function cfs", "    eval (cf-switch $argv | string collect)", "end"]),
+        (true, EvalStyle::Source) => lines.extend(["function cfs", "    cf-switch $argv | source", "end"]),
+        (false, EvalStyle::Eval) => lines.push("cfs() { eval \"$(cf-switch \"$@\")\"; }"),
+        (false, EvalStyle::Source) => lines.push("cfs() { source <(cf-switch \"$@\"); }"),
+    }
+    if auto {
+        lines.push("");
+        match shell {
+            "fish" => lines.push(AUTO_HOOK_FISH),
+            "zsh" => lines.extend([AUTO_HOOK_POSIX, AUTO_HOOK_ZSH]),
+            _ => lines.extend([AUTO_HOOK_POSIX, AUTO_HOOK_BASH]),
+        }
+    }
+    lines.join("\n")
+}
+
+/// Emit the source line for the env file matching the current shell, falling
+/// back to the bash one. The path is double-quoted so the same line works
+/// under both `eval` and `source` in bash, zsh and fish.
+fn output_source_command(ctx: &Ctx, config: &Config) {
+    emit_source(ctx, &source_line(config));
+}
+
+/// Print a `source` line for the shell wrapper to run. Straight to a
+/// terminal nobody will run it, so explain instead of printing it bare.
+fn emit_source(ctx: &Ctx, line: &str) {
+    if io::stdout().is_terminal() && ctx.outcome.is_none() {
+        msg!("{} This shell wasn't updated: run {} or set up the cfs wrapper (cf-switch init)", "!".yellow().bold(), line.bold());
+        return;
+    }
+    cmd(ctx, line);
+}
+
+/// `source "<env file>"` for the env file matching the current shell
+fn source_line(config: &Config) -> String {
+    format!("source \"{}\"", env_file_for_shell(config, &detect_shell()).display())
+}
+
+/// The env file `shell` should source: the configured format for it, or the first one
+fn env_file_for_shell(config: &Config, shell: &str) -> PathBuf {
+    let formats = config.env_formats();
+    let format = formats.iter().find(|f| f.shell() == shell).unwrap_or(&formats[0]);
+    format.path()
+}
+
+/// Match a zone against a protected pattern. `*` matches any run of characters;
+/// a pattern without `*` matches anywhere in the zone name.
+fn zone_matches_pattern(zone: &str, pattern: &str) -> bool {
+    let zone = zone.to_lowercase();
+    let pattern = pattern.to_lowercase();
+    if !pattern.contains('*') {
+        return zone.contains(&pattern);
+    }
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let mut rest = zone.as_str();
+    for (i, part) in parts.iter().enumerate() {
+        if part.is_empty() {
+            continue;
+        }
+        if i == 0 {
+            match rest.strip_prefix(part) {
+                Some(r) => rest = r,
+                None => return false,
+            }
+        } else if i == parts.len() - 1 {
+            return rest.ends_with(part);
+        } else {
+            match rest.find(part) {
+                Some(pos) => rest = &rest[pos + part.len()..],
+                None => return false,
+            }
+        }
+    }
+    true
+}
+
+fn protected_pattern_for<'a>(config: &'a Config, zone: &str) -> Option<&'a str> {
+    config
+        .protected_patterns
+        .iter()
+        .find(|p| zone_matches_pattern(zone, p))
+        .map(|p| p.as_str())
+}
+
+/// The parts of a Workers project's wrangler.toml that identify an account
+struct WranglerConfig {
+    account_id: Option<String>,
+    /// `zone_name` of `route` and every `[[routes]]` entry, in file order
+    zones: Vec<String>,
+}
+
+impl WranglerConfig {
+    fn parse(content: &str) -> Option<Self> {
+        let toml: toml::Table = content.parse().ok()?;
+        let zone_name = |route: &toml::Value| route.get("zone_name")?.as_str().map(str::to_string);
+        let mut zones: Vec<String> = toml.get("route").and_then(zone_name).into_iter().collect();
+        if let Some(routes) = toml.get("routes").and_then(toml::Value::as_array) {
+            zones.extend(routes.iter().filter_map(zone_name));
+        }
+        Some(WranglerConfig {
+            account_id: toml.get("account_id").and_then(toml::Value::as_str).map(str::to_string),
+            zones,
+        })
+    }
+}
+
+/// Resolve `use --from-wrangler`: the profile with the nearest wrangler.toml's
+/// account_id, narrowed (or, without an account_id match, found) by its zones
+fn profile_from_wrangler_or_exit(ctx: &Ctx, config: &Config) -> String {
+    let cwd = std::env::current_dir().expect("Could not read the current directory");
+    let Some(path) = cwd.ancestors().map(|d| d.join("wrangler.toml")).find(|p| p.is_file()) else {
+        msg!("{} No wrangler.toml found above the current directory.", "Error:".red().bold());
+        std::process::exit(1);
+    };
+    let Some(wrangler) = fs::read_to_string(&path).ok().and_then(|c| WranglerConfig::parse(&c)) else {
+        msg!("{} Could not parse {}", "Error:".red().bold(), path.display());
+        std::process::exit(1);
+    };
+
+    let in_zones = |p: &Profile| p.zone.as_ref().is_some_and(|z| wrangler.zones.iter().any(|w| w.eq_ignore_ascii_case(z)));
+    let mut candidates: Vec<&String> = match &wrangler.account_id {
+        Some(id) => config.profiles.iter().filter(|(_, p)| p.account_id.as_ref() == Some(id)).map(|(n, _)| n).collect(),
+        None => Vec::new(),
+    };
+    if candidates.len() > 1 && candidates.iter().any(|n| in_zones(&config.profiles[*n])) {
+        candidates.retain(|n| in_zones(&config.profiles[*n]));
+    }
+    if candidates.is_empty() {
+        candidates = config.profiles.iter().filter(|(_, p)| in_zones(p)).map(|(n, _)| n).collect();
+    }
+    candidates.sort();
+
+    match candidates.as_slice() {
+        [only] => {
+            msg!("{} Using profile {} for {}", ctx.symbols.arrow.cyan(), only.cyan(), path.display());
+            (*only).clone()
+        }
+        [] => {
+            let what = match (&wrangler.account_id, wrangler.zones.first()) {
+                (Some(id), _) => format!("account {}", id),
+                (None, Some(zone)) => format!("zone {}", zone),
+                (None, None) => {
+                    msg!("{} {} has no account_id or route zone_name to match on.", "Error:".red().bold(), path.display());
+                    std::process::exit(1);
+                }
+            };
+            msg!("{} No profile matches {} from {}.", "Error:".red().bold(), what, path.display());
+            if let Some(id) = &wrangler.account_id {
+                msg!("Add one with: cf-switch add <name> -e <email> -t <token> --account-id {}", id);
+            } else {
+                msg!("Add one with: cf-switch add <name> -e <email> -t <token> -z <zone>");
+            }
+            std::process::exit(1);
+        }
+        _ => {
+            msg!("{} Several profiles match {}:", "Error:".red().bold(), path.display());
+            for name in &candidates {
+                msg!("  {}", name);
+            }
+            msg!("Pick one with: cf-switch use <name>");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The nearest repository's git config, following the `.git` file that
+/// worktrees and submodules have instead of a directory
+fn find_git_config() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    let git = cwd.ancestors().map(|d| d.join(".git")).find(|p| p.exists())?;
+    let dir = if git.is_file() {
+        let content = fs::read_to_string(&git).ok()?;
+        let target = content.trim().strip_prefix("gitdir:")?.trim();
+        git.parent()?.join(target)
+    } else {
+        git
+    };
+    // A worktree's own directory points at the shared one holding the config
+    let common = fs::read_to_string(dir.join("commondir")).ok().map(|c| dir.join(c.trim()));
+    Some(common.unwrap_or(dir).join("config"))
+}
+
+/// A remote's `url` from git config text
+fn git_remote_url(git_config: &str, remote: &str) -> Option<String> {
+    let header = format!("[remote \"{}\"]", remote);
+    let mut in_section = false;
+    for line in git_config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_section = line == header;
+        } else if let Some((key, value)) = line.split_once('=').filter(|_| in_section) {
+            if key.trim() == "url" {
+                return Some(value.trim().trim_matches('"').to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Owner and repo of a remote URL, lowercased and without `.git`, for
+/// https://host/owner/repo.git, git@host:owner/repo and ssh://git@host/owner/repo
+fn git_remote_segments(url: &str) -> Vec<String> {
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.split_once('/').map_or("", |(_, path)| path),
+        None => url.split_once(':').map_or(url, |(_, path)| path),
+    };
+    path.split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.trim_end_matches(".git").to_lowercase())
+        .collect()
+}
+
+/// Resolve `use --from-git`: the profile whose zone is named by the origin
+/// remote (a repo called `example.com` or `www.example.com`), or failing
+/// that, looks like it (a repo called `example` or `example-com`)
+fn profile_from_git_or_exit(ctx: &Ctx, config: &Config) -> String {
+    let Some(path) = find_git_config() else {
+        msg!("{} Not inside a git repository.", "Error:".red().bold());
+        std::process::exit(1);
+    };
+    let Some(url) = fs::read_to_string(&path).ok().and_then(|c| git_remote_url(&c, "origin")) else {
+        msg!("{} No origin remote in {}", "Error:".red().bold(), path.display());
+        std::process::exit(1);
+    };
+    let segments = git_remote_segments(&url);
+
+    let zone_of = |p: &Profile| p.zone.as_ref().map(|z| z.trim_end_matches('.').to_lowercase());
+    let names_zone = |zone: &str| segments.iter().any(|s| s == zone || s.ends_with(&format!(".{}", zone)));
+    let resembles_zone = |zone: &str| {
+        segments.iter().any(|s| Some(s.as_str()) == zone.split('.').next() || *s == zone.replace('.', "-"))
+    };
+    let matching = |test: &dyn Fn(&str) -> bool| -> Vec<&String> {
+        let mut names: Vec<&String> = config
+            .profiles
+            .iter()
+            .filter(|(_, p)| zone_of(p).is_some_and(|z| test(&z)))
+            .map(|(n, _)| n)
+            .collect();
+        names.sort();
+        names
+    };
+    let mut candidates = matching(&names_zone);
+    if candidates.is_empty() {
+        candidates = matching(&resembles_zone);
+    }
+
+    match candidates.as_slice() {
+        [only] => {
+            msg!("{} Using profile {} for remote {}", ctx.symbols.arrow.cyan(), only.cyan(), url);
+            (*only).clone()
+        }
+        [] => {
+            msg!("{} No profile's zone matches the origin remote {}", "Error:".red().bold(), url);
+            msg!("Give the profile a zone with: cf-switch edit <name> -z <zone>");
+            std::process::exit(1);
+        }
+        _ => {
+            msg!("{} Several profiles match the origin remote {}:", "Error:".red().bold(), url);
+            for name in &candidates {
+                msg!("  {} (zone {})", name, config.profiles[*name].zone.as_deref().unwrap_or_default());
+            }
+            msg!("Pick one with: cf-switch use <name>");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Look for a zone in a single project file, based on its file name
+fn zone_from_project_file(path: &Path) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let zone = match path.file_name()?.to_str()? {
+        ".cf-zone" => content.lines().map(str::trim).find(|l| !l.is_empty() && !l.starts_with('#'))?.to_string(),
+        "wrangler.toml" => WranglerConfig::parse(&content)?.zones.into_iter().next()?,
+        "package.json" => {
+            let json: serde_json::Value = serde_json::from_str(&content).ok()?;
+            json.get("cloudflare")?.get("zone")?.as_str()?.to_string()
+        }
+        _ => return None,
+    };
+    (!zone.is_empty()).then_some(zone)
+}
+
+/// Walk up from the current directory and return the first project file that names a zone
+fn find_zone_from_cwd() -> Option<(PathBuf, String)> {
+    let cwd = std::env::current_dir().ok()?;
+    for dir in cwd.ancestors() {
+        for file in [".cf-zone", "wrangler.toml", "package.json"] {
+            let path = dir.join(file);
+            if let Some(zone) = zone_from_project_file(&path) {
+                return Some((path, zone));
+            }
+        }
+    }
+    None
+}
+
+/// Resolve `--zone-from-cwd`, exiting if no project file names a zone
+fn zone_from_cwd_or_exit(ctx: &Ctx) -> String {
+    match find_zone_from_cwd() {
+        Some((path, zone)) => {
+            msg!("{} Using zone {} from {}", ctx.symbols.arrow.cyan(), zone.bold(), path.display());
+            zone
+        }
+        None => {
+            msg!("{} No .cf-zone, wrangler.toml zone_name or package.json cloudflare.zone found above the current directory.", "Error:".red().bold());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// A file's modification time as Unix seconds
+fn modified_secs(path: &Path) -> io::Result<u64> {
+    let modified = fs::metadata(path)?.modified()?;
+    Ok(modified.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Seconds left before `zone` may be purged again, if it's still cooling down
+fn purge_cooldown_remaining(config: &Config, zone: &str) -> Option<u64> {
+    let cooldown = config.purge_cooldown_secs.unwrap_or(DEFAULT_PURGE_COOLDOWN_SECS);
+    let last = *config.last_purge.get(zone)?;
+    let elapsed = now_secs().saturating_sub(last);
+    (elapsed < cooldown).then(|| cooldown - elapsed)
+}
+
+/// How a purge reaches Cloudflare
+#[derive(Clone, Copy)]
+enum PurgeRoute<'a> {
+    Flarectl,
+    /// The API, with the zone's id when it was given rather than looked up
+    Api(Option<&'a str>),
+}
+
+/// Purge everything on `zone` and record it for the cooldown. Returns false on failure.
+fn purge_zone(ctx: &Ctx, config: &mut Config, source: &str, profile: &Profile, zone: &str, route: PurgeRoute, target: &api::PurgeTarget) -> bool {
+    msg!("{} Purging {} for {} using {}...", ctx.symbols.arrow.cyan(), target, zone.bold(), source);
+    ctx.event("purge_start", serde_json::json!({ "zone": zone, "target": target.to_string() }));
+    let ok = match route {
+        PurgeRoute::Api(zone_id) => {
+            let max_retries = config.purge_max_retries.unwrap_or(DEFAULT_PURGE_MAX_RETRIES);
+            let purged = purge_via_api(ctx, profile, zone, zone_id, target, max_retries, &mut |line| {
+                msg!("{}", line);
+            });
+            finish_api_purge(ctx, config, source, zone, target, purged)
+        }
+        PurgeRoute::Flarectl => {
+            let result = flarectl_output(flarectl_command(profile).args(purge_everything_args(zone)));
+            if result.status.success() {
+                record_purge(ctx, config, zone, target, None);
+                true
+            } else {
+                let stderr = String::from_utf8_lossy(&result.stderr);
+                msg!("{} Failed to purge: {}", "Error:".red().bold(), stderr);
+                let kind = ErrorKind::classify(&stderr);
+                if kind == Some(ErrorKind::ZoneNotFound) {
+                    suggest_zone(config, zone);
+                }
+                explain_failure(ctx, source, kind);
+                audit::record(audit::Entry::new("purge", false).zone(zone).detail(&stderr));
+                false
+            }
+        }
+    };
+    ctx.event("purge_done", serde_json::json!({ "zone": zone, "ok": ok }));
+    ok
+}
+
+/// `--require-zone` (or the config's `require_zone`): exit rather than fall
+/// back to the profile's default zone when no zone was `given`
+fn require_zone_or_exit(config: &Config, flag: bool, given: bool, usage: &str) {
+    if given || !(flag || config.require_zone) {
+        return;
+    }
+    let why = if flag { "--require-zone" } else { "require_zone in the config" };
+    msg!("{} No zone given, and {} stops the profile's default zone being used.", "Error:".red().bold(), why);
+    msg!("Usage: {}", usage);
+    std::process::exit(1);
+}
+
+/// `--output json`: a zone that purge left alone, and why
+fn emit_purge_skipped(ctx: &Ctx, zone: &str, reason: &str) {
+    ctx.event("purge_skipped", serde_json::json!({ "zone": zone, "reason": reason }));
+}
+
+/// The zone and account ids an API operation acted on
+fn print_resolved_ids(zone: &api::Zone) {
+    let account = zone.account_id.as_deref().map(|id| format!(", account id {}", id)).unwrap_or_default();
+    msg!("  {}", format!("zone id {}{}", zone.id, account).dimmed());
+}
+
+/// Report a successful purge and start its cooldown. `resolved` is the zone
+/// the API looked up, whose ids are shown for matching against the dashboard.
+fn record_purge(ctx: &Ctx, config: &mut Config, zone: &str, target: &api::PurgeTarget, resolved: Option<&api::Zone>) {
+    let narrowed = match target {
+        api::PurgeTarget::Everything => String::new(),
+        _ => format!(" ({})", target),
+    };
+    msg!("{} Cache purged for {}{}", ctx.symbols.ok.green(), zone.bold(), narrowed);
+    let mut entry = audit::Entry::new("purge", true).zone(zone).detail(&target.to_string());
+    if let Some(resolved) = resolved {
+        print_resolved_ids(resolved);
+        entry = entry.ids(&resolved.id, resolved.account_id.as_deref());
+    }
+    audit::record(entry);
+    let now = now_secs();
+    config.last_purge.insert(zone.to_string(), now);
+    // Save into the config as it is on disk now rather than the copy loaded
+    // at the start: a long `--watch` or `--parallel` purge would otherwise
+    // undo any use, add or edit run in the meantime
+    let mut on_disk = read_config_file(ctx, &ctx.config_path);
+    on_disk.last_purge.insert(zone.to_string(), now);
+    save_config(ctx, &on_disk);
+}
+
+/// Look up the zone (unless its id is given) and purge the target on it,
+/// retrying when rate limited. Lists too long for one request go in batches,
+/// one after another, and a failed batch doesn't stop the rest; the outer
+/// error is the zone lookup. Progress lines go to `report` so parallel
+/// purges can buffer them.
+fn purge_via_api(
+    ctx: &Ctx,
+    profile: &Profile,
+    zone: &str,
+    zone_id: Option<&str>,
+    target: &api::PurgeTarget,
+    max_retries: u32,
+    report: &mut dyn FnMut(String),
+) -> Result<(api::Zone, Vec<Result<(), api::Error>>), api::Error> {
+    let resolved = match zone_id {
+        Some(id) => api::Zone::from_id(id, zone),
+        None => with_rate_limit_retries(max_retries, &mut *report, || api::zone(&profile.token, zone, api::RateLimited::Return))?,
+    };
+    let id = resolved.id.clone();
+    let batches = target.batches();
+    let total = batches.len();
+    let results = batches
+        .iter()
+        .enumerate()
+        .map(|(i, batch)| {
+            let purged = with_rate_limit_retries(max_retries, &mut *report, || api::purge(&profile.token, &id, batch));
+            if total > 1 {
+                match &purged {
+                    Ok(()) => report(format!("  {} batch {}/{} ({})", ctx.symbols.ok.green(), i + 1, total, batch)),
+                    Err(e) => report(format!("  {} batch {}/{}: {}", ctx.symbols.fail.red(), i + 1, total, e)),
+                }
+            }
+            purged
+        })
+        .collect();
+    Ok((resolved, results))
+}
+
+/// Report how an API purge went, returning whether every batch worked. A
+/// partly applied purge counts as failed and doesn't start the cooldown, so
+/// it can be run again straight away.
+fn finish_api_purge(
+    ctx: &Ctx,
+    config: &mut Config,
+    source: &str,
+    zone: &str,
+    target: &api::PurgeTarget,
+    purged: Result<(api::Zone, Vec<Result<(), api::Error>>), api::Error>,
+) -> bool {
+    let (resolved, batches) = match purged {
+        Ok(purged) => purged,
+        Err(e) => return report_purge_failure(ctx, config, source, zone, &e),
+    };
+    let failed = batches.iter().filter(|b| b.is_err()).count();
+    if failed == 0 {
+        if batches.len() > 1 {
+            msg!("Sent in {} batches of up to {}", batches.len(), api::PURGE_BATCH_LIMIT);
+        }
+        record_purge(ctx, config, zone, target, Some(&resolved));
+        return true;
+    }
+    if failed == batches.len() {
+        let Some(Err(e)) = batches.into_iter().next() else {
+            unreachable!("every batch failed");
+        };
+        return report_purge_failure(ctx, config, source, zone, &e);
+    }
+    let detail = format!("{} of {} batches failed ({})", failed, batches.len(), target);
+    msg!("{} Partly purged {}: {}; run it again to retry.", "Error:".red().bold(), zone.bold(), detail);
+    print_resolved_ids(&resolved);
+    audit::record(audit::Entry::new("purge", false).zone(zone).ids(&resolved.id, resolved.account_id.as_deref()).detail(&detail));
+    false
+}
+
+fn report_purge_failure(ctx: &Ctx, config: &Config, source: &str, zone: &str, e: &api::Error) -> bool {
+    msg!("{} Failed to purge: {}", "Error:".red().bold(), e);
+    let kind = ErrorKind::from_api(e);
+    if kind == Some(ErrorKind::ZoneNotFound) {
+        suggest_zone(config, zone);
+    }
+    explain_failure(ctx, source, kind);
+    audit::record(audit::Entry::new("purge", false).zone(zone).detail(&e.to_string()));
+    false
+}
+
+/// `--files` values with each `-` replaced by the URLs piped on stdin (one
+/// per line, blank lines skipped), duplicates dropped in order
+fn purge_files_or_exit(files: Vec<String>) -> Vec<String> {
+    let mut urls = Vec::new();
+    for file in files {
+        if file == "-" {
+            let mut piped = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut piped) {
+                msg!("{} Could not read URLs from stdin: {}", "Error:".red().bold(), e);
+                std::process::exit(1);
+            }
+            urls.extend(piped.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string));
+        } else {
+            urls.push(file.trim().to_string());
+        }
+    }
+    let mut seen = HashSet::new();
+    urls.retain(|url| seen.insert(url.clone()));
+    if urls.is_empty() {
+        msg!("{} --files got no URLs to purge.", "Error:".red().bold());
+        std::process::exit(1);
+    }
+    urls
+}
+
+/// Most sitemaps `purge --sitemap` follows a sitemap index into
+const SITEMAP_INDEX_LIMIT: usize = 50;
+
+/// A sitemap's text, from an http(s) URL or a local file
+fn read_sitemap_or_exit(location: &str) -> String {
+    if !location.starts_with("http://") && !location.starts_with("https://") {
+        return fs::read_to_string(location).unwrap_or_else(|e| {
+            msg!("{} Failed to read {}: {}", "Error:".red().bold(), location, e);
+            std::process::exit(1);
+        });
+    }
+    let response = http::Request::get(location).timeout(Duration::from_secs(30)).send().unwrap_or_else(|e| {
+        msg!("{} Failed to fetch {}: {}", "Error:".red().bold(), location, e);
+        std::process::exit(1);
+    });
+    if !(200..300).contains(&response.status) {
+        msg!("{} {} answered HTTP {}", "Error:".red().bold(), location, response.status);
+        std::process::exit(1);
+    }
+    response.body
+}
+
+fn parse_sitemap_or_exit(location: &str) -> sitemap::Sitemap {
+    sitemap::parse(&read_sitemap_or_exit(location)).unwrap_or_else(|e| {
+        msg!("{} {} isn't a sitemap: {}", "Error:".red().bold(), location, e);
+        std::process::exit(1);
+    })
+}
+
+/// `--sitemap`: the page URLs it lists that are in `zone`, deduplicated. A
+/// sitemap index is followed one level down.
+fn sitemap_files_or_exit(ctx: &Ctx, location: &str, zone: &str) -> Vec<String> {
+    msg!("{} Reading sitemap {}...", ctx.symbols.arrow.cyan(), location);
+    let urls = match parse_sitemap_or_exit(location) {
+        sitemap::Sitemap::Urls(urls) => urls,
+        sitemap::Sitemap::Index(sitemaps) => {
+            if sitemaps.len() > SITEMAP_INDEX_LIMIT {
+                msg!("{} The sitemap index lists {} sitemaps; --sitemap follows at most {}.", "Error:".red().bold(), sitemaps.len(), SITEMAP_INDEX_LIMIT);
+                std::process::exit(1);
+            }
+            msg!("  sitemap index with {} sitemap(s)", sitemaps.len());
+            let mut urls = Vec::new();
+            for child in &sitemaps {
+                match parse_sitemap_or_exit(child) {
+                    sitemap::Sitemap::Urls(found) => urls.extend(found),
+                    sitemap::Sitemap::Index(_) => {
+                        msg!("{} {} is another sitemap index; only one level is followed.", "Error:".red().bold(), child);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            urls
+        }
+    };
+    let mut seen = HashSet::new();
+    let found: Vec<String> = urls.into_iter().filter(|url| seen.insert(url.clone())).collect();
+    let (in_zone, elsewhere): (Vec<String>, Vec<String>) =
+        found.into_iter().partition(|url| url_host(url).is_some_and(|host| host_in_zone(host, zone)));
+    msg!("  {} URL(s) found, {} in zone {}", in_zone.len() + elsewhere.len(), in_zone.len(), zone.bold());
+    if !elsewhere.is_empty() {
+        msg!("{}", format!("  skipping {} URL(s) on other hosts, e.g. {}", elsewhere.len(), elsewhere[0]).dimmed());
+    }
+    if in_zone.is_empty() {
+        msg!("{} The sitemap has no URLs in zone {}.", "Error:".red().bold(), zone);
+        std::process::exit(1);
+    }
+    in_zone
+}
+
+/// The hostname of an http(s) URL, without any port
+fn url_host(url: &str) -> Option<&str> {
+    let rest = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://"))?;
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    Some(host.rsplit_once(':').map_or(host, |(host, _)| host))
+}
+
+/// Whether `host` is `zone` or one of its subdomains
+fn host_in_zone(host: &str, zone: &str) -> bool {
+    let host = host.to_lowercase();
+    let zone = zone.to_lowercase();
+    host == zone || host.ends_with(&format!(".{}", zone))
+}
+
+/// Check `--hosts`/`--prefixes`/`--files` belong to `zone`, so a typo fails here
+/// rather than silently purging nothing
+fn validate_purge_target(target: &api::PurgeTarget, zone: &str) -> Result<(), String> {
+    let in_zone = |host: &str| host_in_zone(host, zone);
+    match target {
+        api::PurgeTarget::Everything => Ok(()),
+        api::PurgeTarget::Hosts(hosts) => match hosts.iter().find(|h| !is_valid_hostname(h) || !in_zone(h)) {
+            Some(host) if !is_valid_hostname(host) => Err(format!("'{}' is not a valid hostname.", host)),
+            Some(host) => Err(format!("{} is not in zone {}.", host, zone)),
+            None => Ok(()),
+        },
+        api::PurgeTarget::Prefixes(prefixes) => {
+            for prefix in prefixes {
+                if prefix.contains("://") {
+                    return Err(format!("prefix '{}' should leave out the scheme, e.g. {}/blog.", prefix, zone));
+                }
+                let host = prefix.split('/').next().unwrap_or_default();
+                if !in_zone(host) {
+                    return Err(format!("prefix '{}' is not in zone {}.", prefix, zone));
+                }
+            }
+            Ok(())
+        }
+        api::PurgeTarget::Tags(tags) => match tags.iter().find(|t| t.trim().is_empty() || t.chars().any(char::is_whitespace) || t.len() > 1024) {
+            Some(tag) => Err(format!("'{}' isn't a cache tag (1 to 1024 characters, no spaces).", tag)),
+            None => Ok(()),
+        },
+        api::PurgeTarget::Files(files) => {
+            for file in files {
+                let Some(host) = url_host(file) else {
+                    return Err(format!("'{}' is not an http(s) URL.", file));
+                };
+                if !is_valid_hostname(host) {
+                    return Err(format!("'{}' doesn't have a valid hostname.", file));
+                }
+                if !in_zone(host) {
+                    return Err(format!("{} is not in zone {}.", file, zone));
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// After Cloudflare didn't know `zone`, point at a likely typo: the closest
+/// zone the config knows of (profile zones and past purges), if it's close.
+/// Only a suggestion; nothing is retried.
+fn suggest_zone(config: &Config, zone: &str) {
+    let known: BTreeSet<&str> = config
+        .profiles
+        .values()
+        .filter_map(|p| p.zone.as_deref())
+        .chain(config.last_purge.keys().map(String::as_str))
+        .collect();
+    let closest = known
+        .into_iter()
+        .filter(|k| *k != zone)
+        .map(|k| (edit_distance(zone, k), k))
+        .filter(|(distance, _)| *distance <= 2 && *distance < zone.len() / 2)
+        .min();
+    if let Some((_, suggestion)) = closest {
+        msg!("Did you mean {}?", suggestion.bold());
+    }
+}
+
+/// Levenshtein distance: single-character insertions, deletions and substitutions
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Most purges `purge --parallel` runs at once; Cloudflare's purge limits
+/// are per account, so more threads would mostly buy 429s
+const MAX_PURGE_PARALLEL: usize = 8;
+
+/// Purge `zones` through the API on up to `parallel` threads. Each zone's
+/// messages are held back and printed in order once every purge is done, so
+/// they don't interleave. Returns whether each purge worked, in order; with
+/// `fail_fast`, no new purge starts after one fails and those give None.
+#[allow(clippy::too_many_arguments)]
+fn purge_zones_in_parallel(
+    ctx: &Ctx,
+    config: &mut Config,
+    source: &str,
+    profile: &Profile,
+    zones: &[String],
+    parallel: usize,
+    target: &api::PurgeTarget,
+    fail_fast: bool,
+) -> Vec<Option<bool>> {
+    let max_retries = config.purge_max_retries.unwrap_or(DEFAULT_PURGE_MAX_RETRIES);
+    let parallel = parallel.min(MAX_PURGE_PARALLEL);
+    msg!("{} Purging {} zones using {}, {} at a time...", ctx.symbols.arrow.cyan(), zones.len(), source, parallel);
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let results = Mutex::new(HashMap::new());
+    thread::scope(|scope| {
+        for _ in 0..parallel.min(zones.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(zone) = zones.get(index).filter(|_| !stop.load(Ordering::Relaxed)) else {
+                        break;
+                    };
+                    ctx.event("purge_start", serde_json::json!({ "zone": zone, "target": target.to_string() }));
+                    let mut lines = Vec::new();
+                    let purged = purge_via_api(ctx, profile, zone, None, target, max_retries, &mut |line| lines.push(line));
+                    let failed = purged.as_ref().map_or(true, |(_, batches)| batches.iter().any(Result::is_err));
+                    ctx.event("purge_done", serde_json::json!({ "zone": zone, "ok": !failed }));
+                    if fail_fast && failed {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    results.lock().expect("purge results lock").insert(index, (lines, purged));
+                }
+            });
+        }
+    });
+    let mut results = results.into_inner().expect("purge results lock");
+    zones
+        .iter()
+        .enumerate()
+        .map(|(index, zone)| {
+            let (lines, purged) = results.remove(&index)?;
+            msg!("{} Purging {} for {}...", ctx.symbols.arrow.cyan(), target, zone.bold());
+            for line in lines {
+                msg!("{}", line);
+            }
+            Some(finish_api_purge(ctx, config, source, zone, target, purged))
+        })
+        .collect()
+}
+
+/// Why purging `zone` would fail or do nothing, from one lookup: Cloudflare
+/// says it isn't active, or `target` is a tag purge its plan doesn't offer.
+/// Comes with the skip reason for events. Lookup failures (global API keys,
+/// tokens without Zone Read) give None and the purge itself gets to report.
+fn purge_zone_problem(profile: &Profile, zone: &str, target: &api::PurgeTarget) -> Option<(String, &'static str)> {
+    if profile.auth_mode() == AuthMode::Key {
+        return None;
+    }
+    let details = api::zone(&profile.token, zone, api::RateLimited::Retry).ok()?;
+    // Cloudflare's answer to a tag purge it won't do doesn't say why
+    let plan = details.plan.as_deref().filter(|_| matches!(target, api::PurgeTarget::Tags(_)));
+    if let Some(plan) = plan.filter(|plan| !plan.to_lowercase().contains("enterprise")) {
+        let problem = format!("{} is on the {} plan; purging by cache tag needs an Enterprise plan.", zone, plan);
+        return Some((problem, "unsupported"));
+    }
+    let status = details.status;
+    let guidance = match status.as_str() {
+        "active" => return None,
+        "pending" | "initializing" => "its nameservers don't point at Cloudflare yet, so nothing is cached there",
+        "moved" => "its nameservers were moved away from Cloudflare",
+        "deleted" | "deactivated" => "it's no longer served by Cloudflare",
+        _ => "Cloudflare may not be serving it",
+    };
+    Some((format!("{} is {} on Cloudflare: {}.", zone, status, guidance), "inactive"))
+}
+
+/// Most URLs, hosts or prefixes listed before the rest are counted
+const PURGE_SCOPE_LIST_LIMIT: usize = 20;
+
+/// Describe what purging `target` on `zone` clears, so a confirmation means
+/// something: for a full purge the zone's id, account and plan (looked up
+/// when the credentials are a scoped token), otherwise each URL, host or prefix
+fn show_purge_scope(profile: &Profile, zone: &str, target: &api::PurgeTarget) {
+    let (kind, items) = match target {
+        api::PurgeTarget::Everything => {
+            msg!("  {} {}", "Everything cached on".bold(), zone.bold());
+            if profile.auth_mode() == AuthMode::Token {
+                match api::zone(&profile.token, zone, api::RateLimited::Retry) {
+                    Ok(details) => {
+                        msg!("  Zone id: {}", details.id);
+                        msg!("  Account: {}", details.account.as_deref().unwrap_or("unknown"));
+                        msg!("  Plan:    {}", details.plan.as_deref().unwrap_or("unknown"));
+                    }
+                    Err(e) => {
+                        msg!("  {}", format!("(zone details unavailable: {})", e).dimmed());
+                    }
+                }
+            }
+            return;
+        }
+        api::PurgeTarget::Hosts(items) => ("host", items),
+        api::PurgeTarget::Prefixes(items) => ("prefix", items),
+        api::PurgeTarget::Files(items) => ("URL", items),
+        api::PurgeTarget::Tags(items) => ("tag", items),
+    };
+    msg!("  {} {} {}(s) on {}:", "Only".bold(), items.len(), kind, zone.bold());
+    for item in items.iter().take(PURGE_SCOPE_LIST_LIMIT) {
+        msg!("    {}", item);
+    }
+    if items.len() > PURGE_SCOPE_LIST_LIMIT {
+        msg!("    ...and {} more", items.len() - PURGE_SCOPE_LIST_LIMIT);
+    }
+}
+
+/// For a profile with `allow_purge_everything` off: purging a whole zone
+/// needs --force and the zone's name typed in, so it can't happen by habit
+fn confirm_purge_everything(ctx: &Ctx, source: &str, zone: &str, force: bool) -> bool {
+    if !force {
+        msg!("{} {} doesn't allow purging everything on {}.", "Error:".red().bold(), source, zone.bold());
+        msg!("Narrow it with --api --hosts/--prefixes/--files, or pass --force and confirm.");
+        return false;
+    }
+    if !io::stdin().is_terminal() {
+        msg!("{} {} needs {} typed in to purge everything on it, and there's no terminal to ask on.", "Error:".red().bold(), source, zone.bold());
+        return false;
+    }
+    let typed: String = prompt_or_exit(
+        ctx,
+        dialoguer::Input::new()
+            .with_prompt(format!("Type {} to purge everything cached on it", zone))
+            .allow_empty(true)
+            .interact_text(),
+    );
+    if !typed.trim().eq_ignore_ascii_case(zone) {
+        msg!("That isn't {}; skipped.", zone.bold());
+        return false;
+    }
+    true
+}
+
+/// `purge --confirm`: show the scope and ask. Without a terminal to ask on,
+/// nothing is purged.
+fn confirm_purge(ctx: &Ctx, profile: &Profile, zone: &str, target: &api::PurgeTarget) -> bool {
+    if !io::stdin().is_terminal() {
+        msg!("{} --confirm needs a terminal to ask on; skipped {}.", "Error:".red().bold(), zone.bold());
+        return false;
+    }
+    msg!("{} About to purge:", ctx.symbols.arrow.cyan());
+    show_purge_scope(profile, zone, target);
+    let confirmed = dialoguer::Confirm::new().with_prompt("Purge?").default(false).interact().unwrap_or(false);
+    if !confirmed {
+        msg!("Skipped {}.", zone.bold());
+    }
+    confirmed
+}
+
+/// Longest single wait between rate-limited attempts
+const RATE_LIMIT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The wait before retry number `attempt` (from 0): what Cloudflare asked
+/// for, or 2s doubled per attempt, never over `RATE_LIMIT_MAX_BACKOFF`
+fn rate_limit_backoff(attempt: u32, retry_after: Option<Duration>) -> Duration {
+    retry_after
+        .unwrap_or_else(|| Duration::from_secs(2u64.saturating_mul(1 << attempt.min(16))))
+        .min(RATE_LIMIT_MAX_BACKOFF)
+}
+
+/// Run an API call, retrying up to `max_retries` times on HTTP 429. Waits as
+/// long as Cloudflare asks, or backs off exponentially from 2s if it doesn't
+/// say, plus up to a second of jitter so parallel runs don't retry in step.
+/// Each wait is announced through `report`.
+fn with_rate_limit_retries<T>(
+    max_retries: u32,
+    report: &mut dyn FnMut(String),
+    mut call: impl FnMut() -> Result<T, api::Error>,
+) -> Result<T, api::Error> {
+    let mut attempt = 0;
+    loop {
+        match call() {
+            Err(api::Error::Api { status: 429, retry_after, .. }) if attempt < max_retries => {
+                let backoff = rate_limit_backoff(attempt, retry_after);
+                let jitter = Duration::from_millis(u64::from(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_millis())));
+                let wait = backoff + jitter;
+                attempt += 1;
+                report(format!(
+                    "{} Rate limited by Cloudflare; waiting {:.1}s before retrying ({}/{})...",
+                    "!".yellow().bold(),
+                    wait.as_secs_f64(),
+                    attempt,
+                    max_retries
+                ));
+                thread::sleep(wait);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Quiet period after the last file change before `purge --watch` purges
+const PURGE_WATCH_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Purge `zones` after each burst of changes under `dir`, until interrupted.
+/// Bursts that land inside the cooldown wait it out rather than being dropped.
+fn watch_and_purge(ctx: &Ctx, config: &mut Config, source: &str, profile: &Profile, zones: &[String], dir: &Path, via_api: bool) -> ! {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).unwrap_or_else(|e| {
+        msg!("{} Could not start the file watcher: {}", "Error:".red().bold(), e);
+        std::process::exit(1);
+    });
+    if let Err(e) = watcher.watch(dir, RecursiveMode::Recursive) {
+        msg!("{} Could not watch {}: {}", "Error:".red().bold(), dir.display(), e);
+        std::process::exit(1);
+    }
+    msg!("{} Watching {} for changes (Ctrl-C to stop)...", ctx.symbols.arrow.cyan(), dir.display());
+    ctx.event("watch_start", serde_json::json!({ "dir": dir.display().to_string(), "zones": zones }));
+
+    let is_change = |event: &notify::Result<notify::Event>| match event {
+        Ok(event) => !matches!(event.kind, notify::EventKind::Access(_)),
+        Err(e) => {
+            msg!("{} Watch error: {}", "Warning:".yellow().bold(), e);
+            false
+        }
+    };
+    loop {
+        let Ok(event) = rx.recv() else {
+            msg!("{} The file watcher stopped.", "Error:".red().bold());
+            std::process::exit(1);
+        };
+        if !is_change(&event) {
+            continue;
+        }
+        // Let a save-everything or build step finish before purging once
+        while let Ok(event) = rx.recv_timeout(PURGE_WATCH_DEBOUNCE) {
+            is_change(&event);
+        }
+        ctx.event("change", serde_json::json!({}));
+        for zone in zones {
+            if let Some(remaining) = purge_cooldown_remaining(config, zone) {
+                msg!("{} Cooldown: waiting {}s before purging {}", "!".yellow().bold(), remaining, zone.bold());
+                thread::sleep(Duration::from_secs(remaining));
+                while rx.try_recv().is_ok() {}
+            }
+            let route = if via_api { PurgeRoute::Api(None) } else { PurgeRoute::Flarectl };
+            purge_zone(ctx, config, source, profile, zone, route, &api::PurgeTarget::Everything);
+        }
+    }
+}
+
+/// `watch-expiry`: every `interval`, warn about each profile (or, with
+/// `via_api`, token) that has come within `within` seconds of expiring. Each
+/// is reported once until it's renewed; Ctrl-C stops cleanly.
+fn watch_expiry(ctx: &Ctx, interval: Duration, within: u64, via_api: bool) -> ! {
+    let (tx, rx) = mpsc::channel();
+    if let Err(e) = ctrlc::set_handler(move || {
+        tx.send(()).ok();
+    }) {
+        msg!("{} Could not install the Ctrl-C handler: {}", "Error:".red().bold(), e);
+        std::process::exit(1);
+    }
+    msg!("{} Checking expiries every {} (Ctrl-C to stop)...", ctx.symbols.arrow.cyan(), audit::format_duration(interval.as_secs()));
+    let mut warned: HashSet<String> = HashSet::new();
+    loop {
+        let config = load_config(ctx);
+        let now = now_secs();
+        let mut names: Vec<&String> = config.profiles.keys().collect();
+        names.sort();
+        for name in names {
+            let profile = &config.profiles[name];
+            let mut expiries: Vec<(&str, u64)> = profile.expires_at.map(|at| ("profile", at)).into_iter().collect();
+            // Global API keys can't be checked with the token endpoints
+            if via_api && profile.auth_mode() == AuthMode::Token {
+                let status = with_secret(name, profile)
+                    .map_err(|e| e.to_string())
+                    .and_then(|p| api::verify_token(&p.token).map_err(|e| e.to_string()));
+                match status {
+                    Ok(status) if !status.is_active() => {
+                        if warned.insert(format!("{}:status", name)) {
+                            msg!("{} Token for '{}' is {}", "!".yellow().bold(), name, status.status);
+                        }
+                    }
+                    Ok(status) => {
+                        warned.remove(&format!("{}:status", name));
+                        if let Some(at) = status.expires_on.and_then(|e| audit::parse_time(&e, false).ok()) {
+                            expiries.push(("token", at));
+                        }
+                    }
+                    Err(e) => {
+                        msg!("{} Could not check the token for '{}': {}", "Warning:".yellow().bold(), name, e);
+                    }
+                }
+            }
+            for (what, at) in expiries {
+                let key = format!("{}:{}", name, what);
+                if at.saturating_sub(now) > within {
+                    warned.remove(&key);
+                } else if warned.insert(key) {
+                    msg!(
+                        "{} {} '{}' expires in {} ({} UTC)",
+                        "!".yellow().bold(),
+                        if what == "token" { "Token for" } else { "Profile" },
+                        name,
+                        audit::format_duration(at.saturating_sub(now)),
+                        audit::format_time(at)
+                    );
+                }
+            }
+        }
+        if rx.recv_timeout(interval).is_ok() {
+            msg!("Stopped.");
+            std::process::exit(0);
+        }
+    }
+}
+
+/// `list --filter` pattern: `/.../` is a regex, anything else a case-insensitive substring
+enum ProfileFilter {
+    Substring(String),
+    Regex(regex::Regex),
+}
+
+impl ProfileFilter {
+    fn parse(pattern: &str) -> Result<Self, regex::Error> {
+        match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            Some(re) => regex::Regex::new(re).map(ProfileFilter::Regex),
+            None => Ok(ProfileFilter::Substring(pattern.to_lowercase())),
+        }
+    }
+
+    fn matches(&self, name: &str, profile: &Profile) -> bool {
+        match self {
+            ProfileFilter::Substring(s) => {
+                name.to_lowercase().contains(s) || profile.email.to_lowercase().contains(s)
+            }
+            ProfileFilter::Regex(re) => re.is_match(name) || re.is_match(&profile.email),
+        }
+    }
+}
+
+/// Group headers a profile is listed under for `list --group-by`
+fn profile_groups(profile: &Profile, group_by: GroupBy) -> Vec<String> {
+    match group_by {
+        GroupBy::Account => vec![profile.account_id.clone().unwrap_or_else(|| "(no account id)".to_string())],
+        GroupBy::EmailDomain => vec![profile.email_domain().unwrap_or_else(|| "(no email domain)".to_string())],
+        GroupBy::Tag if profile.tags.is_empty() => vec!["(untagged)".to_string()],
+        GroupBy::Tag => profile.tags.clone(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn print_profile_row(
+    ctx: &Ctx,
+    config: &Config,
+    name: &str,
+    profile: &Profile,
+    verbose: bool,
+    indent: &str,
+    check: Option<&TokenCheck>,
+    zones: Option<&Result<Vec<api::Zone>, String>>,
+) {
+    let marker = if config.current.as_deref() == Some(name) {
+        ctx.symbols.on.green().bold()
+    } else {
+        " ".repeat(ctx.symbols.on.len()).normal()
+    };
+    let check = check.map(|c| format!(" [{}]", c.label())).unwrap_or_default();
+    let scope = if config.is_local(name) { format!(" {}", "[project]".dimmed()) } else { String::new() };
+    let aliases = if profile.aliases.is_empty() { String::new() } else { format!(" (aka {})", profile.aliases.join(", ")) };
+    msg!("{}{} {}{} ({}){}{}", indent, marker, name.cyan(), aliases, profile.email_label(), scope, check);
+    if verbose {
+        if let Some(z) = &profile.zone {
+            msg!("{}     zone: {}", indent, z);
+        }
+        if let Some(d) = &profile.description {
+            msg!("{}     {}", indent, d.dimmed());
+        }
+        if let Some(at) = profile.expires_at {
+            msg!("{}     expires: {} UTC", indent, audit::format_time(at));
+        }
+        if profile.use_count > 0 {
+            msg!("{}     {}", indent, format!("switched to {} time(s)", profile.use_count).dimmed());
+        }
+        match (profile.last_used, profile.unused(None)) {
+            (Some(at), _) => {
+                msg!("{}     {}", indent, format!("last switched to: {} UTC", audit::format_time(at)).dimmed());
+            }
+            (None, Some(never)) => {
+                msg!("{}     {}", indent, never.to_string().dimmed());
+            }
+            (None, None) => {}
+        }
+        if !profile.allows_purge_everything() {
+            msg!("{}     purge everything: needs --force and confirmation", indent);
+        }
+    }
+    match zones {
+        None => {}
+        Some(Err(e)) => {
+            msg!("{}     {}", indent, format!("zones: {}", e).red());
+        }
+        Some(Ok(zones)) => {
+            if zones.is_empty() {
+                msg!("{}     {}", indent, "no zones visible to this token".yellow());
+            }
+            let width = zones.iter().map(|z| z.name.len()).max().unwrap_or(0);
+            let status_width = zones.iter().map(|z| z.status.len()).max().unwrap_or(0);
+            for zone in zones {
+                let padded = format!("{:status_width$}", zone.status);
+                let status = if zone.status == "active" { padded.green() } else { padded.yellow() };
+                let plan = zone.plan.as_deref().map(|p| format!("  {}", p.dimmed())).unwrap_or_default();
+                msg!("{}     {:width$}  {}{}", indent, zone.name, status, plan, width = width);
+            }
+            if let Some(stored) = profile.zone.as_ref().filter(|z| !zones.iter().any(|found| found.name.eq_ignore_ascii_case(z))) {
+                msg!("{}     {} zone {} isn't visible to this token", indent, "!".yellow().bold(), stored.bold());
+            }
+        }
+    }
+}
+
+/// Groups of two or more profiles, among `names`, whose config holds the
+/// same token. Only plaintext tokens are compared: reading the others could
+/// mean a keychain prompt per profile.
+fn duplicate_tokens<'a>(config: &Config, names: &[&'a String]) -> Vec<Vec<&'a String>> {
+    let mut by_token: BTreeMap<&str, Vec<&'a String>> = BTreeMap::new();
+    for name in names {
+        let profile = &config.profiles[*name];
+        if profile.backend.is_plaintext() && !profile.token.is_empty() {
+            by_token.entry(profile.token.as_str()).or_default().push(name);
+        }
+    }
+    by_token.into_values().filter(|group| group.len() > 1).collect()
+}
+
+/// How many verify requests `list --check` keeps in flight
+const LIST_CHECK_CONCURRENCY: usize = 4;
+
+/// Result of verifying one profile's token for `list --check`
+enum TokenCheck {
+    /// What Cloudflare reports: `active`, `disabled` or `expired`
+    Status(String),
+    Error(String),
+    /// A global API key, which the verify endpoint doesn't take
+    NotChecked,
+}
+
+impl TokenCheck {
+    fn is_ok(&self) -> bool {
+        matches!(self, TokenCheck::Status(s) if s == "active") || matches!(self, TokenCheck::NotChecked)
+    }
+
+    fn label(&self) -> ColoredString {
+        match self {
+            TokenCheck::Status(s) if s == "active" => s.green(),
+            TokenCheck::Status(s) => s.red(),
+            TokenCheck::Error(e) => format!("error: {}", e).red(),
+            TokenCheck::NotChecked => "global key, not checked".dimmed(),
+        }
+    }
+}
+
+/// Verify the named profiles' tokens on a few worker threads
+fn check_profile_tokens(config: &Config, names: &[&String]) -> HashMap<String, TokenCheck> {
+    let (keys, tokens): (Vec<&String>, Vec<&String>) =
+        names.iter().partition(|name| config.profiles[**name].auth_mode() == AuthMode::Key);
+    per_profile_in_parallel(config, &tokens, |profile| api::verify_token(&profile.token).map_err(|e| e.to_string()))
+        .into_iter()
+        .map(|(name, result)| (name, result.map_or_else(TokenCheck::Error, |status| TokenCheck::Status(status.status))))
+        .chain(keys.into_iter().map(|name| (name.clone(), TokenCheck::NotChecked)))
+        .collect()
+}
+
+/// Most zones `list --zones-resolved` shows per profile (one page)
+const ZONES_RESOLVED_LIMIT: usize = 50;
+
+/// Fetch the zones each named profile's token can see, on a few worker threads
+fn resolve_profile_zones(config: &Config, names: &[&String]) -> HashMap<String, Result<Vec<api::Zone>, String>> {
+    per_profile_in_parallel(config, names, |profile| {
+        let mut zones = api::zones(&profile.token, ZONES_RESOLVED_LIMIT).map_err(|e| e.to_string())?;
+        zones.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(zones)
+    })
+}
+
+/// `lookup` for each named profile, with its secret loaded, keeping
+/// `LIST_CHECK_CONCURRENCY` requests in flight. One profile's failure (a
+/// missing keychain entry, a revoked token) only fails its own entry.
+fn per_profile_in_parallel<T: Send>(
+    config: &Config,
+    names: &[&String],
+    lookup: impl Fn(&Profile) -> Result<T, String> + Sync,
+) -> HashMap<String, Result<T, String>> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(HashMap::new());
+    thread::scope(|scope| {
+        for _ in 0..LIST_CHECK_CONCURRENCY.min(names.len()) {
+            scope.spawn(|| {
+                while let Some(name) = names.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let result = with_secret(name, &config.profiles[*name]).map_err(|e| e.to_string()).and_then(|p| lookup(&p));
+                    results.lock().expect("lookup results lock").insert(name.to_string(), result);
+                }
+            });
+        }
+    });
+    results.into_inner().expect("lookup results lock")
+}
+
+/// How long `doctor` waits for Cloudflare before calling the network down
+const DOCTOR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Probe the API and print a line per connection step, so a failure shows
+/// whether it's DNS, the connection or TLS. Returns whether the API answered.
+fn check_connectivity(ctx: &Ctx) -> bool {
+    let host = api::url("").split('/').nth(2).unwrap_or_default().to_string();
+    let via = http::settings().proxy.map(|proxy| format!(" through proxy {}", http::without_userinfo(&proxy)));
+    msg!("{} Checking {}{}...", ctx.symbols.arrow.cyan(), host, via.unwrap_or_default());
+    let steps = [(http::Stage::Dns, "DNS resolves"), (http::Stage::Connect, "Connection opens"), (http::Stage::Tls, "TLS handshake succeeds")];
+    match api::probe(DOCTOR_TIMEOUT) {
+        Ok((status, latency)) => {
+            for (_, step) in steps {
+                msg!("  {} {}", ctx.symbols.ok.green(), step);
+            }
+            msg!("  {} API answered (HTTP {}) in {} ms", ctx.symbols.ok.green(), status, latency.as_millis());
+            true
+        }
+        Err(e) if e.stage() == http::Stage::Timeout => {
+            msg!("  {} No answer within {}s: {}", ctx.symbols.fail.red(), DOCTOR_TIMEOUT.as_secs(), e);
+            msg!("    Are you offline, or is a firewall or proxy dropping the connection?");
+            false
+        }
+        Err(e) if e.stage() == http::Stage::Other => {
+            msg!("  {} Request failed: {}", ctx.symbols.fail.red(), e);
+            false
+        }
+        Err(e) => {
+            let failed = e.stage();
+            let mut reached = false;
+            for (stage, step) in steps {
+                if stage == failed {
+                    msg!("  {} {}: {}", ctx.symbols.fail.red(), step, e);
+                    reached = true;
+                } else if reached {
+                    msg!("  {} {} (not reached)", "-".dimmed(), step);
+                } else {
+                    msg!("  {} {}", ctx.symbols.ok.green(), step);
+                }
+            }
+            false
+        }
+    }
+}
+
+/// Report the installed flarectl and whether it's new enough for every
+/// profile. Not having it is only a warning: --api commands don't need it.
+fn check_flarectl(ctx: &Ctx, config: &Config) -> bool {
+    msg!("{} Checking flarectl...", ctx.symbols.arrow.cyan());
+    let Some(installed) = flarectl::version() else {
+        msg!("  {} No version from flarectl --version; if it isn't installed, only --api commands will work", "!".yellow().bold());
+        return true;
+    };
+    msg!("  {} flarectl {}", ctx.symbols.ok.green(), installed);
+    let tokens = config.profiles.values().any(|p| p.auth_mode() == AuthMode::Token);
+    match flarectl::check(flarectl::Feature::TokenAuth) {
+        Err(_) if tokens => {
+            let feature = flarectl::Feature::TokenAuth;
+            msg!("  {} Too old for {}, which some profiles use; needs {} or newer", ctx.symbols.fail.red(), feature.describe(), feature.since());
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Profiles whose explicit `auth` disagrees with what their token looks
+/// like, which makes the env file export the secret under the wrong
+/// variable. Only plaintext tokens are looked at, so nothing prompts.
+fn check_auth_modes(ctx: &Ctx, config: &Config) -> bool {
+    msg!("{} Checking each profile's auth mode against its token...", ctx.symbols.arrow.cyan());
+    let mut mismatched = 0;
+    for name in ordered_profile_names(config) {
+        let profile = &config.profiles[&name];
+        let Some(configured) = profile.auth else {
+            continue;
+        };
+        if let Some(guessed) = auth_mode_mismatch(profile) {
+            let looks_like = if guessed == AuthMode::Key { "a global API key" } else { "a scoped API token" };
+            msg!("  {} '{}' is set to auth {} but its token looks like {}", ctx.symbols.fail.red(), name, configured, looks_like);
+            msg!("    Fix with: cf-switch edit {} --auth {}", name, guessed);
+            mismatched += 1;
+        }
+    }
+    if mismatched == 0 {
+        msg!("  {} No profile's auth mode contradicts its token", ctx.symbols.ok.green());
+    }
+    mismatched == 0
+}
+
+/// What the profile's plaintext token looks like, when that contradicts
+/// its explicit `auth`
+fn auth_mode_mismatch(profile: &Profile) -> Option<AuthMode> {
+    let configured = profile.auth.filter(|_| profile.backend.is_plaintext())?;
+    AuthMode::guess(&profile.token).filter(|guessed| *guessed != configured)
+}
+
+/// Most zones `health` counts per profile; more shows as `50+`
+const HEALTH_ZONES_LIMIT: usize = 50;
+
+/// One profile's row in `health`
+struct Health {
+    name: String,
+    /// Cloudflare's status for the token, or why it couldn't be checked
+    token: Result<String, String>,
+    /// Days until the token (or failing that, the profile) expires
+    expires_in_days: Option<i64>,
+    /// Zones the token can see, up to `HEALTH_ZONES_LIMIT`
+    zones: Option<Result<usize, String>>,
+    auth_mismatch: Option<AuthMode>,
+    problems: Vec<String>,
+}
+
+/// A profile's token status and how many zones it sees, or why they
+/// couldn't be fetched
+type HealthCheck = Result<(api::TokenStatus, Result<usize, String>), String>;
+
+/// The API half of `health` for each profile: token status and visible
+/// zones. Up to `concurrency` profiles run at once, and one that takes
+/// longer than `deadline` is reported as timed out without holding up the
+/// rest (its requests are still bounded by --timeout).
+fn fleet_token_checks(
+    config: &Config,
+    names: &[String],
+    concurrency: usize,
+    deadline: Duration,
+) -> HashMap<String, HealthCheck> {
+    let (tx, rx) = mpsc::channel();
+    let mut waiting = names.iter().filter(|name| config.profiles[*name].auth_mode() == AuthMode::Token);
+    let mut running: HashMap<String, Instant> = HashMap::new();
+    let mut results = HashMap::new();
+    loop {
+        while running.len() < concurrency {
+            let Some(name) = waiting.next() else {
+                break;
+            };
+            let (tx, name_owned, profile) = (tx.clone(), name.clone(), config.profiles[name].clone());
+            thread::spawn(move || {
+                let result = with_secret(&name_owned, &profile).map_err(|e| e.to_string()).and_then(|profile| {
+                    let status = api::verify_token(&profile.token).map_err(|e| e.to_string())?;
+                    let zones = api::zones(&profile.token, HEALTH_ZONES_LIMIT).map(|z| z.len()).map_err(|e| e.to_string());
+                    Ok((status, zones))
+                });
+                tx.send((name_owned, result)).ok();
+            });
+            running.insert(name.clone(), Instant::now() + deadline);
+        }
+        let Some(soonest) = running.values().min().copied() else {
+            break;
+        };
+        match rx.recv_timeout(soonest.saturating_duration_since(Instant::now())) {
+            Ok((name, result)) => {
+                if running.remove(&name).is_some() {
+                    results.insert(name, result);
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let now = Instant::now();
+                let late: Vec<String> = running.iter().filter(|(_, due)| **due <= now).map(|(name, _)| name.clone()).collect();
+                for name in late {
+                    running.remove(&name);
+                    results.insert(name, Err(format!("no answer within {}s", deadline.as_secs())));
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    results
+}
+
+/// Put together each profile's `health` row from the API checks and what
+/// the config itself says
+fn fleet_health(config: &Config, concurrency: usize, deadline: Duration, expiry_days: u64) -> Vec<Health> {
+    let names = ordered_profile_names(config);
+    let mut checks = fleet_token_checks(config, &names, concurrency, deadline);
+    let now = now_secs() as i64;
+    names
+        .into_iter()
+        .map(|name| {
+            let profile = &config.profiles[&name];
+            let mut problems = Vec::new();
+            let auth_mismatch = auth_mode_mismatch(profile);
+            if let Some(guessed) = auth_mismatch {
+                problems.push(format!("auth is {} but the token looks like {}", profile.auth_mode(), guessed));
+            }
+            let (token, expires_on, zones) = match checks.remove(&name) {
+                // Global API keys can't be checked with the token endpoints
+                None => (Err("global API key, not checked".to_string()), None, None),
+                Some(Err(e)) => {
+                    problems.push(format!("token check failed: {}", e));
+                    (Err(e), None, None)
+                }
+                Some(Ok((status, zones))) => {
+                    if !status.is_active() {
+                        problems.push(format!("token is {}", status.status));
+                    }
+                    if let Err(e) = &zones {
+                        problems.push(format!("zones lookup failed: {}", e));
+                    }
+                    (Ok(status.status), status.expires_on, Some(zones))
+                }
+            };
+            let expires_at = expires_on.as_deref().and_then(|e| audit::parse_time(e, false).ok()).or(profile.expires_at);
+            let expires_in_days = expires_at.map(|at| (at as i64 - now).div_euclid(86_400));
+            if let Some(days) = expires_in_days.filter(|days| *days < expiry_days as i64) {
+                problems.push(if days < 0 { "expired".to_string() } else { format!("expires in {} day(s)", days) });
+            }
+            Health { name, token, expires_in_days, zones, auth_mismatch, problems }
+        })
+        .collect()
+}
+
+const PURGE_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Poll `url` until its CF-Cache-Status shows the cached copy is gone.
+/// Returns false if the purge couldn't be confirmed.
+fn wait_for_cache_clear(ctx: &Ctx, url: &str) -> bool {
+    msg!("{} Waiting for {} to come back uncached...", ctx.symbols.arrow.cyan(), url);
+    let started = Instant::now();
+    let mut delay = Duration::from_secs(1);
+    loop {
+        match http::Request::get(url).timeout(Duration::from_secs(10)).send() {
+            Ok(response) => match response.header("cf-cache-status").map(str::to_uppercase) {
+                Some(status) if status == "MISS" || status == "EXPIRED" => {
+                    msg!("{} Cache status for {}: {}", ctx.symbols.ok.green(), url, status.bold());
+                    return true;
+                }
+                Some(status) if status == "DYNAMIC" || status == "BYPASS" => {
+                    msg!("{} Cache status for {} is {}: Cloudflare doesn't cache it, nothing to confirm.", "!".yellow().bold(), url, status);
+                    return true;
+                }
+                Some(status) => {
+                    msg!("  still {} ({}s elapsed)", status, started.elapsed().as_secs());
+                }
+                None => {
+                    msg!("{} {} returned no CF-Cache-Status header (HTTP {}); is it proxied through Cloudflare?", "!".yellow().bold(), url, response.status);
+                    return false;
+                }
+            },
+            Err(e) => {
+                msg!("  request failed: {}", e);
+            }
+        }
+        if started.elapsed() + delay > PURGE_WAIT_TIMEOUT {
+            msg!("{} Gave up after {}s without seeing a MISS/EXPIRED for {}", "Error:".red().bold(), PURGE_WAIT_TIMEOUT.as_secs(), url);
+            return false;
+        }
+        thread::sleep(delay);
+        delay = (delay * 2).min(Duration::from_secs(8));
+    }
+}
+
+/// Wait after creating a record before `add-lamdera-app --probe` first fetches the site
+const PROBE_INITIAL_DELAY: Duration = Duration::from_secs(5);
+/// Fetches before `--probe` gives up on connection and TLS errors
+const PROBE_ATTEMPTS: u32 = 5;
+const PROBE_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Fetch `https://<domain>/` and report the status and whether Cloudflare
+/// served it. Connection and TLS failures are retried, since a fresh record's
+/// edge certificate can take a little while to be issued.
+fn probe_site(ctx: &Ctx, domain: &str) {
+    let url = format!("https://{}/", domain);
+    msg!("{} Probing {}...", ctx.symbols.arrow.cyan(), url);
+    for attempt in 1..=PROBE_ATTEMPTS {
+        match http::Request::get(&url).timeout(Duration::from_secs(15)).send() {
+            Ok(response) => {
+                let via_cloudflare = response.header("cf-ray").is_some()
+                    || response.header("server").is_some_and(|s| s.eq_ignore_ascii_case("cloudflare"));
+                let served = if via_cloudflare { "through Cloudflare" } else { "NOT through Cloudflare" };
+                if (200..400).contains(&response.status) && via_cloudflare {
+                    msg!("{} {} answered HTTP {} {}", ctx.symbols.ok.green(), url, response.status, served);
+                    return;
+                }
+                msg!("{} {} answered HTTP {} {}", "!".yellow().bold(), url, response.status, served);
+                // 52x are Cloudflare failing to reach or trust the origin
+                if (520..=527).contains(&response.status) {
+                    msg!("Cloudflare couldn't get a good answer from the origin; the app may not be deployed yet.");
+                }
+                return;
+            }
+            Err(e) if attempt < PROBE_ATTEMPTS => {
+                msg!("  not reachable yet ({}); retrying in {}s", e, PROBE_RETRY_DELAY.as_secs());
+                thread::sleep(PROBE_RETRY_DELAY);
+            }
+            Err(e) => {
+                msg!("{} {} still unreachable after {} attempts: {}", "!".yellow().bold(), url, PROBE_ATTEMPTS, e);
+                msg!("DNS and the edge certificate can take a few minutes; try the URL again shortly.");
+            }
+        }
+    }
+}
+
+/// Profile names in toggle order: the configured `order` first (skipping
+/// names that no longer exist), then everything else alphabetically. With
+/// `order_by_usage` it's the most-used first instead, ties alphabetical.
+fn ordered_profile_names(config: &Config) -> Vec<String> {
+    if config.order_by_usage {
+        return names_by_usage(config, config.profiles.keys());
+    }
+    let mut names: Vec<String> = config
+        .order
+        .iter()
+        .filter(|n| config.profiles.contains_key(*n))
+        .cloned()
+        .collect();
+    let mut rest: Vec<String> = config
+        .profiles
+        .keys()
+        .filter(|n| !names.contains(n))
+        .cloned()
+        .collect();
+    rest.sort();
+    names.extend(rest);
+    names
+}
+
+/// `names` most-used first, ties alphabetical
+fn names_by_usage<'a>(config: &Config, names: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+    let mut names: Vec<String> = names.into_iter().cloned().collect();
+    names.sort_by(|a, b| config.profiles[b].use_count.cmp(&config.profiles[a].use_count).then_with(|| a.cmp(b)));
+    names
+}
+
+/// The profile `offset` places away from the current one in toggle order,
+/// wrapping around; negative offsets go backward. With no current profile,
+/// counting starts just before the first (or after the last, going backward).
+/// Callers must ensure there's at least one profile.
+fn next_profile(config: &Config, offset: i64) -> String {
+    let names = ordered_profile_names(config);
+    let len = names.len() as i64;
+    let start = match &config.current {
+        Some(current) => names.iter().position(|n| n == current).unwrap_or(0) as i64,
+        None if offset < 0 => len,
+        None => -1,
+    };
+    // Reduced first, so a huge count can't overflow
+    names[(start + offset.rem_euclid(len)).rem_euclid(len) as usize].clone()
+}
+
+/// Move `offset` places through the toggle order and switch there. With
+/// `may_pick`, `interactive_toggle` set and a terminal to ask on, the user
+/// picks instead, starting from that same profile.
+fn cycle_profiles(ctx: &Ctx, offset: i64, may_pick: bool, verify: bool, env_out: Option<&Path>) {
+    let mut config = load_config(ctx);
+    if config.profiles.is_empty() {
+        msg!("{}", "No profiles configured.".yellow());
+        msg!("Add one with: cf-switch add <name> -e <email> -t <token>");
+        return;
+    }
+
+    let next_name = next_profile(&config, offset);
+    let pick = may_pick && config.interactive_toggle && io::stdin().is_terminal() && io::stderr().is_terminal();
+    let next_name = if pick {
+        let Some(picked) = fuzzy_pick_profile(&config, &next_name) else {
+            msg!("Cancelled.");
+            return;
+        };
+        picked
+    } else {
+        next_name
+    };
+    // When usage decides the order, toggling mustn't feed back into it or
+    // the two most-used profiles would take turns and the rest never come up.
+    // A profile picked by hand is a real choice, so that counts.
+    let ctx = &Ctx { count_use: pick || !config.order_by_usage, ..ctx.clone() };
+
+    if verify && !verify_before_switch(ctx, &config, &next_name) {
+        std::process::exit(1);
+    }
+    if !switch_to_profile(ctx, &mut config, &next_name, env_out) {
+        std::process::exit(1);
+    }
+}
+
+/// Ask which profile to switch to, filtering as the user types (names,
+/// aliases and emails all match). Enter alone takes `default`, the profile
+/// the toggle would have moved to.
+fn fuzzy_pick_profile(config: &Config, default: &str) -> Option<String> {
+    let names = ordered_profile_names(config);
+    let items: Vec<String> = names
+        .iter()
+        .map(|n| {
+            let profile = &config.profiles[n];
+            let aliases = if profile.aliases.is_empty() { String::new() } else { format!(" (aka {})", profile.aliases.join(", ")) };
+            let current = if config.current.as_ref() == Some(n) { " [current]" } else { "" };
+            format!("{}{} ({}){}", n, aliases, profile.email_label(), current)
+        })
+        .collect();
+    let default = names.iter().position(|n| n == default).unwrap_or(0);
+    // dialoguer draws on stderr, so stdout stays clean for eval
+    match dialoguer::FuzzySelect::new()
+        .with_prompt("Switch to profile")
+        .items(&items)
+        .default(default)
+        .interact_opt()
+    {
+        Ok(Some(idx)) => Some(names[idx].clone()),
+        _ => None,
+    }
+}
+
+/// Resolve a profile name, prompting with an interactive selector when it was omitted.
+/// Only prompts when stdin is a TTY so scripts still get a hard error.
+fn resolve_profile_name(config: &Config, name: Option<String>, prompt: &str) -> Option<String> {
+    if let Some(name) = name {
+        return match_profile_name(config, &name);
+    }
+    if !io::stdin().is_terminal() {
+        msg!("{} No profile name given (interactive selection requires a TTY).", "Error:".red().bold());
+        return None;
+    }
+    if config.profiles.is_empty() {
+        msg!("{}", "No profiles configured.".yellow());
+        return None;
+    }
+
+    let mut names: Vec<_> = config.profiles.keys().cloned().collect();
+    names.sort();
+    let items: Vec<String> = names
+        .iter()
+        .map(|n| {
+            let email = config.profiles[n].email_label();
+            if config.current.as_ref() == Some(n) {
+                format!("{} ({}) [current]", n, email)
+            } else {
+                format!("{} ({})", n, email)
+            }
+        })
+        .collect();
+    let default = config
+        .current
+        .as_ref()
+        .and_then(|c| names.iter().position(|n| n == c))
+        .unwrap_or(0);
+
+    // dialoguer draws on stderr, so stdout stays clean for eval
+    match dialoguer::Select::new()
+        .with_prompt(prompt)
+        .items(&items)
+        .default(default)
+        .interact_opt()
+    {
+        Ok(Some(idx)) => Some(names[idx].clone()),
+        _ => None,
+    }
+}
+
+/// Fail after `resolve_profile_name` came back empty: a `typed` name can only
+/// have matched several profiles, while with none nothing was picked
+fn fail_unresolved(ctx: &Ctx, typed: Option<&str>) -> ! {
+    match typed {
+        Some(typed) => ctx.fail("ambiguous", &format!("'{}' matches several profiles", typed)),
+        None => ctx.fail("not_found", "no single profile was picked"),
+    }
+}
+
+/// Refuse a new profile name that's already some profile's alias, which
+/// would leave the alias unreachable
+fn check_not_alias_or_exit(config: &Config, name: &str) {
+    if let Some(owner) = config.alias_owner(name) {
+        msg!("{} '{}' is an alias of profile '{}'; remove it first with: cf-switch unalias {}", "Error:".red().bold(), name, owner, name);
+        std::process::exit(1);
+    }
+}
+
+/// Expand a typed name to a profile: an exact name wins, then an exact
+/// alias, otherwise a unique prefix of a name. Unknown names pass through so
+/// callers report them as not found.
+fn match_profile_name(config: &Config, input: &str) -> Option<String> {
+    if config.profiles.contains_key(input) {
+        return Some(input.to_string());
+    }
+    if let Some(owner) = config.alias_owner(input) {
+        return Some(owner.clone());
+    }
+    let mut candidates: Vec<&String> = config.profiles.keys().filter(|n| n.starts_with(input)).collect();
+    candidates.sort();
+    match candidates.as_slice() {
+        [] => Some(input.to_string()),
+        [only] => Some((*only).clone()),
+        _ => {
+            msg!("{} '{}' matches several profiles:", "Error:".red().bold(), input);
+            for name in candidates {
+                msg!("  {}", name);
+            }
+            msg!("Type more of the name to pick one.");
+            None
+        }
+    }
+}
+
+/// Check a profile's token against the verify endpoint before switching.
+/// Problems are warnings unless `--strict`, in which case the switch is refused.
+fn verify_before_switch(ctx: &Ctx, config: &Config, name: &str) -> bool {
+    let Some(profile) = config.profiles.get(name) else {
+        // switch_to_profile reports the missing profile
+        return true;
+    };
+    let profile = with_secret_or_exit(ctx, name, profile);
+    check_token(ctx, name, &profile.token, profile.auth_mode(), "not switching")
+}
+
+/// Verify a token, reporting problems as warnings, or under `--strict` as
+/// errors with `refusal` saying what won't happen. Returns whether to go ahead.
+/// A global API key can't be checked against the token endpoint, so it's
+/// let through with a note.
+fn check_token(ctx: &Ctx, name: &str, token: &str, auth: AuthMode, refusal: &str) -> bool {
+    if auth == AuthMode::Key {
+        msg!("{}", format!("'{}' uses a global API key, which can't be verified; skipping the check.", name).dimmed());
+        return true;
+    }
+    let (problem, kind) = match api::verify_token(token) {
+        Ok(status) if status.is_active() => {
+            let expiry = status.expires_on.map(|e| format!(" (expires {})", e)).unwrap_or_default();
+            msg!("{} Token for '{}' is active{}", ctx.symbols.ok.green(), name, expiry);
+            return true;
+        }
+        Ok(status) => (format!("token for '{}' is {}", name, status.status), Some(ErrorKind::AuthError)),
+        Err(e) => (format!("could not verify token for '{}': {}", name, e), ErrorKind::from_api(&e)),
+    };
+    if ctx.strict {
+        msg!("{} {}; {}.", "Error:".red().bold(), problem, refusal);
+    } else {
+        msg!("{} {}", "Warning:".yellow().bold(), problem);
+    }
+    explain_failure(ctx, &format!("profile '{}'", name), kind);
+    !ctx.strict
+}
+
+/// Switch the active profile. The config is saved first and the env file
+/// written second; if the env file can't be written the config is rolled back
+/// so `current` keeps describing what's on disk. With `env_out` a single env
+/// file is written there instead, and the usual ones are left alone.
+fn switch_to_profile(ctx: &Ctx, config: &mut Config, name: &str, env_out: Option<&Path>) -> bool {
+    let Some(profile) = config.profiles.get(name) else {
+        msg!("{} Profile '{}' not found.", "Error:".red().bold(), name);
+        return false;
+    };
+    let profile = match with_secret(name, profile) {
+        Ok(p) => p,
+        Err(e) => {
+            msg!("{} Could not load the token for '{}' ({} backend): {}", "Error:".red().bold(), name, profile.backend, e);
+            return false;
+        }
+    };
+
+    let files = match env_out {
+        Some(path) => vec![env_file_at(config, &profile, name, path)],
+        None => {
+            warn_modified_env_files(config, true);
+            env_files(config, &profile, name)
+        }
+    };
+    let previous = config.current.replace(name.to_string());
+    let previous_previous = config.previous.clone();
+    if previous.as_deref().is_some_and(|p| p != name) {
+        config.previous = previous.clone();
+    }
+    let previous_hashes = config.env_hash.clone();
+    if env_out.is_none() {
+        config.record_env_files(&files);
+    }
+    let previous_last_used = config.profiles.get_mut(name).and_then(|p| p.last_used.replace(now_secs()));
+    if ctx.count_use {
+        adjust_use_count(config, name, true);
+    }
+    if let Err(e) = try_save_config(ctx, config) {
+        config.current = previous;
+        config.previous = previous_previous;
+        config.env_hash = previous_hashes;
+        if let Some(profile) = config.profiles.get_mut(name) {
+            profile.last_used = previous_last_used;
+        }
+        if ctx.count_use {
+            adjust_use_count(config, name, false);
+        }
+        msg!("{} Failed to save config: {}", "Error:".red().bold(), e);
+        msg!("Nothing was changed; the env file still has the previous credentials.");
+        hint_if_unwritable(&e, CONFIG_UNWRITABLE_HINT);
+        return false;
+    }
+
+    if let Err(e) = try_write_env_files(ctx, &files) {
+        msg!("{} Failed to write env file: {}", "Error:".red().bold(), e);
+        hint_if_unwritable(&e, ENV_UNWRITABLE_HINT);
+        config.current = previous;
+        config.previous = previous_previous;
+        config.env_hash = previous_hashes;
+        if let Some(profile) = config.profiles.get_mut(name) {
+            profile.last_used = previous_last_used;
+        }
+        if ctx.count_use {
+            adjust_use_count(config, name, false);
+        }
+        match try_save_config(ctx, config) {
+            Ok(()) => {
+                msg!("Config restored; the previous profile is still active.");
+            }
+            Err(e) => {
+                msg!("{} Could not restore config either: {}", "Error:".red().bold(), e);
+                msg!("Config now says '{}' but the env file was not updated. Run 'cf-switch sync' once the problem is fixed.", name);
+            }
+        }
+        return false;
+    }
+
+    audit::record(audit::Entry::new("switch", true).profile(name));
+    msg!("{} {} ({})", ctx.symbols.on.green().bold(), name.cyan().bold(), profile.email_label());
+    if ctx.print_source {
+        match env_out {
+            Some(_) => emit_source(ctx, &format!("source \"{}\"", files[0].0.display())),
+            None => output_source_command(ctx, config),
+        }
+    }
+    true
+}
+
+/// The zone `ci_zones` maps the build's repository to; GitHub and GitLab
+/// repository names aren't case-sensitive, so neither is the lookup
+fn ci_zone(config: &Config, build: Option<&ci::Build>) -> Option<String> {
+    let repo = build?.repo.as_deref()?;
+    config.ci_zones.iter().find(|(r, _)| r.eq_ignore_ascii_case(repo)).map(|(_, zone)| zone.clone())
+}
+
+/// Move a profile just taken out of `config.profiles` to the trash and drop
+/// references to it. A trashed profile's secret stays in its backend until
+/// it falls out of the trash, so `restore` gets it back too.
+fn trash_removed_profile(ctx: &Ctx, config: &mut Config, name: &str, removed: Profile) {
+    for (evicted, profile) in config.trash_profile(name, removed) {
+        if config.profiles.get(&evicted).is_some_and(|p| p.backend == profile.backend) {
+            continue;
+        }
+        if let Some(store) = credentials::store(profile.backend).filter(|_| !ctx.no_write) {
+            if let Err(e) = store.delete(&evicted) {
+                msg!("{} Could not delete the token from the {} backend: {}", "Warning:".yellow().bold(), profile.backend, e);
+            }
+        }
+    }
+    config.rename_references(name, None);
+}
+
+/// Run this binary again with `args`, so the TUI's changes take exactly the
+/// paths the commands do. The config file is passed on unless a project
+/// config was found, which the child finds again the same way. stdout is
+/// dropped: the source line is printed once, when the TUI exits. With
+/// `capture`, stderr comes back instead of going to the terminal.
+#[cfg(feature = "tui")]
+fn run_self(ctx: &Ctx, args: &[&str], capture: bool) -> io::Result<std::process::Output> {
+    let mut command = Command::new(std::env::current_exe()?);
+    if ctx.local_config_path.is_none() {
+        command.arg("--config").arg(&ctx.config_path);
+    }
+    command.args(args).stdout(Stdio::null());
+    if capture {
+        command.stdin(Stdio::null()).stderr(Stdio::piped()).output()
+    } else {
+        command.status().map(|status| std::process::Output { status, stdout: Vec::new(), stderr: Vec::new() })
+    }
+}
+
+/// `cf-switch tui`: show the profiles, carry out what's picked, repeat
+#[cfg(feature = "tui")]
+fn run_tui(ctx: &Ctx) {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        msg!("{} cf-switch tui needs a terminal; use list, use, add, remove and purge in scripts.", "Error:".red().bold());
+        std::process::exit(1);
+    }
+    let started_on = load_config(ctx).current;
+    let mut selected = 0;
+    let mut status = String::new();
+    loop {
+        let config = load_config(ctx);
+        let rows: Vec<tui::Row> = ordered_profile_names(&config)
+            .into_iter()
+            .map(|name| {
+                let profile = &config.profiles[&name];
+                let mut detail = profile.email_label().to_string();
+                if let Some(zone) = &profile.zone {
+                    detail.push_str(&format!("  {}", zone));
+                }
+                if !profile.aliases.is_empty() {
+                    detail.push_str(&format!("  (aka {})", profile.aliases.join(", ")));
+                }
+                tui::Row { active: config.current.as_ref() == Some(&name), name, detail }
+            })
+            .collect();
+        let action = tui::choose(&rows, &mut selected, &status).unwrap_or_else(|e| {
+            msg!("{} Failed to draw the TUI: {}", "Error:".red().bold(), e);
+            std::process::exit(1);
+        });
+        // Switching is quick and asks nothing, so its outcome goes in the
+        // status line; the rest prompt, so they get the terminal back
+        let (args, capture): (Vec<String>, bool) = match action {
+            tui::Action::Quit => break,
+            tui::Action::Switch(name) => (vec!["use".into(), name], true),
+            tui::Action::Remove(name) => (vec!["remove".into(), name], false),
+            tui::Action::Purge(name) => (vec!["--profile".into(), name, "purge".into(), "--confirm".into()], false),
+            tui::Action::Add => {
+                let name = match dialoguer::Input::<String>::new().with_prompt("New profile name (blank to go back)").allow_empty(true).interact_text() {
+                    Ok(name) if !name.trim().is_empty() => name.trim().to_string(),
+                    _ => continue,
+                };
+                (vec!["add".into(), name], false)
+            }
+        };
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match run_self(ctx, &args, capture) {
+            Ok(output) if capture => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                status = stderr.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or_default().trim().to_string();
+            }
+            Ok(output) => {
+                status = if output.status.success() { format!("Done: cf-switch {}", args.join(" ")) } else { format!("Failed: cf-switch {}", args.join(" ")) };
+                msg!("");
+                let _ = dialoguer::Input::<String>::new().with_prompt("Press Enter to go back").allow_empty(true).interact_text();
+            }
+            Err(e) => status = format!("Failed to run cf-switch: {}", e),
+        }
+    }
+    let config = load_config(ctx);
+    if config.current != started_on {
+        output_source_command(ctx, &config);
+    }
+}
+
+/// Count (or, undoing a failed switch, uncount) a switch to `name`
+fn adjust_use_count(config: &mut Config, name: &str, up: bool) {
+    if let Some(profile) = config.profiles.get_mut(name) {
+        profile.use_count = if up { profile.use_count.saturating_add(1) } else { profile.use_count.saturating_sub(1) };
+    }
+}
+
+/// Argument that makes cf-switch print profile names one per line for
+/// completion scripts. Not a clap subcommand, which would show up in the
+/// generated completions themselves.
+const COMPLETE_PROFILES: &str = "__complete-profiles";
+
+fn main() {
+    if std::env::args().nth(1).as_deref() == Some(COMPLETE_PROFILES) {
+        let ctx = Ctx {
+            strict: false,
+            explain: false,
+            no_write: true,
+            local_config_path: find_local_config(),
+            config_path: config_path(std::env::var_os("CF_SWITCH_CONFIG").map(PathBuf::from)),
+            profile: None,
+            profile_stub: false,
+            pretty_json: None,
+            redaction: None,
+            print_source: true,
+            count_use: true,
+            pin: None,
+            outcome: None,
+            events: false,
+            symbols: theme::symbols(false),
+        };
+        for name in ordered_profile_names(&load_config(&ctx)) {
+            println!("{}", name);
+        }
+        return;
+    }
+    let cli = Cli::parse();
+    if cli.mask_in_logs {
+        mask::enable();
+    }
+    // Messages go to stderr, while stdout is usually captured by the shell
+    // wrapper, so decide on color from stderr rather than colored's stdout check
+    let env = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty());
+    let color = if cli.no_color { ColorChoice::Never } else { cli.color };
+    colored::control::set_override(match color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto if env("NO_COLOR").is_some() => false,
+        ColorChoice::Auto if env("CLICOLOR_FORCE").is_some_and(|v| v != "0") => true,
+        ColorChoice::Auto => io::stderr().is_terminal(),
+    });
+    if cli.profile_stub && !matches!(cli.command, Some(Commands::Purge { .. } | Commands::AddLamderaApp { .. } | Commands::DnsFromFile { .. })) {
+        msg!("{} --profile-stub only works with purge, add-lamdera-app and dns-from-file.", "Error:".red().bold());
+        std::process::exit(1);
+    }
+    api::configure_retries(api::RetryPolicy { retries: cli.retries, backoff: Duration::from_millis(cli.backoff) });
+    http::configure_timeout(Duration::from_secs(cli.timeout));
+    let ctx = Ctx {
+        strict: cli.strict,
+        explain: cli.explain,
+        no_write: cli.no_write,
+        local_config_path: if cli.global || cli.config_file.is_some() { None } else { find_local_config() },
+        config_path: config_path(cli.config_file),
+        profile: cli.profile,
+        profile_stub: cli.profile_stub,
+        pretty_json: if cli.pretty { Some(true) } else if cli.compact { Some(false) } else { None },
+        redaction: if cli.reveal {
+            Some(mask::Redaction::None)
+        } else if cli.redact {
+            Some(mask::Redaction::Full)
+        } else {
+            None
+        },
+        print_source: true,
+        count_use: true,
+        pin: cli.pin,
+        outcome: None,
+        events: false,
+        symbols: theme::symbols(cli.ascii),
+    };
+
+    match cli.command {
+        // No command = toggle to next profile
+        None => cycle_profiles(&ctx, 1, true, cli.verify, cli.env_out.as_deref()),
+
+        Some(Commands::Next { count, verify, env_out }) => cycle_profiles(&ctx, count, false, verify, env_out.as_deref()),
+
+        Some(Commands::List { verbose, filter, group_by, check, json, select, active_only, detect_duplicates, domain, oneline, limit, offset, no_pager, zones_resolved, sort, unused, days }) => {
+            let select = select.as_deref().map(parse_select_or_exit);
+            let config = load_config(&ctx);
+            if config.profiles.is_empty() && json {
+                print_json(&ctx, &serde_json::json!([]), select.as_ref());
+                if active_only {
+                    std::process::exit(1);
+                }
+                return;
+            }
+            if config.profiles.is_empty() && active_only {
+                std::process::exit(1);
+            }
+            if config.profiles.is_empty() {
+                msg!("{}", "No profiles configured.".yellow());
+                msg!("Add one with: cf-switch add <name> -e <email> -t <token>");
+                return;
+            }
+            let filter = filter.map(|f| {
+                ProfileFilter::parse(&f).unwrap_or_else(|e| {
+                    msg!("{} Invalid filter regex: {}", "Error:".red().bold(), e);
+                    std::process::exit(1);
+                })
+            });
+            let mut names: Vec<_> = config
+                .profiles
+                .iter()
+                .filter(|(name, profile)| filter.as_ref().is_none_or(|f| f.matches(name, profile)))
+                .filter(|(name, _)| !active_only || config.current.as_ref() == Some(*name))
+                .filter(|(_, profile)| domain.as_ref().is_none_or(|d| profile.in_email_domain(d)))
+                .filter(|(_, profile)| !unused || profile.unused(days).is_some())
+                .map(|(name, _)| name)
+                .collect();
+            // No active profile is the answer --active-only exists to give, not a problem to report
+            if names.is_empty() && active_only && !json {
+                std::process::exit(1);
+            }
+            if names.is_empty() && !json && unused {
+                msg!("{}", "No unused profiles.".green());
+                return;
+            }
+            if names.is_empty() && !json {
+                msg!("{}", "No profiles match the filter.".yellow());
+                return;
+            }
+            names.sort();
+            if sort == ListSort::Usage {
+                let ranked = names_by_usage(&config, names);
+                names = ranked.iter().map(|n| config.profiles.get_key_value(n).expect("listed profile exists").0).collect();
+            }
+            let total = names.len();
+            names = names.into_iter().skip(offset).take(limit.unwrap_or(usize::MAX)).collect();
+            if names.is_empty() && !json {
+                msg!("{}", format!("No profiles past --offset {} (there are {}).", offset, total).yellow());
+                return;
+            }
+            let checks = if check {
+                msg!("{} Checking {} token(s)...", ctx.symbols.arrow.cyan(), names.len());
+                check_profile_tokens(&config, &names)
+            } else {
+                HashMap::new()
+            };
+            let resolved = if zones_resolved {
+                msg!("{} Looking up zones for {} profile(s)...", ctx.symbols.arrow.cyan(), names.len());
+                resolve_profile_zones(&config, &names)
+            } else {
+                HashMap::new()
+            };
+            let failing = checks.values().filter(|c| !c.is_ok()).count();
+            let duplicates = if detect_duplicates { duplicate_tokens(&config, &names) } else { Vec::new() };
+            if json {
+                let profiles: Vec<serde_json::Value> = names
+                    .iter()
+                    .map(|name| {
+                        let profile = &config.profiles[*name];
+                        let mut entry = serde_json::json!({
+                            "name": name,
+                            "email": profile.email,
+                            "email_domain": profile.email_domain(),
+                            "zone": profile.zone,
+                            "description": profile.description,
+                            "account_id": profile.account_id,
+                            "tags": profile.tags,
+                            "aliases": profile.aliases,
+                            "auth": profile.auth_mode().to_string(),
+                            "backend": profile.backend.to_string(),
+                            "expires_at": profile.expires_at,
+                            "use_count": profile.use_count,
+                            "last_used": profile.last_used,
+                            "allow_purge_everything": profile.allows_purge_everything(),
+                            "active": config.current.as_ref() == Some(*name),
+                        });
+                        if let Some(check) = checks.get(*name) {
+                            entry["token_status"] = match check {
+                                TokenCheck::Status(status) => status.clone(),
+                                TokenCheck::Error(e) => format!("error: {}", e),
+                                TokenCheck::NotChecked => "not_checked".to_string(),
+                            }
+                            .into();
+                        }
+                        match resolved.get(*name) {
+                            None => {}
+                            Some(Ok(zones)) => {
+                                entry["zones"] = zones
+                                    .iter()
+                                    .map(|z| serde_json::json!({ "name": z.name, "status": z.status, "plan": z.plan }))
+                                    .collect();
+                            }
+                            Some(Err(e)) => entry["zones_error"] = e.clone().into(),
+                        }
+                        if detect_duplicates {
+                            let group = duplicates.iter().find(|group| group.contains(name));
+                            let others: Vec<&&String> = group.into_iter().flatten().filter(|n| *n != name).collect();
+                            entry["shares_token_with"] = serde_json::json!(others);
+                        }
+                        entry
+                    })
+                    .collect();
+                print_json(&ctx, &serde_json::Value::Array(profiles), select.as_ref());
+                if failing > 0 || (active_only && names.is_empty()) || (ctx.strict && !duplicates.is_empty()) {
+                    std::process::exit(1);
+                }
+                return;
+            }
+            let shown = names.len();
+            if oneline {
+                for name in &names {
+                    let profile = &config.profiles[*name];
+                    let active = if config.current.as_ref() == Some(*name) { "active" } else { "-" };
+                    println!("{}\t{}\t{}\t{}", name, profile.email, profile.zone.as_deref().unwrap_or("-"), active);
+                }
+            } else {
+                if !no_pager {
+                    pager::start();
+                }
+                msg!("{}", "Cloudflare Profiles:".bold());
+            }
+            match group_by {
+                None if oneline => {}
+                None => {
+                    for name in names {
+                        print_profile_row(&ctx, &config, name, &config.profiles[name], verbose, "", checks.get(name), resolved.get(name));
+                    }
+                }
+                Some(group_by) => {
+                    // BTreeMap keeps group headers in a stable order; names are already sorted
+                    let mut groups: BTreeMap<String, Vec<&String>> = BTreeMap::new();
+                    for name in names {
+                        for group in profile_groups(&config.profiles[name], group_by) {
+                            groups.entry(group).or_default().push(name);
+                        }
+                    }
+                    for (group, members) in groups {
+                        msg!("{}", group.bold());
+                        for name in members {
+                            print_profile_row(&ctx, &config, name, &config.profiles[name], verbose, "  ", checks.get(name), resolved.get(name));
+                        }
+                    }
+                }
+            }
+            if shown < total {
+                msg!("{}", format!("Showing {}-{} of {} profiles", offset + 1, offset + shown, total).dimmed());
+            }
+            pager::finish();
+            if detect_duplicates {
+                msg!("");
+                if duplicates.is_empty() {
+                    msg!("{} No two profiles share a token", ctx.symbols.ok.green());
+                }
+                for group in &duplicates {
+                    let token = &config.profiles[group[0]].token;
+                    let names: Vec<&str> = group.iter().map(|n| n.as_str()).collect();
+                    msg!("{} Same token ({}) in: {}", "!".yellow().bold(), mask::redact(token, redaction(&ctx, &config)), names.join(", "));
+                }
+            }
+            if failing > 0 {
+                msg!("");
+                msg!("{} {} of {} token(s) are not active", "!".yellow().bold(), failing, checks.len());
+                std::process::exit(1);
+            }
+            if ctx.strict && !duplicates.is_empty() {
+                std::process::exit(1);
+            }
+        }
+
+        Some(Commands::Add { name, email, token, token_env, from_env_file, backend, auth, zone, description, account_id, tags, verify, local, ephemeral: _, ttl, json, mutation }) => {
+            let ctx = Ctx { outcome: json.then(|| outcome::Outcome::new("add", &name)), ..ctx };
+            let mut config = load_config(&ctx);
+            let expires_at = ttl.map(|ttl| match audit::parse_duration(&ttl) {
+                Some(secs) if secs > 0 => now_secs() + secs,
+                _ => {
+                    let message = format!("--ttl '{}' isn't a duration like 30m, 8h or 2d.", ttl);
+                    msg!("{} {}", "Error:".red().bold(), message);
+                    ctx.fail("invalid", &message);
+                }
+            });
+            let local_path = local.then(|| local_config_for_add_or_exit(&ctx));
+            if config.profiles.contains_key(&name) && (!local || config.is_local(&name)) {
+                msg!("{} Profile '{}' already exists.", "Error:".red().bold(), name);
+                ctx.fail("exists", &format!("profile '{}' already exists", name));
+            }
+            if let Some(owner) = config.alias_owner(&name) {
+                msg!("{} '{}' is an alias of profile '{}'; remove it first with: cf-switch unalias {}", "Error:".red().bold(), name, owner, name);
+                ctx.fail("exists", &format!("'{}' is an alias of profile '{}'", name, owner));
+            }
+            // A hand-maintained flarectl env file (or the one cf-switch writes) as a starting point
+            let (email, token, auth, zone) = match from_env_file {
+                Some(path) => {
+                    let path = path.unwrap_or_else(env_path);
+                    let vars = read_env_file_or_exit(&ctx, &path);
+                    let Some((secret, secret_auth)) = env_file_secret(&vars) else {
+                        let message = format!("{} does not set CF_API_TOKEN or CF_API_KEY.", path.display());
+                        msg!("{} {}", "Error:".red().bold(), message);
+                        ctx.fail("invalid", &message);
+                    };
+                    let value = |key: &str| vars.get(key).filter(|v| !v.is_empty()).cloned();
+                    (email.or_else(|| value("CF_API_EMAIL")), Some(secret), auth.or(Some(secret_auth)), zone.or_else(|| value("CF_ZONE")))
+                }
+                None => (email, token, auth, zone),
+            };
+            let zone = zone.map(|z| zone_arg_or_exit(&ctx, &z));
+            let interactive = io::stdin().is_terminal();
+            let token = token.or_else(|| token_env.as_deref().map(|var| token_from_env_or_exit(&ctx, var)));
+            // Prompting keeps the token out of argv and shell history
+            let token = match (backend, token) {
+                (Backend::Plaintext | Backend::Keychain, None) if interactive => {
+                    Some(prompt_or_exit(&ctx, dialoguer::Password::new().with_prompt("API token").interact()))
+                }
+                (_, token) => token,
+            };
+            if token.is_none() && backend != Backend::Env {
+                msg!("{} --token is required with the {} backend (prompting needs a TTY).", "Error:".red().bold(), backend);
+                ctx.fail("invalid", "--token is required");
+            }
+            let needs_email = auth.or_else(|| token.as_deref().and_then(AuthMode::guess)) == Some(AuthMode::Key);
+            let email = match email {
+                Some(email) => email,
+                None if !needs_email => String::new(),
+                None if interactive => prompt_or_exit(&ctx, dialoguer::Input::<String>::new().with_prompt("Email").interact_text()),
+                None => {
+                    msg!("{} --email is required for a global API key (prompting needs a TTY).", "Error:".red().bold());
+                    ctx.fail("invalid", "--email is required");
+                }
+            };
+            let mut builder = ProfileBuilder::new(&name)
+                .email(&email)
+                .backend(backend)
+                .auth(auth)
+                .zone(zone)
+                .description(description)
+                .account_id(account_id)
+                .tags(tags)
+                .expires_at(expires_at);
+            if let Some(token) = &token {
+                builder = builder.token(token);
+            }
+            let mut profile = build_profile_or_exit(&ctx, builder);
+            if verify {
+                let secret = if backend == Backend::Env {
+                    credentials::EnvStore.get(&name).unwrap_or_else(|e| {
+                        msg!("{} Can't verify: {}", "Error:".red().bold(), e);
+                        ctx.fail("backend", &e.to_string());
+                    })
+                } else {
+                    profile.token.clone()
+                };
+                if !check_token(&ctx, &name, &secret, profile.auth_mode(), "not adding the profile") {
+                    ctx.fail("verify_failed", "the token didn't pass verification");
+                }
+                // A token scoped to a single zone tells us the obvious default
+                if profile.zone.is_none() && profile.auth_mode() == AuthMode::Token {
+                    if let Ok(zones) = api::zone_names(&secret, 2) {
+                        if let [only] = zones.as_slice() {
+                            msg!("{} Token can only see {}; using it as the default zone", ctx.symbols.arrow.cyan(), only.bold());
+                            profile.zone = Some(only.clone());
+                        }
+                    }
+                }
+            }
+            let action = match &profile.zone {
+                Some(z) => format!("add profile '{}' ({}, zone {})", name, profile.email_label(), z),
+                None => format!("add profile '{}' ({})", name, profile.email_label()),
+            };
+            if !mutation.confirm(&ctx, &action, false) {
+                ctx.succeed(serde_json::json!({ "dry_run": true }));
+                return;
+            }
+            // Keychain tokens are handed off to the OS and never written to the config
+            if backend == Backend::Keychain {
+                let token = std::mem::take(&mut profile.token);
+                if ctx.no_write {
+                    msg!("{} would store the token in the keychain", "[no-write]".dimmed());
+                } else if let Err(e) = credentials::KeychainStore.set(&name, &token) {
+                    msg!("{} Failed to store the token in the keychain: {}", "Error:".red().bold(), e);
+                    ctx.fail("backend", &format!("failed to store the token in the keychain: {}", e));
+                }
+            }
+            if let Some(path) = &local_path {
+                config.mark_local(path, &name);
+            }
+            let zone = profile.zone.clone();
+            config.profiles.insert(name.clone(), profile);
+            save_config(&ctx, &config);
+            audit::record(audit::Entry::new("add", true).profile(&name));
+
+            let zone_shown = zone.clone();
+            if let Some(z) = zone {
+                msg!("{} Added profile '{}' with zone '{}'", ctx.symbols.ok.green(), name.cyan(), z);
+            } else {
+                msg!("{} Added profile '{}'", ctx.symbols.ok.green(), name.cyan());
+            }
+            if let Some(path) = local_path {
+                msg!("Saved to the project config {}", path.display());
+            }
+            if backend == Backend::Env {
+                msg!("Token will be read from {} when this profile is used.", credentials::EnvStore::var_name(&name));
+            }
+            if let Some(expires_at) = expires_at {
+                msg!("Ephemeral: removed after {} UTC", audit::format_time(expires_at));
+            }
+            ctx.succeed(serde_json::json!({ "zone": zone_shown, "expires_at": expires_at }));
+        }
+
+        Some(Commands::Edit { name, email, token, token_env, auth, zone, description, account_id, tags, allow_purge_everything, json, mutation }) => {
+            let ctx = Ctx { outcome: json.then(|| outcome::Outcome::new("edit", &name)), ..ctx };
+            let mut config = load_config(&ctx);
+            let Some(name) = match_profile_name(&config, &name) else {
+                ctx.fail("ambiguous", &format!("'{}' matches several profiles", name));
+            };
+            let ctx = Ctx { outcome: json.then(|| outcome::Outcome::new("edit", &name)), ..ctx };
+            let Some(before) = config.profiles.get(&name).cloned() else {
+                msg!("{} Profile '{}' not found.", "Error:".red().bold(), name);
+                ctx.fail("not_found", &format!("profile '{}' not found", name));
+            };
+            let token = token.or_else(|| token_env.as_deref().map(|var| token_from_env_or_exit(&ctx, var)));
+            let nothing_given = email.is_none() && token.is_none() && auth.is_none() && zone.is_none()
+                && description.is_none() && account_id.is_none() && tags.is_none() && allow_purge_everything.is_none();
+            if nothing_given {
+                msg!(
+                    "{} Nothing to change; pass at least one of --email, --token, --auth, --zone, --desc, --account-id, --tag or --allow-purge-everything.",
+                    "Error:".red().bold()
+                );
+                ctx.fail("invalid", "nothing to change");
+            }
+            let cleared = |value: String| Some(value.trim().to_string()).filter(|v| !v.is_empty());
+
+            let mut builder = ProfileBuilder::from_profile(&name, before.clone());
+            if let Some(email) = &email {
+                builder = builder.email(email);
+            }
+            if let Some(token) = &token {
+                builder = builder.token(token);
+            }
+            if auth.is_some() {
+                builder = builder.auth(auth);
+            }
+            if let Some(zone) = zone {
+                builder = builder.zone(cleared(zone).map(|z| zone_arg_or_exit(&ctx, &z)));
+            }
+            if let Some(description) = description {
+                builder = builder.description(cleared(description));
+            }
+            if let Some(account_id) = account_id {
+                builder = builder.account_id(cleared(account_id));
+            }
+            if let Some(tags) = tags {
+                builder = builder.tags(tags.into_iter().filter_map(cleared).collect());
+            }
+            if let Some(allow) = allow_purge_everything {
+                builder = builder.allow_purge_everything(allow);
+            }
+            let mut after = build_profile_or_exit(&ctx, builder);
+            let new_secret = token.is_some().then(|| after.token.clone());
+            if !before.backend.is_plaintext() {
+                after.token = before.token.clone();
+            }
+
+            // Keychain tokens live outside the profile, so compare the secrets themselves
+            let old_secret = with_secret(&name, &before).map(|p| p.token).unwrap_or_default();
+            let changes = profile_changes(&before, &after, &old_secret, new_secret.as_deref().unwrap_or(&old_secret), redaction(&ctx, &config));
+            let report = serde_json::json!({ "changes": changes });
+            if !json && changes.is_empty() {
+                msg!("Profile '{}' already has those values; nothing changed.", name);
+            } else if !json {
+                msg!("{} '{}':", "Changes to".bold(), name.cyan());
+                for change in &changes {
+                    let show = |value: &Option<String>| value.clone().unwrap_or_else(|| "(none)".to_string());
+                    msg!("  {}: {} {} {}", change.field, show(&change.old).red(), ctx.symbols.arrow, show(&change.new).green());
+                }
+            }
+            if changes.is_empty() {
+                ctx.succeed(report);
+                return;
+            }
+            if !mutation.confirm(&ctx, &format!("update profile '{}'", name), false) {
+                ctx.succeed(serde_json::json!({ "changes": changes, "dry_run": true }));
+                return;
+            }
+
+            if let (Some(secret), Backend::Keychain) = (&new_secret, before.backend) {
+                if ctx.no_write {
+                    msg!("{} would store the token in the keychain", "[no-write]".dimmed());
+                } else if let Err(e) = credentials::KeychainStore.set(&name, secret) {
+                    msg!("{} Failed to store the token in the keychain: {}", "Error:".red().bold(), e);
+                    ctx.fail("backend", &format!("failed to store the token in the keychain: {}", e));
+                }
+            }
+            config.profiles.insert(name.clone(), after.clone());
+            // Keep the env file in step when the active profile's credentials change
+            let env = (config.current.as_ref() == Some(&name)).then(|| env_files(&config, &with_secret_or_exit(&ctx, &name, &after), &name));
+            if let Some(files) = &env {
+                config.record_env_files(files);
+            }
+            save_config(&ctx, &config);
+            let fields: Vec<&str> = changes.iter().map(|c| c.field).collect();
+            audit::record(audit::Entry::new("edit", true).profile(&name).detail(&fields.join(", ")));
+            msg!("{} Updated profile '{}'", ctx.symbols.ok.green(), name.cyan());
+
+            if let Some(files) = env {
+                write_env_files(&ctx, &files);
+                msg!("{} Synced {}", ctx.symbols.ok.green(), env_file_list(&config));
+                if !json {
+                    output_source_command(&ctx, &config);
+                }
+            }
+            ctx.succeed(report);
+        }
+
+        Some(Commands::Clone { source, name, zone, mutation }) => {
+            let mut config = load_config(&ctx);
+            let zone = zone.map(|z| zone_arg_or_exit(&ctx, &z));
+            let Some(source) = match_profile_name(&config, &source) else {
+                std::process::exit(1);
+            };
+            let Some(original) = config.profiles.get(&source) else {
+                msg!("{} Profile '{}' not found.", "Error:".red().bold(), source);
+                std::process::exit(1);
+            };
+            if config.profiles.contains_key(&name) {
+                msg!("{} Profile '{}' already exists.", "Error:".red().bold(), name);
+                std::process::exit(1);
+            }
+            check_not_alias_or_exit(&config, &name);
+            let profile = build_profile_or_exit(
+                &ctx,
+                ProfileBuilder::from_profile(&name, Profile { aliases: Vec::new(), use_count: 0, last_used: None, ..original.clone() })
+                    .zone(zone.or_else(|| original.zone.clone()))
+                    .description(None),
+            );
+            if !mutation.confirm(&ctx, &format!("copy '{}' into new profile '{}'", source, name), false) {
+                return;
+            }
+            // Keychain entries are keyed by profile name, so the secret needs its own copy
+            if profile.backend == Backend::Keychain && !ctx.no_write {
+                let copied = credentials::KeychainStore
+                    .get(&source)
+                    .and_then(|secret| credentials::KeychainStore.set(&name, &secret));
+                if let Err(e) = copied {
+                    msg!("{} Failed to copy the keychain token: {}", "Error:".red().bold(), e);
+                    std::process::exit(1);
+                }
+            }
+            let backend = profile.backend;
+            let zone = profile.zone.clone();
+            config.profiles.insert(name.clone(), profile);
+            save_config(&ctx, &config);
+
+            if let Some(z) = zone {
+                msg!("{} Cloned '{}' into '{}' with zone '{}'", ctx.symbols.ok.green(), source, name.cyan(), z);
+            } else {
+                msg!("{} Cloned '{}' into '{}'", ctx.symbols.ok.green(), source, name.cyan());
+            }
+            if backend == Backend::Env {
+                msg!("Token will be read from {} when this profile is used.", credentials::EnvStore::var_name(&name));
+            }
+        }
+
+        Some(Commands::Alias { profile, alias, mutation }) => {
+            let mut config = load_config(&ctx);
+            let Some(name) = match_profile_name(&config, &profile) else {
+                std::process::exit(1);
+            };
+            if !config.profiles.contains_key(&name) {
+                msg!("{} Profile '{}' not found.", "Error:".red().bold(), name);
+                std::process::exit(1);
+            }
+            let alias = alias.trim().to_string();
+            if alias.is_empty() || alias.starts_with('-') || alias.chars().any(char::is_whitespace) {
+                msg!("{} '{}' can't be an alias: it must be non-empty, without spaces, and not start with '-'.", "Error:".red().bold(), alias);
+                std::process::exit(1);
+            }
+            if config.profiles.contains_key(&alias) {
+                msg!("{} '{}' is already a profile name.", "Error:".red().bold(), alias);
+                std::process::exit(1);
+            }
+            if let Some(owner) = config.alias_owner(&alias) {
+                msg!("{} '{}' is already an alias of profile '{}'.", "Error:".red().bold(), alias, owner);
+                std::process::exit(1);
+            }
+            if !mutation.confirm(&ctx, &format!("add alias '{}' for profile '{}'", alias, name), false) {
+                return;
+            }
+            config.profiles.get_mut(&name).expect("profile exists").aliases.push(alias.clone());
+            save_config(&ctx, &config);
+            audit::record(audit::Entry::new("edit", true).profile(&name).detail(&format!("alias {}", alias)));
+            msg!("{} '{}' now also answers to '{}'", ctx.symbols.ok.green(), name.cyan(), alias);
+        }
+
+        Some(Commands::Unalias { alias, mutation }) => {
+            let mut config = load_config(&ctx);
+            let Some(name) = config.alias_owner(&alias).cloned() else {
+                msg!("{} '{}' isn't an alias of any profile.", "Error:".red().bold(), alias);
+                std::process::exit(1);
+            };
+            if !mutation.confirm(&ctx, &format!("remove alias '{}' from profile '{}'", alias, name), false) {
+                return;
+            }
+            config.profiles.get_mut(&name).expect("alias owner exists").aliases.retain(|a| *a != alias);
+            save_config(&ctx, &config);
+            audit::record(audit::Entry::new("edit", true).profile(&name).detail(&format!("unalias {}", alias)));
+            msg!("{} Removed alias '{}' from '{}'", ctx.symbols.ok.green(), alias, name.cyan());
+        }
+
+        Some(Commands::Remove { unused: true, days, json, mutation, .. }) => {
+            let ctx = Ctx { outcome: json.then(|| outcome::Outcome::new("remove", "")), ..ctx };
+            let mut config = load_config(&ctx);
+            // The active profile is in use right now, whatever its record says
+            let mut picked: Vec<(String, Unused)> = config
+                .profiles
+                .iter()
+                .filter(|(name, _)| config.current.as_ref() != Some(*name))
+                .filter_map(|(name, profile)| profile.unused(days).map(|why| (name.clone(), why)))
+                .collect();
+            picked.sort_by(|a, b| a.0.cmp(&b.0));
+            if picked.is_empty() {
+                msg!("{}", "No unused profiles to remove.".green());
+                ctx.succeed(serde_json::json!({ "removed": [] }));
+                return;
+            }
+            for (name, why) in &picked {
+                msg!("  {} ({})", name.cyan(), why);
+            }
+            let names: Vec<String> = picked.into_iter().map(|(name, _)| name).collect();
+            if names.len() > TRASH_SIZE {
+                msg!("{} The trash keeps {} profiles, so only the last {} removed can be restored.", "!".yellow().bold(), TRASH_SIZE, TRASH_SIZE);
+            }
+            if !mutation.confirm(&ctx, &format!("remove {} unused profile(s)", names.len()), true) {
+                ctx.succeed(serde_json::json!({ "dry_run": true, "removed": names }));
+                return;
+            }
+            for name in &names {
+                if let Some(removed) = config.profiles.remove(name) {
+                    trash_removed_profile(&ctx, &mut config, name, removed);
+                }
+            }
+            save_config(&ctx, &config);
+            for name in &names {
+                audit::record(audit::Entry::new("remove", true).profile(name));
+            }
+            msg!("{} Removed {} unused profile(s) (undo with: cf-switch restore <name>)", ctx.symbols.ok.green(), names.len());
+            ctx.succeed(serde_json::json!({ "removed": names }));
+        }
+
+        Some(Commands::Remove { name, keep_env, json, mutation, .. }) => {
+            let ctx = Ctx { outcome: json.then(|| outcome::Outcome::new("remove", name.as_deref().unwrap_or_default())), ..ctx };
+            let mut config = load_config(&ctx);
+            let typed = name.clone();
+            let Some(name) = resolve_profile_name(&config, name, "Profile to remove") else {
+                fail_unresolved(&ctx, typed.as_deref());
+            };
+            let ctx = Ctx { outcome: json.then(|| outcome::Outcome::new("remove", &name)), ..ctx };
+            if config.profiles.contains_key(&name) && !mutation.confirm(&ctx, &format!("remove profile '{}'", name), true) {
+                ctx.succeed(serde_json::json!({ "dry_run": true }));
+                return;
+            }
+            if let Some(removed) = config.profiles.remove(&name) {
+                trash_removed_profile(&ctx, &mut config, &name, removed);
+                let was_active = config.current.as_ref() == Some(&name);
+                if was_active {
+                    config.current = None;
+                }
+                let cleared = (was_active && !keep_env).then(|| cleared_env_files(&config));
+                if let Some(files) = &cleared {
+                    config.record_env_files(files);
+                }
+                save_config(&ctx, &config);
+                audit::record(audit::Entry::new("remove", true).profile(&name));
+                msg!("{} Removed profile '{}' (undo with: cf-switch restore {})", ctx.symbols.ok.green(), name, name);
+                if let Some(files) = cleared {
+                    match try_write_env_files(&ctx, &files) {
+                        Ok(()) => {
+                            msg!("{} Cleared credentials from {}", ctx.symbols.ok.green(), env_file_list(&config));
+                            output_source_command(&ctx, &config);
+                        }
+                        Err(e) => {
+                            msg!("{} Failed to clear {}: {}", "Error:".red().bold(), env_file_list(&config), e);
+                            hint_if_unwritable(&e, ENV_UNWRITABLE_HINT);
+                            ctx.fail("write_failed", &format!("failed to clear {}: {}", env_file_list(&config), e));
+                        }
+                    }
+                }
+                ctx.succeed(serde_json::json!({ "was_active": was_active }));
+            } else {
+                msg!("{} Profile '{}' not found.", "Error:".red().bold(), name);
+                ctx.fail("not_found", &format!("profile '{}' not found", name));
+            }
+        }
+
+        Some(Commands::Use { name, verify, format, shell, name_template, from_wrangler, from_git, env_out, json, and_run }) => {
+            let ctx = Ctx { outcome: json.then(|| outcome::Outcome::new("use", name.as_deref().unwrap_or_default())), ..ctx };
+            let mut config = load_config(&ctx);
+            let name = if from_wrangler {
+                Some(profile_from_wrangler_or_exit(&ctx, &config))
+            } else if from_git {
+                Some(profile_from_git_or_exit(&ctx, &config))
+            } else {
+                name
+            };
+            let typed = name.clone();
+            let Some(name) = resolve_profile_name(&config, name, "Switch to profile") else {
+                fail_unresolved(&ctx, typed.as_deref());
+            };
+            let ctx = Ctx { outcome: json.then(|| outcome::Outcome::new("use", &name)), ..ctx };
+            if json && !config.profiles.contains_key(&name) {
+                msg!("{} Profile '{}' not found.", "Error:".red().bold(), name);
+                ctx.fail("not_found", &format!("profile '{}' not found", name));
+            }
+            if verify && !verify_before_switch(&ctx, &config, &name) {
+                ctx.fail("verify_failed", "the token didn't pass verification");
+            }
+            if let Some(format) = format {
+                let Some(profile) = config.profiles.get(&name) else {
+                    msg!("{} Profile '{}' not found.", "Error:".red().bold(), name);
+                    std::process::exit(1);
+                };
+                let profile = with_secret_or_exit(&ctx, &name, profile);
+                let shell = shell.unwrap_or_else(detect_shell);
+                let naming = match name_template {
+                    Some(preset) => vec![preset.names()],
+                    None => config.env_var_names(),
+                };
+                for (key, value) in env_vars(&profile, &naming) {
+                    match format {
+                        OutputFormat::Env => cmd(&ctx, &export_line(&shell, &key, &value)),
+                        OutputFormat::ShellExport => cmd(&ctx, &plain_assignment(&key, &value)),
+                    }
+                }
+                return;
+            }
+            let ctx = Ctx { print_source: and_run.is_none(), ..ctx };
+            if !switch_to_profile(&ctx, &mut config, &name, env_out.as_deref()) {
+                ctx.fail("switch_failed", &format!("could not switch to '{}'", name));
+            }
+            ctx.succeed(serde_json::json!({ "previous": config.previous }));
+            if let Some(command) = and_run {
+                // The result is out; anything the command does is its own
+                run_with_profile(&Ctx { outcome: None, ..ctx }, &config, &name, &command);
+            }
+        }
+
+        Some(Commands::WatchExpiry { interval, within, api }) => {
+            let duration = |flag: &str, value: &str| match audit::parse_duration(value) {
+                Some(secs) if secs > 0 => secs,
+                _ => {
+                    msg!("{} {} '{}' isn't a duration like 30s, 5m or 1d.", "Error:".red().bold(), flag, value);
+                    std::process::exit(1);
+                }
+            };
+            let interval = Duration::from_secs(duration("--interval", &interval));
+            watch_expiry(&ctx, interval, duration("--within", &within), api);
+        }
+
+        Some(Commands::Log { since, until, zone, json }) => {
+            let config = load_config(&ctx);
+            let path = audit::path();
+            if !config.audit_log && !path.exists() {
+                msg!("{}", "Audit logging is off.".yellow());
+                msg!("Turn it on with \"audit_log\": true in {}", ctx.config_path.display());
+                return;
+            }
+            let time = |value: Option<String>, end_of_day: bool| {
+                value.map(|v| {
+                    audit::parse_time(&v, end_of_day).unwrap_or_else(|e| {
+                        msg!("{} {}", "Error:".red().bold(), e);
+                        std::process::exit(1);
+                    })
+                })
+            };
+            let filter = audit::Filter { since: time(since, false), until: time(until, true), profile: ctx.profile.clone(), zone };
+            let entries = audit::read(&path).unwrap_or_else(|e| {
+                msg!("{} Failed to read {}: {}", "Error:".red().bold(), path.display(), e);
+                std::process::exit(1);
+            });
+            let matching: Vec<_> = entries.into_iter().filter(|(_, entry)| filter.matches(entry)).collect();
+            if json {
+                for (line, _) in &matching {
+                    println!("{}", line);
+                }
+                return;
+            }
+            if matching.is_empty() {
+                msg!("{}", "No matching audit entries.".yellow());
+                return;
+            }
+            msg!("{}", format!("{:<19}  {:<6}  {:<16}  {:<24}  RESULT", "TIME (UTC)", "ACTION", "PROFILE", "ZONE").bold());
+            for (_, entry) in &matching {
+                let result = if entry.ok { "ok".green() } else { "failed".red() };
+                let detail = entry.detail.as_deref().map(|d| format!(" {}", d.lines().next().unwrap_or("").dimmed())).unwrap_or_default();
+                let build = entry.ci.as_ref().map(|b| format!(" {}", format!("[{}]", b.describe()).dimmed())).unwrap_or_default();
+                msg!(
+                    "{:<19}  {:<6}  {:<16}  {:<24}  {}{}{}",
+                    audit::format_time(entry.time),
+                    entry.action,
+                    entry.profile.as_deref().unwrap_or("-"),
+                    entry.zone.as_deref().unwrap_or("-"),
+                    result,
+                    detail,
+                    build
+                );
+            }
+        }
+
+        Some(Commands::Stats { json, domain }) => {
+            let config = load_config(&ctx);
+            let profiles = config.profiles.iter().filter(|(_, p)| domain.as_ref().is_none_or(|d| p.in_email_domain(d)));
+            let total = profiles.clone().count();
+            let with_zone = profiles.clone().filter(|(_, p)| p.zone.is_some()).count();
+            let key_auth = profiles.clone().filter(|(_, p)| p.auth_mode() == AuthMode::Key).count();
+            let mut backends: BTreeMap<String, usize> = BTreeMap::new();
+            let mut email_domains: BTreeMap<String, usize> = BTreeMap::new();
+            for (_, profile) in profiles.clone() {
+                *backends.entry(profile.backend.to_string()).or_default() += 1;
+                *email_domains.entry(profile.email_domain().unwrap_or_else(|| "(none)".to_string())).or_default() += 1;
+            }
+            let active = config.current.as_ref().filter(|c| profiles.clone().any(|(name, _)| name == *c));
+            if json {
+                let value = serde_json::json!({
+                    "profiles": total,
+                    "with_zone": with_zone,
+                    "auth": { "token": total - key_auth, "key": key_auth },
+                    "backends": backends,
+                    "email_domains": email_domains,
+                    "active": active,
+                });
+                println!("{}", ctx.json_string(&value, false));
+                return;
+            }
+            msg!("{}", "Cloudflare Profiles:".bold());
+            msg!("  profiles:       {}", total);
+            msg!("  default zone:   {}", with_zone);
+            msg!("  API token auth: {}", total - key_auth);
+            msg!("  global key:     {}", key_auth);
+            for (backend, count) in &backends {
+                msg!("  {:<15} {}", format!("{}:", backend), count);
+            }
+            if email_domains.len() > 1 {
+                msg!("  email domains:");
+                for (email_domain, count) in &email_domains {
+                    msg!("    {:<13} {}", format!("{}:", email_domain), count);
+                }
+            }
+            if let Some(name) = active {
+                msg!("  active:         {}", name.cyan());
+            } else {
+                msg!("  active:         {}", "none".dimmed());
+            }
+        }
+
+        Some(Commands::Current { json, select, verify_env }) => {
+            let select = select.as_deref().map(parse_select_or_exit);
+            let config = load_config(&ctx);
+            warn_modified_env_files(&config, false);
+            let drift = if verify_env { env_drift(&ctx, &config) } else { None };
+            let status = match &config.current {
+                Some(name) if config.profiles.contains_key(name) => 0,
+                Some(_) => CURRENT_MISSING_EXIT,
+                None => CURRENT_NONE_EXIT,
+            };
+            // A stale shell only fails an otherwise good answer; no profile or
+            // a missing one keeps its own code
+            let status = if status == 0 && drift.is_some() { 1 } else { status };
+            if verify_env && !json {
+                match &drift {
+                    Some(problem) => {
+                        msg!("{} {}", "!".yellow().bold(), problem);
+                        msg!("Re-source it: {}", source_line(&config));
+                    }
+                    None if config.current.is_none() => {
+                        msg!("{} No profile is active (and no credentials are exported)", "!".yellow().bold());
+                    }
+                    None => {
+                        msg!("{} This shell's credentials match the active profile", ctx.symbols.ok.green());
+                    }
+                }
+            }
+            if json {
+                let active = config
+                    .current
+                    .as_ref()
+                    .and_then(|name| config.profiles.get(name).map(|p| (name, p)));
+                let mut value = match active {
+                    Some((name, profile)) => serde_json::json!({
+                        "name": name,
+                        "email": profile.email,
+                        "zone": profile.zone,
+                        "active": true,
+                    }),
+                    None => serde_json::Value::Null,
+                };
+                if let (Some(object), Some(previous)) = (value.as_object_mut(), &config.previous) {
+                    object.insert("previous".to_string(), previous.clone().into());
+                }
+                if verify_env {
+                    if let Some(object) = value.as_object_mut() {
+                        object.insert("env_in_sync".to_string(), drift.is_none().into());
+                    }
+                }
+                print_json(&ctx, &value, select.as_ref());
+                if status != 0 {
+                    std::process::exit(status);
+                }
+                return;
+            }
+            if verify_env {
+                if status != 0 {
+                    std::process::exit(status);
+                }
+                return;
+            }
+            match config.current {
+                Some(name) => {
                     if let Some(profile) = config.profiles.get(&name) {
-                        msg!("{} {} ({})", "ON".green().bold(), name.cyan(), profile.email);
+                        msg!("{} {} ({})", ctx.symbols.on.green().bold(), name.cyan(), profile.email_label());
+                        if let Some(d) = &profile.description {
+                            msg!("   {}", d.dimmed());
+                        }
                     } else {
                         msg!("{}", "Current profile no longer exists.".yellow());
                     }
@@ -251,139 +5748,1430 @@ fn main() {
                     msg!("{}", "No profile currently active.".yellow());
                 }
             }
+            if let Some(previous) = &config.previous {
+                msg!("   {}", format!("(previously: {})", previous).dimmed());
+            }
+            if status != 0 {
+                std::process::exit(status);
+            }
+        }
+
+        Some(Commands::Back { verify }) => {
+            let mut config = load_config(&ctx);
+            let Some(name) = config.previous.clone() else {
+                msg!("{} No previous profile to go back to.", "Error:".red().bold());
+                std::process::exit(1);
+            };
+            if !config.profiles.contains_key(&name) {
+                msg!("{} The previous profile '{}' no longer exists.", "Error:".red().bold(), name);
+                std::process::exit(1);
+            }
+            if verify && !verify_before_switch(&ctx, &config, &name) {
+                std::process::exit(1);
+            }
+            if !switch_to_profile(&ctx, &mut config, &name, None) {
+                std::process::exit(1);
+            }
+        }
+
+        Some(Commands::Validate) => {
+            let mut valid = true;
+            for path in std::iter::once(&ctx.config_path).chain(&ctx.local_config_path) {
+                let content = config_text_or_exit(&ctx, path);
+                let problems = config_problems(&content);
+                if problems.is_empty() {
+                    msg!("{} {} is valid", ctx.symbols.ok.green(), path.display());
+                    continue;
+                }
+                valid = false;
+                msg!("{} {} problem(s) in {}:", "Error:".red().bold(), problems.len(), path.display());
+                for problem in &problems {
+                    msg!("  {} {}", ctx.symbols.fail.red(), problem);
+                }
+            }
+            if !valid {
+                std::process::exit(1);
+            }
+        }
+
+        Some(Commands::Encrypt) => {
+            let config = load_config(&ctx);
+            if vault::is_active() {
+                msg!("{} is already encrypted.", ctx.config_path.display());
+                return;
+            }
+            vault::set_passphrase(Some(passphrase_or_exit(&ctx, true)));
+            save_config(&ctx, &config);
+            msg!("{} Encrypted {}", ctx.symbols.ok.green(), ctx.config_path.display());
+            msg!("Commands will now ask for the passphrase, or read ${}.", vault::PASSPHRASE_VAR);
+        }
+
+        Some(Commands::Decrypt) => {
+            let config = load_config(&ctx);
+            if !vault::is_active() {
+                msg!("{} isn't encrypted.", ctx.config_path.display());
+                return;
+            }
+            vault::set_passphrase(None);
+            save_config(&ctx, &config);
+            msg!("{} Decrypted {}", ctx.symbols.ok.green(), ctx.config_path.display());
+        }
+
+        #[cfg(feature = "tui")]
+        Some(Commands::Tui) => run_tui(&ctx),
+
+        Some(Commands::Health { json, concurrency, profile_timeout, expiry_days }) => {
+            let config = load_config(&ctx);
+            if config.profiles.is_empty() {
+                msg!("{}", "No profiles configured.".yellow());
+                return;
+            }
+            let deadline = profile_timeout.map_or(http::timeout() * 2, Duration::from_secs);
+            if !json {
+                msg!("{} Checking {} profile(s)...", ctx.symbols.arrow.cyan(), config.profiles.len());
+            }
+            let report = fleet_health(&config, concurrency as usize, deadline, expiry_days);
+            let failing = report.iter().filter(|h| !h.problems.is_empty()).count();
+            if json {
+                let profiles: Vec<serde_json::Value> = report
+                    .iter()
+                    .map(|h| {
+                        serde_json::json!({
+                            "name": h.name,
+                            "ok": h.problems.is_empty(),
+                            "token_status": h.token.as_ref().ok(),
+                            "token_error": h.token.as_ref().err(),
+                            "expires_in_days": h.expires_in_days,
+                            "zones": h.zones.as_ref().and_then(|z| z.as_ref().ok()),
+                            "zones_truncated": h.zones.as_ref().is_some_and(|z| z.as_ref().is_ok_and(|n| *n >= HEALTH_ZONES_LIMIT)),
+                            "auth_mismatch": h.auth_mismatch.map(|m| m.to_string()),
+                            "problems": h.problems,
+                        })
+                    })
+                    .collect();
+                print_json(&ctx, &serde_json::json!({ "ok": failing == 0, "failing": failing, "profiles": profiles }), None);
+            } else {
+                let width = report.iter().map(|h| h.name.len()).max().unwrap_or(0).max(7);
+                msg!("{}", format!("{:<width$}  {:<10}  {:<8}  {:<6}  {:<5}  RESULT", "PROFILE", "TOKEN", "EXPIRES", "ZONES", "AUTH", width = width).bold());
+                for h in &report {
+                    let token = match &h.token {
+                        Ok(status) => status.clone(),
+                        Err(_) if h.problems.iter().any(|p| p.starts_with("token check failed")) => "error".to_string(),
+                        Err(_) => "global key".to_string(),
+                    };
+                    let expires = h.expires_in_days.map_or("-".to_string(), |d| format!("{}d", d));
+                    let zones = match &h.zones {
+                        Some(Ok(n)) if *n >= HEALTH_ZONES_LIMIT => format!("{}+", n),
+                        Some(Ok(n)) => n.to_string(),
+                        Some(Err(_)) => "error".to_string(),
+                        None => "-".to_string(),
+                    };
+                    let auth = if h.auth_mismatch.is_some() { "wrong" } else { "ok" };
+                    let result = if h.problems.is_empty() { "ok".green() } else { h.problems.join("; ").red() };
+                    msg!("{:<width$}  {:<10}  {:<8}  {:<6}  {:<5}  {}", h.name, token, expires, zones, auth, result, width = width);
+                }
+                if failing == 0 {
+                    msg!("{} All {} profile(s) healthy", ctx.symbols.ok.green(), report.len());
+                } else {
+                    msg!("{} {} of {} profile(s) failing", ctx.symbols.fail.red(), failing, report.len());
+                }
+            }
+            if failing > 0 {
+                std::process::exit(1);
+            }
+        }
+
+        Some(Commands::Doctor) => {
+            let config = load_config(&ctx);
+            let auth_ok = check_auth_modes(&ctx, &config);
+            let flarectl_ok = check_flarectl(&ctx, &config);
+            if !check_connectivity(&ctx) || !auth_ok || !flarectl_ok {
+                std::process::exit(1);
+            }
+        }
+
+        Some(Commands::Config { action: ConfigAction::Path }) => {
+            let config = load_config(&ctx);
+            msg!("{} {}", "Config:  ".bold(), ctx.config_path.display());
+            if let Some(local) = &ctx.local_config_path {
+                msg!("{} {}", "Project: ".bold(), local.display());
+            }
+            for format in config.env_formats() {
+                msg!("{} {}", "Env file:".bold(), format.path().display());
+            }
+        }
+
+        Some(Commands::Path { file }) => {
+            let path = match file {
+                PathFile::Env { shell } => env_file_for_shell(&load_config(&ctx), &shell.unwrap_or_else(detect_shell)),
+                PathFile::Config => ctx.config_path.clone(),
+            };
+            let path = std::path::absolute(&path).unwrap_or(path);
+            println!("{}", path.display());
+        }
+
+        Some(Commands::Config { action: ConfigAction::Migrate }) => {
+            let path = &ctx.config_path;
+            if !path.exists() {
+                msg!("No config file at {} yet; it'll be written at schema version {}.", path.display(), SCHEMA_VERSION);
+                return;
+            }
+            let content = config_text_or_exit(&ctx, path);
+            let version = serde_json::from_str::<serde_json::Value>(&content)
+                .ok()
+                .and_then(|v| v.get("schema_version").and_then(serde_json::Value::as_u64))
+                .unwrap_or(0);
+            if version == u64::from(SCHEMA_VERSION) {
+                msg!("{} {} is already at schema version {}.", ctx.symbols.ok.green(), path.display(), SCHEMA_VERSION);
+                return;
+            }
+            migrate_config_text(&ctx, path, content, true);
+        }
+
+        Some(Commands::Restore { name }) => {
+            let mut config = load_config(&ctx);
+            let index = match &name {
+                Some(name) => config.trash.iter().rposition(|(trashed, _)| trashed == name),
+                None => config.trash.len().checked_sub(1),
+            };
+            let Some(index) = index else {
+                match name {
+                    Some(name) => {
+                        msg!("{} No removed profile named '{}' (see: cf-switch trash list)", "Error:".red().bold(), name);
+                    }
+                    None => {
+                        msg!("{} The trash is empty.", "Error:".red().bold());
+                    }
+                }
+                std::process::exit(1);
+            };
+            let (name, mut profile) = config.trash.remove(index);
+            if config.profiles.contains_key(&name) {
+                msg!("{} A profile named '{}' already exists; rename or remove it first.", "Error:".red().bold(), name);
+                std::process::exit(1);
+            }
+            check_not_alias_or_exit(&config, &name);
+            // Aliases handed out while it was in the trash stay where they are
+            profile.aliases.retain(|alias| {
+                let taken = config.profiles.contains_key(alias) || config.alias_owner(alias).is_some();
+                if taken {
+                    msg!("{} Alias '{}' is taken now; dropped it", "!".yellow().bold(), alias);
+                }
+                !taken
+            });
+            config.profiles.insert(name.clone(), profile);
+            save_config(&ctx, &config);
+            audit::record(audit::Entry::new("restore", true).profile(&name));
+            msg!("{} Restored profile '{}'", ctx.symbols.ok.green(), name);
+        }
+
+        Some(Commands::Trash { action: TrashAction::List }) => {
+            let config = load_config(&ctx);
+            if config.trash.is_empty() {
+                msg!("{}", "The trash is empty.".yellow());
+                return;
+            }
+            for (name, profile) in config.trash.iter().rev() {
+                let secret = if profile.backend.is_plaintext() { mask::redact(&profile.token, redaction(&ctx, &config)) } else { format!("{} backend", profile.backend) };
+                msg!("  {} ({}) {}", name.cyan(), profile.email_label(), secret.dimmed());
+            }
+        }
+
+        Some(Commands::Config { action: ConfigAction::Show }) => {
+            let config = load_config(&ctx);
+            let mut json = serde_json::to_value(&config).expect("Failed to serialize config");
+            let mask_profile = |profile: &mut serde_json::Value| {
+                if let Some(token) = profile.get_mut("token") {
+                    if let Some(t) = token.as_str().filter(|t| !t.is_empty()) {
+                        *token = serde_json::Value::String(mask::redact(t, redaction(&ctx, &config)));
+                    }
+                }
+            };
+            if let Some(profiles) = json.get_mut("profiles").and_then(|p| p.as_object_mut()) {
+                profiles.values_mut().for_each(mask_profile);
+            }
+            if let Some(trash) = json.get_mut("trash").and_then(|t| t.as_array_mut()) {
+                trash.iter_mut().filter_map(|entry| entry.get_mut(1)).for_each(mask_profile);
+            }
+            println!("{}", ctx.json_string(&json, true));
+        }
+
+        Some(Commands::Sync) => {
+            let mut config = load_config(&ctx);
+            match config.current.clone() {
+                Some(name) => {
+                    if let Some(profile) = config.profiles.get(&name) {
+                        warn_modified_env_files(&config, true);
+                        let files = env_files(&config, &with_secret_or_exit(&ctx, &name, profile), &name);
+                        config.record_env_files(&files);
+                        save_config(&ctx, &config);
+                        write_env_files(&ctx, &files);
+                        msg!("{} Synced env file with profile '{}'", ctx.symbols.ok.green(), name.cyan());
+                        output_source_command(&ctx, &config);
+                    } else {
+                        msg!("{}", "Current profile no longer exists.".yellow());
+                        std::process::exit(1);
+                    }
+                }
+                None => {
+                    msg!("{}", "No profile currently active. Use 'cf-switch use <profile>' first.".yellow());
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Some(Commands::Completions { shell, dynamic, action }) => match action {
+            None => {
+                let shell = shell_or_detected(shell);
+                check_dynamic_completions(shell, dynamic);
+                write_completions(shell, dynamic, &mut io::stdout());
+            }
+            Some(CompletionsAction::Install { shell, force, dynamic }) => {
+                let shell = shell_or_detected(shell);
+                check_dynamic_completions(shell, dynamic);
+                let Some(path) = completion_install_path(shell) else {
+                    msg!("{} Don't know where {} completions go; use 'cf-switch completions {}' and install it manually.", "Error:".red().bold(), shell, shell);
+                    std::process::exit(1);
+                };
+                if path.exists() && !force {
+                    msg!("{} {} already exists. Use --force to overwrite.", "Error:".red().bold(), path.display());
+                    std::process::exit(1);
+                }
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).expect("Failed to create completions directory");
+                }
+                let mut script = Vec::new();
+                write_completions(shell, dynamic, &mut script);
+                fs::write(&path, script).expect("Failed to write completion script");
+                msg!("{} Wrote {} completions to {}", ctx.symbols.ok.green(), shell, path.display());
+                if shell == Shell::Zsh {
+                    msg!("Make sure ~/.zsh/completions is on your fpath, e.g. in ~/.zshrc:");
+                    msg!("  fpath=(~/.zsh/completions $fpath); autoload -Uz compinit; compinit");
+                }
+            }
+        },
+
+        Some(Commands::DnsFromFile { file, zone, env_file, bulk, output }) => {
+            let zone = zone.map(|z| zone_arg_or_exit(&ctx, &z));
+            let read_records = || {
+                let content = fs::read_to_string(&file).unwrap_or_else(|e| {
+                    msg!("{} Failed to read {}: {}", "Error:".red().bold(), file.display(), e);
+                    std::process::exit(1);
+                });
+                parse_dns_records(&content).unwrap_or_else(|e| {
+                    msg!("{} {}: {}", "Error:".red().bold(), file.display(), e);
+                    std::process::exit(1);
+                })
+            };
+            if ctx.profile_stub {
+                let z = stub_zone_or_exit(zone, "--zone");
+                for record in read_records() {
+                    print_flarectl_plan(&dns_create_args(&z, &record));
+                }
+                return;
+            }
+            let ctx = Ctx { events: output == OutputMode::Json, ..ctx };
+            let config = load_config(&ctx);
+            let (source, profile) = resolve_credentials(&ctx, &config, env_file.as_deref());
+            let Some(z) = zone.or_else(|| profile.zone.clone()) else {
+                msg!("{} No zone specified and {} has no default zone.", "Error:".red().bold(), source);
+                msg!("Usage: cf-switch dns-from-file <file> --zone <zone>");
+                std::process::exit(1);
+            };
+            let records = read_records();
+
+            msg!("{} Creating {} DNS records in {} using {}...", ctx.symbols.arrow.cyan(), records.len(), z.bold(), source);
+            let (mut created, mut existing) = (0, 0);
+            let mut results = BulkResults::new(&bulk);
+            let mut failure_kinds = Vec::new();
+            for record in &records {
+                let mut event = serde_json::json!({ "zone": z, "type": record.kind, "name": record.name, "content": record.content });
+                if results.stopping() {
+                    event["reason"] = "not_attempted".into();
+                    ctx.event("record_skipped", event);
+                    results.skip();
+                    continue;
+                }
+                let label = format!("{} {} -> {}{}", record.kind, record.name, record.content, if record.proxied { " (proxied)" } else { "" });
+                event["result"] = match create_dns_record(&profile, &z, None, record) {
+                    DnsOutcome::Created => {
+                        created += 1;
+                        msg!("{} {}", ctx.symbols.ok.green(), label);
+                        "created".into()
+                    }
+                    DnsOutcome::AlreadyExists => {
+                        existing += 1;
+                        msg!("{} {} (already exists)", ctx.symbols.ok.yellow(), label);
+                        "exists".into()
+                    }
+                    DnsOutcome::Failed(output) => {
+                        results.record(false);
+                        if let Some(kind) = ErrorKind::classify(&output).filter(|k| !failure_kinds.contains(k)) {
+                            failure_kinds.push(kind);
+                        }
+                        msg!("{} {}: {}", ctx.symbols.fail.red(), label, output.trim());
+                        event["error"] = output.trim().into();
+                        "failed".into()
+                    }
+                };
+                ctx.event("record_done", event);
+            }
+            results.report_stop();
+            ctx.event(
+                "summary",
+                serde_json::json!({
+                    "created": created,
+                    "existing": existing,
+                    "failed": results.failed,
+                    "not_attempted": results.not_attempted,
+                }),
+            );
+            msg!("");
+            msg!("{} created, {} already existed, {} failed{}", created, existing, results.failed, results.not_attempted_note());
+            for kind in failure_kinds {
+                explain_failure(&ctx, &source, Some(kind));
+            }
+            if results.failed > 0 {
+                std::process::exit(1);
+            }
+        }
+
+        Some(Commands::Reorder { order, reset, sort_by_usage, mutation }) => {
+            let mut config = load_config(&ctx);
+            let new_order = if reset || sort_by_usage {
+                config.order.clone()
+            } else if let Some(order) = order {
+                let order: Vec<String> = order
+                    .into_iter()
+                    .map(|n| n.trim().to_string())
+                    .filter(|n| !n.is_empty())
+                    .collect();
+                if let Some(unknown) = order.iter().find(|n| !config.profiles.contains_key(*n)) {
+                    msg!("{} Profile '{}' not found.", "Error:".red().bold(), unknown);
+                    std::process::exit(1);
+                }
+                order
+            } else {
+                if !io::stdin().is_terminal() {
+                    msg!(
+                        "{} Interactive reorder requires a TTY; pass --order a,b,c instead.",
+                        "Error:".red().bold()
+                    );
+                    std::process::exit(1);
+                }
+                let names = ordered_profile_names(&config);
+                match dialoguer::Sort::new()
+                    .with_prompt("Order profiles (space to pick up/drop, enter to save)")
+                    .items(&names)
+                    .interact_opt()
+                {
+                    Ok(Some(indices)) => indices.into_iter().map(|i| names[i].clone()).collect(),
+                    _ => {
+                        msg!("Cancelled.");
+                        return;
+                    }
+                }
+            };
+            // --sort-by-usage keeps any explicit order for if it's switched off again
+            config.order = if reset { Vec::new() } else { new_order };
+            config.order_by_usage = sort_by_usage;
+            if !mutation.confirm(&ctx, &format!("set the toggle order to {}", ordered_profile_names(&config).join(&format!(" {} ", ctx.symbols.arrow))), reset) {
+                return;
+            }
+            save_config(&ctx, &config);
+            msg!("{} Toggle order: {}", ctx.symbols.ok.green(), ordered_profile_names(&config).join(&format!(" {} ", ctx.symbols.arrow)));
+        }
+
+        Some(Commands::Export { redacted }) => {
+            let config = load_config(&ctx);
+            // A file to read and hand around, so pretty unless --compact
+            println!("{}", ctx.json_string(&exported_profiles(&config, redacted), true));
+            if redacted {
+                msg!("{} Redacted: tokens replaced with {} and emails masked. Not a backup.", ctx.symbols.ok.green(), REDACTED_TOKEN);
+            }
+        }
+
+        Some(Commands::Pull { url, prefix, mutation }) => {
+            let mut config = load_config(&ctx);
+            let body = if url.starts_with("http://") || url.starts_with("https://") {
+                msg!("{} Fetching shared profiles from {}...", ctx.symbols.arrow.cyan(), url);
+                let response = http::Request::get(&url).timeout(Duration::from_secs(15)).send().unwrap_or_else(|e| {
+                    msg!("{} Failed to fetch {}: {}", "Error:".red().bold(), url, e);
+                    std::process::exit(1);
+                });
+                if !(200..300).contains(&response.status) {
+                    msg!("{} {} answered HTTP {}", "Error:".red().bold(), url, response.status);
+                    std::process::exit(1);
+                }
+                response.body
+            } else {
+                fs::read_to_string(&url).unwrap_or_else(|e| {
+                    msg!("{} Failed to read {}: {}", "Error:".red().bold(), url, e);
+                    std::process::exit(1);
+                })
+            };
+            let (mut skeletons, redacted) = parse_skeletons(&body).unwrap_or_else(|e| {
+                msg!("{} Can't use the profiles from {}: {}", "Error:".red().bold(), url, e);
+                std::process::exit(1);
+            });
+            if redacted {
+                msg!("{} This is a redacted export: it has no tokens, and masked emails are left out.", "!".yellow().bold());
+            }
+            for skeleton in &mut skeletons {
+                skeleton.name = prefixed_name(prefix.as_deref(), skeleton.name.trim());
+            }
+
+            let (new, existing): (Vec<_>, Vec<_>) =
+                skeletons.into_iter().partition(|s| !config.profiles.contains_key(s.name.trim()));
+            for skeleton in &existing {
+                msg!("  {} {} (already set up, left as is)", "=".dimmed(), skeleton.name.trim());
+            }
+            if new.is_empty() {
+                msg!("{} Nothing new to add.", ctx.symbols.ok.green());
+                return;
+            }
+            let names: Vec<String> = new.iter().map(|s| s.name.trim().to_string()).collect();
+            if !mutation.confirm(&ctx, &format!("add {} profile(s): {}", new.len(), names.join(", ")), false) {
+                return;
+            }
+
+            let interactive = io::stdin().is_terminal();
+            let mut missing_tokens = Vec::new();
+            for skeleton in new {
+                let name = skeleton.name.trim().to_string();
+                let token = if interactive {
+                    let prompt = format!("API token for '{}' (blank to set later)", name);
+                    prompt_or_exit(&ctx, dialoguer::Password::new().with_prompt(prompt).allow_empty_password(true).interact())
+                } else {
+                    String::new()
+                };
+                let mut builder = ProfileBuilder::new(&name)
+                    .email(&skeleton.email)
+                    .zone(skeleton.zone)
+                    .description(skeleton.description)
+                    .account_id(skeleton.account_id)
+                    .tags(skeleton.tags);
+                if token.trim().is_empty() {
+                    missing_tokens.push(name.clone());
+                } else {
+                    builder = builder.token(&token);
+                }
+                let profile = build_profile_or_exit(&ctx, builder);
+                msg!("  {} {}", "+".green(), name.cyan());
+                config.profiles.insert(name, profile);
+            }
+            save_config(&ctx, &config);
+            msg!("{} Added {} profile(s) from {}", ctx.symbols.ok.green(), names.len(), url);
+            if !missing_tokens.is_empty() {
+                msg!(
+                    "No token yet for {}; add one with: cf-switch edit <name> -t <token>",
+                    missing_tokens.join(", ")
+                );
+            }
+        }
+
+        Some(Commands::ImportEnv { path, name, prefix, mutation }) => {
+            let name = prefixed_name(prefix.as_deref(), &name);
+            let mut config = load_config(&ctx);
+            if config.profiles.contains_key(&name) {
+                msg!("{} Profile '{}' already exists.", "Error:".red().bold(), name);
+                std::process::exit(1);
+            }
+            check_not_alias_or_exit(&config, &name);
+            let vars = read_env_file_or_exit(&ctx, &path);
+            let value = |key: &str| vars.get(key).filter(|v| !v.is_empty()).cloned();
+            let Some((token, auth)) = env_file_secret(&vars) else {
+                msg!("{} {} does not set CF_API_TOKEN or CF_API_KEY.", "Error:".red().bold(), path.display());
+                std::process::exit(1);
+            };
+            let Some(email) = value("CF_API_EMAIL") else {
+                msg!("{} {} does not set CF_API_EMAIL.", "Error:".red().bold(), path.display());
+                std::process::exit(1);
+            };
+            let profile = build_profile_or_exit(
+                &ctx,
+                ProfileBuilder::new(&name).email(&email).token(&token).auth(Some(auth)).zone(value("CF_ZONE")),
+            );
+            if !mutation.confirm(&ctx, &format!("import profile '{}' ({}) from {}", name, profile.email_label(), path.display()), false) {
+                return;
+            }
+            let zone = profile.zone.clone();
+            config.profiles.insert(name.clone(), profile);
+            save_config(&ctx, &config);
+            if let Some(z) = zone {
+                msg!("{} Imported profile '{}' from {} with zone '{}'", ctx.symbols.ok.green(), name.cyan(), path.display(), z);
+            } else {
+                msg!("{} Imported profile '{}' from {}", ctx.symbols.ok.green(), name.cyan(), path.display());
+            }
+        }
+
+        Some(Commands::Init { shell, auto, dry_run }) => {
+            let shell = shell_or_detected(shell);
+            let Some(rc) = rc_file_path(shell) else {
+                msg!("{} Don't know which rc file {} uses; run 'cf-switch hook' and add it manually.", "Error:".red().bold(), shell);
+                std::process::exit(1);
+            };
+            let existing = fs::read_to_string(&rc).unwrap_or_default();
+            if existing.contains(INIT_BEGIN) {
+                msg!("{} cf-switch is already set up in {}", ctx.symbols.ok.green(), rc.display());
+                return;
+            }
+            // Drop the script's "which rc file" comment; we're already in it
+            let script = hook_script(&shell.to_string(), None, auto);
+            let script = script.split_once('\n').map_or(script.as_str(), |(_, rest)| rest);
+            let block = format!("{}\n{}\n{}\n", INIT_BEGIN, script, INIT_END);
+            if dry_run || ctx.no_write {
+                msg!("Would append to {}:\n", rc.display());
+                msg!("{}", block);
+                return;
+            }
+            if let Some(parent) = rc.parent() {
+                fs::create_dir_all(parent).expect("Failed to create rc file directory");
+            }
+            let separator = if existing.is_empty() || existing.ends_with("\n\n") {
+                ""
+            } else if existing.ends_with('\n') {
+                "\n"
+            } else {
+                "\n\n"
+            };
+            let result = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&rc)
+                .and_then(|mut f| f.write_all(format!("{}{}", separator, block).as_bytes()));
+            if let Err(e) = result {
+                msg!("{} Failed to update {}: {}", "Error:".red().bold(), rc.display(), e);
+                std::process::exit(1);
+            }
+            msg!("{} Added the cfs wrapper{} to {}", ctx.symbols.ok.green(), if auto { " and auto-switch hook" } else { "" }, rc.display());
+            msg!("Reload your shell (or run: source {}) to start using cfs.", rc.display());
         }
 
-        Some(Commands::Hook) => {
+        Some(Commands::Hook { eval_style, auto }) => {
             let shell = detect_shell();
             msg!("Add this to your shell config:\n");
-            match shell.as_str() {
-                "fish" => {
-                    msg!("# ~/.config/fish/config.fish");
-                    msg!("function cfs");
-                    msg!("    cf-switch $argv | source");
-                    msg!("end");
+            msg!("{}", hook_script(&shell, eval_style, auto));
+        }
+
+        Some(Commands::Purge { zones, i_know_what_im_doing, force, zone_from_cwd, env_file, wait, wait_url, watch, api, parallel, hosts, prefixes, files, tags, sitemap, if_newer, if_newer_than_file, confirm, zone_id, output, require_zone, ci, bulk }) => {
+            let zones = if zone_from_cwd { vec![zone_from_cwd_or_exit(&ctx)] } else { zones };
+            let zones: Vec<String> = zones.iter().map(|z| zone_arg_or_exit(&ctx, z)).collect();
+            if ctx.profile_stub {
+                if api {
+                    msg!("{} --api purges talk to Cloudflare directly; there's no flarectl command to show.", "Error:".red().bold());
+                    std::process::exit(1);
                 }
-                _ => {
-                    msg!("# ~/.bashrc or ~/.zshrc");
-                    msg!("cfs() {{ eval \"$(cf-switch \"$@\")\"; }}");
+                if zones.is_empty() {
+                    stub_zone_or_exit(None, "zone to purge");
+                }
+                for z in &zones {
+                    print_flarectl_plan(&purge_everything_args(z));
                 }
+                return;
+            }
+            let zone_id = zone_id.map(|id| id.trim().to_lowercase());
+            if let Some(id) = zone_id.as_deref() {
+                if !api::is_zone_id(id) {
+                    msg!("{} --zone-id '{}' isn't a zone id (32 hex characters).", "Error:".red().bold(), id);
+                    std::process::exit(1);
+                }
+                if zones.len() > 1 {
+                    msg!("{} --zone-id purges one zone; give at most one zone name with it.", "Error:".red().bold());
+                    std::process::exit(1);
+                }
+            }
+            let ctx = Ctx { events: output == OutputMode::Json, ..ctx };
+            let mut config = load_config(&ctx);
+            let (source, profile) = resolve_credentials(&ctx, &config, env_file.as_deref());
+            if api && profile.auth_mode() == AuthMode::Key {
+                msg!("{} {} uses a global API key; purge --api needs a scoped API token.", "Error:".red().bold(), source);
+                std::process::exit(1);
             }
-        }
 
-        Some(Commands::Purge { zone }) => {
-            let config = load_config();
-            match config.current {
-                Some(name) => {
-                    if let Some(profile) = config.profiles.get(&name) {
-                        // Use provided zone or fall back to profile's default zone
-                        let target_zone = zone.or_else(|| profile.zone.clone());
-
-                        match target_zone {
-                            Some(z) => {
-                                msg!("{} Purging cache for {} using profile '{}'...", "→".cyan(), z.bold(), name.cyan());
-
-                                let output = Command::new("flarectl")
-                                    .env("CF_API_TOKEN", &profile.token)
-                                    .env_remove("CF_API_EMAIL")
-                                    .env_remove("CF_API_KEY")
-                                    .args(["zone", "purge", "--zone", &z, "--everything"])
-                                    .output();
-
-                                match output {
-                                    Ok(result) => {
-                                        if result.status.success() {
-                                            msg!("{} Cache purged for {}", "✓".green(), z.bold());
-                                        } else {
-                                            let stderr = String::from_utf8_lossy(&result.stderr);
-                                            msg!("{} Failed to purge: {}", "Error:".red().bold(), stderr);
-                                            std::process::exit(1);
-                                        }
-                                    }
-                                    Err(e) => {
-                                        msg!("{} Failed to run flarectl: {}", "Error:".red().bold(), e);
-                                        msg!("Make sure flarectl is installed: brew install cloudflare/cloudflare/flarectl");
-                                        std::process::exit(1);
-                                    }
-                                }
-                            }
-                            None => {
-                                msg!("{} No zone specified and profile '{}' has no default zone.", "Error:".red().bold(), name);
-                                msg!("Usage: cfs purge <zone> or set default zone with: cf-switch add <name> -e <email> -t <token> -z <zone>");
-                                std::process::exit(1);
-                            }
-                        }
-                    } else {
-                        msg!("{}", "Current profile no longer exists.".yellow());
-                        std::process::exit(1);
+            let build = ci.then(ci::detect).flatten();
+            match &build {
+                Some(build) => {
+                    msg!("{} CI build: {}", ctx.symbols.arrow.cyan(), build.describe());
+                }
+                None if ci => {
+                    msg!("{}", "Not running in CI; --ci has no effect.".dimmed());
+                }
+                None => {}
+            }
+            audit::triggered_by(build.clone());
+            let zones = match ci_zone(&config, build.as_ref()) {
+                Some(zone) if zones.is_empty() && zone_id.is_none() => vec![zone],
+                None if zones.is_empty() && zone_id.is_none() => {
+                    if let Some(repo) = build.as_ref().and_then(|b| b.repo.as_deref()) {
+                        msg!("{}", format!("No ci_zones entry for {}; falling back to the profile's zone.", repo).dimmed());
                     }
+                    zones
                 }
-                None => {
-                    msg!("{}", "No profile currently active. Use 'cf-switch use <profile>' first.".yellow());
+                _ => zones,
+            };
+
+            // Use provided zones or fall back to profile's default zone. A
+            // zone id with no name stands in for it, since the profile's zone
+            // may not be the zone the id belongs to.
+            let named = !zones.is_empty();
+            require_zone_or_exit(&config, require_zone, named || zone_id.is_some(), "cfs purge <zone>...");
+            let zones: Vec<String> = match &zone_id {
+                Some(id) if zones.is_empty() => vec![id.clone()],
+                _ if zones.is_empty() => profile.zone.iter().cloned().collect(),
+                _ => zones,
+            };
+            if zones.is_empty() {
+                msg!("{} No zone specified and {} has no default zone.", "Error:".red().bold(), source);
+                msg!("Usage: cfs purge <zone>... or set default zone with: cf-switch add <name> -e <email> -t <token> -z <zone>");
+                std::process::exit(1);
+            }
+            if wait_url.is_some() && zones.len() > 1 {
+                msg!("{} --wait-url only works when purging a single zone.", "Error:".red().bold());
+                std::process::exit(1);
+            }
+
+            if sitemap.is_some() && (zones.len() > 1 || (zone_id.is_some() && !named)) {
+                msg!("{} --sitemap purges one zone, by name so its URLs can be picked out of the sitemap.", "Error:".red().bold());
+                std::process::exit(1);
+            }
+
+            let target = if !tags.is_empty() {
+                api::PurgeTarget::Tags(tags)
+            } else if let Some(location) = &sitemap {
+                api::PurgeTarget::Files(sitemap_files_or_exit(&ctx, location, &zones[0]))
+            } else if !files.is_empty() {
+                api::PurgeTarget::Files(purge_files_or_exit(files))
+            } else if !hosts.is_empty() {
+                api::PurgeTarget::Hosts(hosts)
+            } else if !prefixes.is_empty() {
+                api::PurgeTarget::Prefixes(prefixes)
+            } else {
+                api::PurgeTarget::Everything
+            };
+            if zone_id.is_some() && !named && !matches!(target, api::PurgeTarget::Everything | api::PurgeTarget::Tags(_)) {
+                msg!("{} With --zone-id, give the zone name too so --hosts/--prefixes/--files can be checked against it.", "Error:".red().bold());
+                std::process::exit(1);
+            }
+            for z in &zones {
+                if let Err(e) = validate_purge_target(&target, z) {
+                    msg!("{} {}", "Error:".red().bold(), e);
+                    std::process::exit(1);
+                }
+            }
+
+            let newer_than = match (&if_newer, &if_newer_than_file) {
+                (Some(time), _) => Some(audit::parse_time(time, false).unwrap_or_else(|e| {
+                    msg!("{} --if-newer: {}", "Error:".red().bold(), e);
+                    std::process::exit(1);
+                })),
+                (_, Some(path)) => Some(modified_secs(path).unwrap_or_else(|e| {
+                    msg!("{} --if-newer-than-file: can't read the modification time of {}: {}", "Error:".red().bold(), path.display(), e);
+                    std::process::exit(1);
+                })),
+                _ => None,
+            };
+
+            let mut refused = 0;
+            let mut allowed = Vec::new();
+            for z in zones {
+                // A bare id can't be matched against name patterns, so it gets the same override
+                if zone_id.is_some() && !named && !config.protected_patterns.is_empty() && !i_know_what_im_doing {
+                    msg!("{} Can't check zone {} against protected_patterns without its name.", "Error:".red().bold(), z.bold());
+                    msg!("Give the zone name too, or re-run with --i-know-what-im-doing.");
+                    emit_purge_skipped(&ctx, &z, "protected");
+                    refused += 1;
+                    continue;
+                }
+                // Protected patterns guard against clearing a whole zone; a narrower purge is fine
+                let pattern = protected_pattern_for(&config, &z).filter(|_| matches!(target, api::PurgeTarget::Everything));
+                if let Some(pattern) = pattern {
+                    if !i_know_what_im_doing {
+                        msg!("{} Refusing to purge everything on {}: it matches protected pattern '{}'.", "Error:".red().bold(), z.bold(), pattern);
+                        msg!("Re-run with --i-know-what-im-doing if this is intended.");
+                        emit_purge_skipped(&ctx, &z, "protected");
+                        refused += 1;
+                        continue;
+                    }
+                    msg!("{} {} matches protected pattern '{}', continuing as requested.", "!".yellow().bold(), z.bold(), pattern);
+                }
+                if matches!(target, api::PurgeTarget::Everything) && !profile.allows_purge_everything() && !confirm_purge_everything(&ctx, &source, &z, force) {
+                    emit_purge_skipped(&ctx, &z, "not_allowed");
+                    refused += 1;
+                    continue;
+                }
+                allowed.push(z);
+            }
+
+            if let Some(dir) = watch {
+                if refused > 0 {
                     std::process::exit(1);
                 }
+                watch_and_purge(&ctx, &mut config, &source, &profile, &allowed, &dir, api);
+            }
+
+            let total = refused + allowed.len();
+            let (mut purged, mut skipped, mut up_to_date) = (0, refused, 0);
+            let mut queued = Vec::new();
+            for z in &allowed {
+                if let Some(since) = newer_than {
+                    if let Some(last) = config.last_purge.get(z).copied().filter(|&last| last >= since) {
+                        msg!(
+                            "{} {} was last purged at {}, after {}; nothing to do.",
+                            ctx.symbols.ok.green(),
+                            z.bold(),
+                            audit::format_time(last),
+                            audit::format_time(since)
+                        );
+                        emit_purge_skipped(&ctx, z, "up_to_date");
+                        up_to_date += 1;
+                        continue;
+                    }
+                }
+                if let Some(remaining) = purge_cooldown_remaining(&config, z).filter(|_| !force) {
+                    msg!("{} {} was purged moments ago ({}s of cooldown left).", "!".yellow().bold(), z.bold(), remaining);
+                    let interactive = io::stdin().is_terminal();
+                    if interactive {
+                        show_purge_scope(&profile, z, &target);
+                    }
+                    let confirmed = interactive
+                        && dialoguer::Confirm::new()
+                            .with_prompt("Purge again anyway?")
+                            .default(false)
+                            .interact()
+                            .unwrap_or(false);
+                    if !confirmed {
+                        msg!("Skipped. Use --force to bypass the cooldown.");
+                        emit_purge_skipped(&ctx, z, "cooldown");
+                        skipped += 1;
+                        continue;
+                    }
+                }
+
+                // A given zone id is used as is, without looking the zone up
+                let problem = if force || zone_id.is_some() { None } else { purge_zone_problem(&profile, z, &target) };
+                if let Some((problem, reason)) = problem {
+                    msg!("{} {}", "Error:".red().bold(), problem);
+                    msg!("Skipped. Use --force to purge anyway.");
+                    emit_purge_skipped(&ctx, z, reason);
+                    skipped += 1;
+                    continue;
+                }
+                if confirm && !confirm_purge(&ctx, &profile, z, &target) {
+                    emit_purge_skipped(&ctx, z, "declined");
+                    skipped += 1;
+                    continue;
+                }
+                queued.push(z.clone());
+            }
+
+            // In parallel, every purge finishes before any --wait polling starts
+            let mut results = BulkResults::new(&bulk);
+            let parallel_results = if parallel > 1 && queued.len() > 1 {
+                purge_zones_in_parallel(&ctx, &mut config, &source, &profile, &queued, parallel, &target, bulk.fail_fast)
+            } else {
+                Vec::new()
+            };
+            for (i, z) in queued.iter().enumerate() {
+                let ok = match parallel_results.get(i) {
+                    Some(Some(ok)) => *ok,
+                    Some(None) => {
+                        emit_purge_skipped(&ctx, z, "not_attempted");
+                        results.skip();
+                        continue;
+                    }
+                    None if results.stopping() => {
+                        emit_purge_skipped(&ctx, z, "not_attempted");
+                        results.skip();
+                        continue;
+                    }
+                    None => {
+                        let route = if api { PurgeRoute::Api(zone_id.as_deref()) } else { PurgeRoute::Flarectl };
+                        purge_zone(&ctx, &mut config, &source, &profile, z, route, &target)
+                    }
+                };
+                results.record(ok);
+                if !ok {
+                    continue;
+                }
+                purged += 1;
+                if wait && !results.stopping() {
+                    let url = wait_url.clone().unwrap_or_else(|| format!("https://{}/", z));
+                    let cleared = wait_for_cache_clear(&ctx, &url);
+                    ctx.event("wait_done", serde_json::json!({ "zone": z, "url": url, "ok": cleared }));
+                    results.record(cleared);
+                }
+            }
+            results.report_stop();
+            ctx.event(
+                "summary",
+                serde_json::json!({
+                    "purged": purged,
+                    "up_to_date": up_to_date,
+                    "skipped": skipped,
+                    "failed": results.failed,
+                    "not_attempted": results.not_attempted,
+                }),
+            );
+            if total > 1 {
+                msg!("");
+                let up_to_date = if up_to_date > 0 { format!("{} already up to date, ", up_to_date) } else { String::new() };
+                msg!("{} purged, {}{} skipped, {} failed{}", purged, up_to_date, skipped, results.failed, results.not_attempted_note());
+            }
+            if skipped + results.failed > 0 {
+                std::process::exit(1);
             }
         }
 
-        Some(Commands::AddLamderaApp { domain }) => {
-            let config = load_config();
-            match config.current {
-                Some(name) => {
-                    if let Some(profile) = config.profiles.get(&name) {
-                        // Use provided domain or fall back to profile's default zone
-                        let target_domain = domain.or_else(|| profile.zone.clone());
-
-                        match target_domain {
-                            Some(d) => {
-                                msg!("{} Adding Lamdera DNS record for {} using profile '{}'...", "→".cyan(), d.bold(), name.cyan());
-
-                                let output = Command::new("flarectl")
-                                    .env("CF_API_TOKEN", &profile.token)
-                                    .env_remove("CF_API_EMAIL")
-                                    .env_remove("CF_API_KEY")
-                                    .args(["dns", "create", "--zone", &d, "--type", "CNAME", "--name", "@", "--content", "apps.lamdera.app", "--proxy"])
-                                    .output();
-
-                                match output {
-                                    Ok(result) => {
-                                        if result.status.success() {
-                                            msg!("{} DNS record created: {} -> apps.lamdera.app (proxied)", "✓".green(), d.bold());
-                                            msg!("");
-                                            msg!("{}", "Next step:".bold());
-                                            msg!("DM Lamdera team with: https://{}/ and https://{}.lamdera.app/", d, d.replace('.', "-"));
-                                        } else {
-                                            let stderr = String::from_utf8_lossy(&result.stderr);
-                                            let stdout = String::from_utf8_lossy(&result.stdout);
-                                            if stderr.contains("already exists") || stdout.contains("already exists") {
-                                                msg!("{} DNS record already exists for {}", "✓".yellow(), d.bold());
-                                            } else {
-                                                msg!("{} Failed to create DNS record: {}{}", "Error:".red().bold(), stderr, stdout);
-                                                std::process::exit(1);
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        msg!("{} Failed to run flarectl: {}", "Error:".red().bold(), e);
-                                        msg!("Make sure flarectl is installed: brew install cloudflare/cloudflare/flarectl");
-                                        std::process::exit(1);
-                                    }
-                                }
-                            }
-                            None => {
-                                msg!("{} No domain specified and profile '{}' has no default zone.", "Error:".red().bold(), name);
-                                msg!("Usage: cfs add-lamdera-app <domain>");
-                                std::process::exit(1);
-                            }
-                        }
-                    } else {
-                        msg!("{}", "Current profile no longer exists.".yellow());
-                        std::process::exit(1);
+        Some(Commands::AddLamderaApp { domain, zone_from_cwd, env_file, probe, with_www, www_to, require_zone }) => {
+            let domain = if zone_from_cwd { Some(zone_from_cwd_or_exit(&ctx)) } else { domain };
+            let domain = domain.map(|d| zone_arg_or_exit(&ctx, &d));
+            let records = |d: &str| {
+                let mut records = vec![DnsRecord::lamdera_apex()];
+                if with_www {
+                    records.push(DnsRecord::lamdera_www(d, www_to));
+                }
+                records
+            };
+            if ctx.profile_stub {
+                let d = stub_zone_or_exit(domain, "domain");
+                for record in records(&d) {
+                    print_flarectl_plan(&dns_create_args(&d, &record));
+                }
+                return;
+            }
+            let config = load_config(&ctx);
+            let (source, profile) = resolve_credentials(&ctx, &config, env_file.as_deref());
+
+            // Use provided domain or fall back to profile's default zone
+            require_zone_or_exit(&config, require_zone, domain.is_some(), "cfs add-lamdera-app <domain>");
+            let Some(d) = domain.or_else(|| profile.zone.clone()) else {
+                msg!("{} No domain specified and {} has no default zone.", "Error:".red().bold(), source);
+                msg!("Usage: cfs add-lamdera-app <domain>");
+                std::process::exit(1);
+            };
+
+            let records = records(&d);
+            let noun = if records.len() > 1 { "records" } else { "record" };
+            msg!("{} Adding Lamdera DNS {} for {} using {}...", ctx.symbols.arrow.cyan(), noun, d.bold(), source);
+
+            // Looked up once for every record and to report its ids; if it
+            // fails, each create looks again and flarectl reports the error
+            let resolved = api::zone(&profile.token, &d, api::RateLimited::Retry).ok();
+
+            // Each record is reported on its own; one failing doesn't stop the other
+            let mut created = false;
+            let mut failed = false;
+            for record in &records {
+                let host = record.fqdn(&d);
+                match create_dns_record(&profile, &d, resolved.as_ref(), record) {
+                    DnsOutcome::Created => {
+                        msg!("{} DNS record created: {} -> {} (proxied)", ctx.symbols.ok.green(), host.bold(), record.content);
+                        created = true;
+                    }
+                    DnsOutcome::AlreadyExists => {
+                        msg!("{} Already configured: {} -> {}", ctx.symbols.ok.yellow(), host.bold(), record.content);
+                    }
+                    DnsOutcome::Failed(output) => {
+                        let which = if records.len() > 1 { format!(" for {}", host) } else { String::new() };
+                        msg!("{} Failed to create DNS record{}: {}", "Error:".red().bold(), which, output);
+                        explain_failure(&ctx, &source, ErrorKind::classify(&output));
+                        failed = true;
                     }
                 }
-                None => {
-                    msg!("{}", "No profile currently active. Use 'cf-switch use <profile>' first.".yellow());
+            }
+            if failed {
+                std::process::exit(1);
+            }
+            if let Some(resolved) = &resolved {
+                print_resolved_ids(resolved);
+            }
+            if probe {
+                if created {
+                    // Give the new record a moment before the first request
+                    thread::sleep(PROBE_INITIAL_DELAY);
+                }
+                probe_site(&ctx, &d);
+            }
+            if created {
+                msg!("");
+                msg!("{}", "Next step:".bold());
+                msg!("DM Lamdera team with: https://{}/ and https://{}.lamdera.app/", d, d.replace('.', "-"));
+            }
+        }
+
+        Some(Commands::Api { method, path, data, raw, verbose, env_file }) => {
+            let config = load_config(&ctx);
+            let (source, profile) = resolve_credentials(&ctx, &config, env_file.as_deref());
+            if profile.auth_mode() == AuthMode::Key {
+                msg!("{} {} uses a global API key; 'cf-switch api' needs a scoped API token.", "Error:".red().bold(), source);
+                std::process::exit(1);
+            }
+            let body = data.map(|d| {
+                serde_json::from_str::<serde_json::Value>(&d).unwrap_or_else(|e| {
+                    msg!("{} --data is not valid JSON: {}", "Error:".red().bold(), e);
                     std::process::exit(1);
+                })
+            });
+            let method = method.to_uppercase();
+            if verbose {
+                msg!("{} {} {} using {}", ctx.symbols.arrow.cyan(), method, api::url(&path), source);
+                msg!("  Authorization: Bearer {}", mask::redact(&profile.token, redaction(&ctx, &config)));
+                if let Some(body) = &body {
+                    msg!("  Content-Type: application/json");
+                    msg!("  {}", body);
                 }
             }
+            let response = api::send(&method, &profile.token, &path, body.as_ref(), api::RateLimited::Retry).unwrap_or_else(|e| {
+                msg!("{} {}", "Error:".red().bold(), e);
+                explain_failure(&ctx, &source, ErrorKind::from_api(&e));
+                std::process::exit(1);
+            });
+            if verbose {
+                msg!("{} HTTP {}", ctx.symbols.back.cyan(), response.status);
+            }
+            let formatted = serde_json::from_str::<serde_json::Value>(&response.body)
+                .ok()
+                .filter(|_| !raw)
+                .map(|json| ctx.json_string(&json, false));
+            println!("{}", formatted.as_deref().unwrap_or(response.body.trim_end()));
+            if !(200..300).contains(&response.status) {
+                msg!("{} Cloudflare answered HTTP {}", "Error:".red().bold(), response.status);
+                let kind = match response.status {
+                    401 | 403 => Some(ErrorKind::AuthError),
+                    429 => Some(ErrorKind::RateLimited),
+                    _ => ErrorKind::classify(&response.body),
+                };
+                explain_failure(&ctx, &source, kind);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: &str) -> Config {
+        serde_json::from_str(json).expect("test config parses")
+    }
+
+    const MATCHING: &str = r#"{"profiles":{"prod":{"email":"","token":"t1"},"prod-eu":{"email":"","token":"t2"},
+        "production":{"email":"","token":"t3","aliases":["p"]},"preview":{"email":"","token":"t4"}}}"#;
+
+    #[test]
+    fn exact_name_beats_prefix() {
+        assert_eq!(match_profile_name(&config(MATCHING), "prod").as_deref(), Some("prod"));
+    }
+
+    #[test]
+    fn exact_alias_beats_prefix() {
+        // "p" is also a prefix of every profile
+        assert_eq!(match_profile_name(&config(MATCHING), "p").as_deref(), Some("production"));
+    }
+
+    #[test]
+    fn unique_prefix_expands() {
+        assert_eq!(match_profile_name(&config(MATCHING), "prev").as_deref(), Some("preview"));
+        assert_eq!(match_profile_name(&config(MATCHING), "prod-").as_deref(), Some("prod-eu"));
+    }
+
+    #[test]
+    fn ambiguous_prefix_matches_nothing() {
+        assert_eq!(match_profile_name(&config(MATCHING), "pro"), None);
+        assert_eq!(match_profile_name(&config(MATCHING), "pr"), None);
+    }
+
+    #[test]
+    fn unknown_name_passes_through() {
+        assert_eq!(match_profile_name(&config(MATCHING), "staging").as_deref(), Some("staging"));
+    }
+
+    /// Toggle order c, a, b, d (`order` first, then the rest alphabetically)
+    const CYCLE: &str = r#"{"profiles":{"a":{"email":"","token":"t"},"b":{"email":"","token":"t"},
+        "c":{"email":"","token":"t"},"d":{"email":"","token":"t"}},"order":["c","a"],"current":"a"}"#;
+
+    #[test]
+    fn next_profile_steps_and_wraps() {
+        let config = config(CYCLE);
+        assert_eq!(next_profile(&config, 1), "b");
+        assert_eq!(next_profile(&config, 3), "c");
+        assert_eq!(next_profile(&config, 4), "a");
+    }
+
+    #[test]
+    fn next_profile_wraps_backward() {
+        let config = config(CYCLE);
+        assert_eq!(next_profile(&config, -1), "c");
+        assert_eq!(next_profile(&config, -2), "d");
+        assert_eq!(next_profile(&config, -9), "c");
+    }
+
+    #[test]
+    fn next_profile_handles_huge_counts() {
+        let config = config(CYCLE);
+        // i64::MAX is 3 more than a multiple of 4, and i64::MIN a multiple of 4
+        assert_eq!(next_profile(&config, i64::MAX), "c");
+        assert_eq!(next_profile(&config, i64::MIN), "a");
+        assert_eq!(next_profile(&config, 4_000_000_001), "b");
+    }
+
+    #[test]
+    fn next_profile_without_current_starts_at_an_end() {
+        let mut config = config(CYCLE);
+        config.current = None;
+        assert_eq!(next_profile(&config, 1), "c");
+        assert_eq!(next_profile(&config, -1), "d");
+        assert_eq!(next_profile(&config, i64::MIN), "c");
+        assert_eq!(next_profile(&config, i64::MAX), "b");
+    }
+
+    /// A global API key: 37 hex characters
+    const GLOBAL_KEY: &str = "0123456789abcdef0123456789abcdef01234";
+
+    #[cfg(unix)]
+    #[test]
+    fn written_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("env");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_atomic(&path, "export CF_API_TOKEN=\"secret\"\n").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "export CF_API_TOKEN=\"secret\"\n");
+        // Only the file itself is left behind, no temp files
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn token_profile_exports_only_the_token() {
+        let config = config(r#"{"profiles":{"t":{"email":"ops@example.com","token":"scoped-token"}}}"#);
+        let content = env_file_content(&config, &config.profiles["t"], "t", EnvFormat::Bash);
+        assert!(content.contains("export CF_API_TOKEN=\"scoped-token\"\n"));
+        assert!(content.contains("export CF_API_KEY=\"\"\n"));
+        assert!(content.contains("export CF_API_EMAIL=\"\"\n"));
+        assert!(content.contains("export CF_SWITCH_PROFILE=\"t\"\n"));
+    }
+
+    #[test]
+    fn key_profile_exports_key_and_email() {
+        let config = config(&format!(r#"{{"profiles":{{"k":{{"email":"ops@example.com","token":"{}"}}}}}}"#, GLOBAL_KEY));
+        let profile = &config.profiles["k"];
+        assert!(profile.auth_mode() == AuthMode::Key);
+        let content = env_file_content(&config, profile, "k", EnvFormat::Bash);
+        assert!(content.contains(&format!("export CF_API_KEY=\"{}\"\n", GLOBAL_KEY)));
+        assert!(content.contains("export CF_API_EMAIL=\"ops@example.com\"\n"));
+        assert!(content.contains("export CF_API_TOKEN=\"\"\n"));
+    }
+
+    #[test]
+    fn explicit_auth_overrides_the_guess() {
+        let config = config(&format!(r#"{{"profiles":{{"t":{{"email":"ops@example.com","token":"{}","auth":"token"}}}}}}"#, GLOBAL_KEY));
+        let content = env_file_content(&config, &config.profiles["t"], "t", EnvFormat::Fish);
+        assert!(content.contains(&format!("set -gx CF_API_TOKEN \"{}\"\n", GLOBAL_KEY)));
+        assert!(content.contains("set -gx CF_API_KEY \"\"\n"));
+    }
+
+    fn with_email(email: &str) -> Profile {
+        Profile { email: email.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn email_domain_is_lowercased_after_the_last_at() {
+        assert_eq!(with_email("Ops@Example.COM").email_domain().as_deref(), Some("example.com"));
+        assert_eq!(with_email("\"a@b\"@example.com").email_domain().as_deref(), Some("example.com"));
+        assert_eq!(with_email("ops@").email_domain(), None);
+        assert_eq!(with_email("no-at-sign").email_domain(), None);
+        assert_eq!(with_email("").email_domain(), None);
+    }
+
+    #[test]
+    fn in_email_domain_matches_the_domain_and_its_subdomains() {
+        let profile = with_email("ops@eu.corp.example.com");
+        assert!(profile.in_email_domain("eu.corp.example.com"));
+        assert!(profile.in_email_domain("example.com"));
+        assert!(profile.in_email_domain("@Corp.Example.com "));
+        assert!(!profile.in_email_domain("ample.com"));
+        assert!(!profile.in_email_domain("example.org"));
+        assert!(!profile.in_email_domain("us.corp.example.com"));
+        assert!(!with_email("no-at-sign").in_email_domain("example.com"));
+    }
+
+    #[test]
+    fn normalize_zone_strips_scheme_path_and_port() {
+        assert_eq!(normalize_zone("https://example.com/blog?x=1#top").unwrap(), "example.com");
+        assert_eq!(normalize_zone("http://shop.example.com:8443").unwrap(), "shop.example.com");
+        assert_eq!(normalize_zone("example.com/path").unwrap(), "example.com");
+        assert_eq!(normalize_zone("example.com:443").unwrap(), "example.com");
+    }
+
+    #[test]
+    fn normalize_zone_drops_trailing_dot_case_and_whitespace() {
+        assert_eq!(normalize_zone("example.com.").unwrap(), "example.com");
+        assert_eq!(normalize_zone("  Example.COM ").unwrap(), "example.com");
+        assert_eq!(normalize_zone("example.com").unwrap(), "example.com");
+    }
+
+    #[test]
+    fn normalize_zone_rejects_invalid_hosts() {
+        for input in ["", "localhost", "https://", "exa mple.com", "-bad.com", "bad-.com", "a..com", "under_score.com"] {
+            assert!(normalize_zone(input).is_err(), "{:?} should be rejected", input);
+        }
+        assert!(normalize_zone(&format!("{}.com", "a".repeat(64))).is_err());
+    }
+
+    /// A context that reports writes instead of making them
+    fn dry_ctx(config_path: &Path) -> Ctx {
+        Ctx {
+            strict: false,
+            explain: false,
+            no_write: true,
+            config_path: config_path.to_path_buf(),
+            local_config_path: None,
+            profile: None,
+            profile_stub: false,
+            pretty_json: None,
+            redaction: None,
+            print_source: false,
+            count_use: false,
+            pin: None,
+            outcome: None,
+            events: false,
+            symbols: theme::symbols(false),
+        }
+    }
+
+    /// Profiles `a`, `b` and `c`, with `a` as `previous` and all three in `order`
+    const REFERENCED: &str = r#"{"profiles":{"a":{"email":"","token":"ta"},"b":{"email":"","token":"tb"},"c":{"email":"","token":"tc"}},
+        "current":"b","previous":"a","order":["c","a","b"]}"#;
+
+    #[test]
+    fn removing_a_profile_drops_its_references() {
+        let mut config = config(REFERENCED);
+        let removed = config.profiles.remove("a").unwrap();
+        trash_removed_profile(&dry_ctx(Path::new("unused.json")), &mut config, "a", removed);
+        assert_eq!(config.previous, None);
+        assert_eq!(config.order, ["c", "b"]);
+        assert_eq!(config.trash.len(), 1);
+        assert_eq!(config.current.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn pruning_an_expired_profile_drops_its_references() {
+        let mut config = config(&REFERENCED.replace(r#""ta"}"#, r#""ta","expires_at":1}"#));
+        prune_expired_profiles(&dry_ctx(Path::new("unused.json")), &mut config);
+        assert!(!config.profiles.contains_key("a"));
+        assert_eq!(config.previous, None);
+        assert_eq!(config.order, ["c", "b"]);
+    }
+
+    #[test]
+    fn pruning_the_active_profile_clears_current() {
+        let mut config = config(&REFERENCED.replace(r#""tb"}"#, r#""tb","expires_at":1}"#));
+        prune_expired_profiles(&dry_ctx(Path::new("unused.json")), &mut config);
+        assert_eq!(config.current, None);
+        assert_eq!(config.previous.as_deref(), Some("a"));
+        assert_eq!(config.order, ["c", "a"]);
+    }
+
+    #[test]
+    fn renaming_points_references_at_the_new_name() {
+        let mut config = config(REFERENCED);
+        config.rename_references("a", Some("z"));
+        assert_eq!(config.previous.as_deref(), Some("z"));
+        assert_eq!(config.order, ["c", "z", "b"]);
+        // Unrelated names are left alone
+        config.rename_references("missing", Some("y"));
+        assert_eq!(config.order, ["c", "z", "b"]);
+    }
+
+    #[test]
+    fn unparsable_config_loads_empty_and_is_never_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cf-switch.json");
+        let broken = r#"{"profiles":{"prod":{"email":"ops@example.com","token":"tok",}}}"#;
+        fs::write(&path, broken).unwrap();
+        let mut ctx = dry_ctx(&path);
+        ctx.no_write = false;
+
+        let mut config = read_config_file(&ctx, &path);
+        assert!(config.profiles.is_empty());
+        assert_eq!(config.unparsed.as_deref(), Some(path.as_path()));
+
+        config.profiles.insert("new".to_string(), Profile::default());
+        let err = try_save_config(&ctx, &config).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read_to_string(&path).unwrap(), broken);
+    }
+
+    #[test]
+    fn rate_limit_backoff_doubles_up_to_the_cap() {
+        assert_eq!(rate_limit_backoff(0, None), Duration::from_secs(2));
+        assert_eq!(rate_limit_backoff(2, None), Duration::from_secs(8));
+        assert_eq!(rate_limit_backoff(3, Some(Duration::from_secs(1))), Duration::from_secs(1));
+        // A large `purge_max_retries` used to overflow the shift
+        for attempt in [5, 31, 32, 64, u32::MAX] {
+            assert_eq!(rate_limit_backoff(attempt, None), RATE_LIMIT_MAX_BACKOFF);
+        }
+    }
+
+    #[test]
+    fn rate_limit_retries_survive_a_large_max_retries() {
+        let mut calls = 0;
+        let mut reports = 0;
+        let result = with_rate_limit_retries(u32::MAX, &mut |_| reports += 1, || {
+            calls += 1;
+            if calls < 3 {
+                Err(api::Error::Api { status: 429, messages: Vec::new(), retry_after: Some(Duration::ZERO) })
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(reports, 2);
+    }
+
+    #[test]
+    fn recording_a_purge_keeps_changes_made_since_loading() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cf-switch.json");
+        fs::write(&path, r#"{"profiles":{"prod":{"email":"","token":"t1"}},"current":"prod"}"#).unwrap();
+        let mut ctx = dry_ctx(&path);
+        ctx.no_write = false;
+        let mut config = read_config_file(&ctx, &path);
+
+        // Another cf-switch adds a profile and switches to it mid-purge
+        fs::write(&path, r#"{"profiles":{"prod":{"email":"","token":"t1"},"dev":{"email":"","token":"t2"}},"current":"dev"}"#).unwrap();
+        record_purge(&ctx, &mut config, "example.com", &api::PurgeTarget::Everything, None);
+
+        let saved = read_config_file(&ctx, &path);
+        assert!(saved.profiles.contains_key("dev"));
+        assert_eq!(saved.current.as_deref(), Some("dev"));
+        assert!(saved.last_purge.contains_key("example.com"));
+        assert!(config.last_purge.contains_key("example.com"));
+    }
+
+    /// A config from before each schema version, and what `MIGRATIONS` turns
+    /// it into: one pair per step, so a new step can't land without one
+    const MIGRATION_FIXTURES: [(&str, &str); SCHEMA_VERSION as usize] = [(
+        r#"{"profiles":{"prod":{"email":"ops@example.com","token":"t","zone":"example.com"}},"current":"prod","order":["prod"]}"#,
+        r#"{"profiles":{"prod":{"email":"ops@example.com","token":"t","zone":"example.com"}},"current":"prod","order":["prod"]}"#,
+    )];
+
+    #[test]
+    fn each_migration_step_produces_the_next_version() {
+        for (version, (old, expected)) in MIGRATION_FIXTURES.iter().enumerate() {
+            let mut value: serde_json::Value = serde_json::from_str(old).unwrap();
+            MIGRATIONS[version](&mut value);
+            assert_eq!(value, serde_json::from_str::<serde_json::Value>(expected).unwrap(), "step {} -> {}", version, version + 1);
+        }
+    }
+
+    #[test]
+    fn migrating_from_each_version_reaches_the_current_one() {
+        for (version, (old, _)) in MIGRATION_FIXTURES.iter().enumerate() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("cf-switch.json");
+            let mut old: serde_json::Value = serde_json::from_str(old).unwrap();
+            if version > 0 {
+                old["schema_version"] = version.into();
+            }
+            fs::write(&path, old.to_string()).unwrap();
+            let mut ctx = dry_ctx(&path);
+            ctx.no_write = false;
+
+            let migrated: serde_json::Value = serde_json::from_str(&migrate_config_text(&ctx, &path, old.to_string(), true)).unwrap();
+            assert_eq!(migrated["schema_version"], SCHEMA_VERSION);
+            let mut expected: serde_json::Value = serde_json::from_str(MIGRATION_FIXTURES.last().unwrap().1).unwrap();
+            expected["schema_version"] = SCHEMA_VERSION.into();
+            assert_eq!(migrated, expected);
+            // The file is rewritten, with the original kept beside it
+            assert_eq!(serde_json::from_str::<serde_json::Value>(&fs::read_to_string(&path).unwrap()).unwrap(), migrated);
+            let backup = dir.path().join(format!("cf-switch.json.v{}.bak", version));
+            assert_eq!(serde_json::from_str::<serde_json::Value>(&fs::read_to_string(backup).unwrap()).unwrap(), old);
         }
     }
+
+    #[test]
+    fn unchanged_config_is_not_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cf-switch.json");
+        let content = MIGRATION_FIXTURES[0].0.to_string();
+        fs::write(&path, &content).unwrap();
+        let mut ctx = dry_ctx(&path);
+        ctx.no_write = false;
+        // Version 0 needs no changes, so without always_write there's nothing to back up
+        assert_eq!(migrate_config_text(&ctx, &path, content.clone(), false), content);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        let current = format!(r#"{{"schema_version":{},"profiles":{{}}}}"#, SCHEMA_VERSION);
+        assert_eq!(migrate_config_text(&ctx, &path, current.clone(), true), current);
+    }
+
+    /// A token profile exported under flarectl's names and a custom mapping,
+    /// with a header and footer
+    const GOLDEN: &str = r#"{"env_header":"Project env\n# kept in git","env_footer":"end",
+        "env_var_names":["flarectl",{"token":"MY_TOKEN","zone":"MY_ZONE"}],
+        "profiles":{"p":{"email":"ops@example.com","token":"tok$1","zone":"example.com"}}}"#;
+
+    fn golden(format: EnvFormat) -> String {
+        let config = config(GOLDEN);
+        env_file_content(&config, &config.profiles["p"], "p", format)
+    }
+
+    #[test]
+    fn bash_env_file_is_byte_stable() {
+        let expected = "# managed by cf-switch\n\
+            # Project env\n\
+            # kept in git\n\
+            # Cloudflare credentials - profile: p\n\
+            export CF_API_EMAIL=\"\"\n\
+            export CF_API_KEY=\"\"\n\
+            export CF_API_TOKEN=\"tok\\$1\"\n\
+            export MY_TOKEN=\"tok\\$1\"\n\
+            export MY_ZONE=\"example.com\"\n\
+            export CF_SWITCH_PROFILE=\"p\"\n\
+            # end\n";
+        assert_eq!(golden(EnvFormat::Bash), expected);
+        assert_eq!(golden(EnvFormat::Zsh), expected);
+    }
+
+    #[test]
+    fn fish_env_file_is_byte_stable() {
+        let expected = "# managed by cf-switch\n\
+            # Project env\n\
+            # kept in git\n\
+            # Cloudflare credentials - profile: p\n\
+            set -gx CF_API_EMAIL \"\"\n\
+            set -gx CF_API_KEY \"\"\n\
+            set -gx CF_API_TOKEN \"tok\\$1\"\n\
+            set -gx MY_TOKEN \"tok\\$1\"\n\
+            set -gx MY_ZONE \"example.com\"\n\
+            set -gx CF_SWITCH_PROFILE \"p\"\n\
+            # end\n";
+        assert_eq!(golden(EnvFormat::Fish), expected);
+    }
+
+    #[test]
+    fn nu_env_file_is_a_record_without_comments() {
+        let expected = "{\"CF_API_EMAIL\":\"\",\"CF_API_KEY\":\"\",\"CF_API_TOKEN\":\"tok$1\",\"CF_SWITCH_PROFILE\":\"p\",\
+            \"MY_TOKEN\":\"tok$1\",\"MY_ZONE\":\"example.com\"}\n";
+        assert_eq!(golden(EnvFormat::Nu), expected);
+    }
+
+    #[test]
+    fn env_file_lines_keep_their_order_across_auth_modes() {
+        let names = |content: &str| -> Vec<String> {
+            content.lines().filter_map(|l| l.strip_prefix("export ")?.split('=').next().map(str::to_string)).collect()
+        };
+        let config = config(&format!(
+            r#"{{"profiles":{{"t":{{"email":"","token":"scoped"}},"k":{{"email":"ops@example.com","token":"{}"}}}}}}"#,
+            GLOBAL_KEY
+        ));
+        let token = env_file_content(&config, &config.profiles["t"], "t", EnvFormat::Bash);
+        let key = env_file_content(&config, &config.profiles["k"], "k", EnvFormat::Bash);
+        assert_eq!(names(&token), names(&key));
+        assert!(token.starts_with("# managed by cf-switch\n"));
+    }
 }
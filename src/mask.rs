@@ -0,0 +1,131 @@
+//! `--mask-in-logs`: scrub known tokens from everything printed to stderr,
+//! and the `redaction` level a printed token goes through. Tokens are
+//! registered where they're read, `ProfileBuilder` included, and scrubbed by
+//! `msg!` for every message, and neither has a `Ctx` to hand; so the switch
+//! and the list of secrets are kept here.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Shorter values are too likely to match ordinary text
+const MIN_SECRET_LEN: usize = 8;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// How much of a token `config show`, `trash list`, `api --verbose` and the
+/// like print
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Redaction {
+    /// The whole token
+    None,
+    /// `****` and the last four characters, enough to tell two apart
+    #[default]
+    Last4,
+    /// Just `****`
+    Full,
+}
+
+/// `token` as `level` allows it to be printed
+pub fn redact(token: &str, level: Redaction) -> String {
+    let chars: Vec<char> = token.chars().collect();
+    match level {
+        Redaction::None => token.to_string(),
+        Redaction::Last4 if chars.len() > 8 => format!("****{}", chars[chars.len() - 4..].iter().collect::<String>()),
+        Redaction::Last4 | Redaction::Full => "****".to_string(),
+    }
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Remember a token so later output can be scrubbed of it
+pub fn register(secret: &str) {
+    add_secret(&mut SECRETS.lock().expect("mask secrets lock"), secret);
+}
+
+fn add_secret(secrets: &mut Vec<String>, secret: &str) {
+    if secret.len() < MIN_SECRET_LEN {
+        return;
+    }
+    if !secrets.iter().any(|s| s == secret) {
+        secrets.push(secret.to_string());
+        // Longest first, so a token containing another is replaced whole
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    }
+}
+
+/// `text` with every registered token replaced by `***`, when enabled
+pub fn scrub(text: &str) -> String {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return text.to_string();
+    }
+    scrub_with(text, &SECRETS.lock().expect("mask secrets lock"))
+}
+
+fn scrub_with(text: &str, secrets: &[String]) -> String {
+    secrets.iter().fold(text.to_string(), |out, secret| out.replace(secret.as_str(), "***"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "abcdefgh1234wxyz";
+
+    fn secrets(values: &[&str]) -> Vec<String> {
+        let mut secrets = Vec::new();
+        for value in values {
+            add_secret(&mut secrets, value);
+        }
+        secrets
+    }
+
+    #[test]
+    fn redaction_levels() {
+        assert_eq!(redact(TOKEN, Redaction::None), TOKEN);
+        assert_eq!(redact(TOKEN, Redaction::Last4), "****wxyz");
+        assert_eq!(redact(TOKEN, Redaction::Full), "****");
+    }
+
+    #[test]
+    fn short_tokens_lose_their_last_four() {
+        assert_eq!(redact("12345678", Redaction::Last4), "****");
+        assert_eq!(redact("123456789", Redaction::Last4), "****6789");
+        assert_eq!(redact("", Redaction::None), "");
+    }
+
+    #[test]
+    fn last4_counts_characters_not_bytes() {
+        assert_eq!(redact("ééééééééé", Redaction::Last4), "****éééé");
+    }
+
+    #[test]
+    fn secrets_shorter_than_the_minimum_are_ignored() {
+        let short = "x".repeat(MIN_SECRET_LEN - 1);
+        let long = "y".repeat(MIN_SECRET_LEN);
+        assert_eq!(secrets(&[&short, &long]), vec![long.clone()]);
+        assert_eq!(scrub_with(&format!("{} {}", short, long), &secrets(&[&short, &long])), format!("{} ***", short));
+    }
+
+    #[test]
+    fn secrets_are_registered_once() {
+        assert_eq!(secrets(&[TOKEN, TOKEN]).len(), 1);
+    }
+
+    #[test]
+    fn longest_secret_is_replaced_first() {
+        let inner = "abcdefgh";
+        let registered = secrets(&[inner, TOKEN]);
+        assert_eq!(registered, vec![TOKEN.to_string(), inner.to_string()]);
+        assert_eq!(scrub_with(&format!("token={} key={}", TOKEN, inner), &registered), "token=*** key=***");
+    }
+
+    #[test]
+    fn scrub_leaves_other_text_alone() {
+        assert_eq!(scrub_with("nothing secret here", &secrets(&[TOKEN])), "nothing secret here");
+    }
+}
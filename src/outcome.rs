@@ -0,0 +1,41 @@
+//! `--json` results for the commands that change profiles: one object on
+//! stdout saying what was attempted and whether it worked, with a stable
+//! `code` when it didn't. A command reporting one carries its `Outcome` in
+//! its `Ctx`, so the shared helpers that can fail it report the failure too.
+
+use serde_json::{json, Value};
+
+/// The command a result is due for
+#[derive(Clone)]
+pub struct Outcome {
+    action: &'static str,
+    name: String,
+}
+
+impl Outcome {
+    pub fn new(action: &'static str, name: &str) -> Outcome {
+        Outcome { action, name: name.to_string() }
+    }
+
+    /// Print the success object, with `extra`'s fields merged in
+    pub fn succeed(&self, pretty: bool, extra: Value) {
+        self.print(true, pretty, extra);
+    }
+
+    /// Print the failure object. `code` is one of `not_found`, `ambiguous` (a
+    /// prefix matching several profiles), `exists`, `invalid`, `backend`,
+    /// `write_failed`, `verify_failed`, `switch_failed` or `cancelled`;
+    /// `message` is for people.
+    pub fn fail(&self, pretty: bool, code: &str, message: &str) {
+        self.print(false, pretty, json!({ "code": code, "error": message }));
+    }
+
+    fn print(&self, ok: bool, pretty: bool, extra: Value) {
+        let mut result = json!({ "action": self.action, "name": self.name, "ok": ok });
+        if let (Some(result), Value::Object(extra)) = (result.as_object_mut(), extra) {
+            result.extend(extra);
+        }
+        let text = if pretty { serde_json::to_string_pretty(&result) } else { serde_json::to_string(&result) };
+        println!("{}", text.expect("Failed to serialize result"));
+    }
+}
@@ -0,0 +1,57 @@
+//! Paging long `list` output. Messages go to stderr through `msg!`, which
+//! can't be handed a pager, so while paging is on they're collected here and
+//! shown through `$PAGER` at the end if they don't fit on the screen.
+
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+static BUFFER: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+/// Collect messages from now on, when stderr is a terminal worth paging on
+pub fn start() {
+    if io::stderr().is_terminal() {
+        *BUFFER.lock().expect("pager buffer lock") = Some(Vec::new());
+    }
+}
+
+/// Keep `line` for the pager if paging; false means print it as usual
+pub fn capture(line: &str) -> bool {
+    match BUFFER.lock().expect("pager buffer lock").as_mut() {
+        Some(lines) => {
+            lines.extend(line.lines().map(str::to_string));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Show what was collected: through the pager if it's taller than the
+/// terminal, straight to stderr otherwise or if the pager can't be started
+pub fn finish() {
+    let Some(lines) = BUFFER.lock().expect("pager buffer lock").take() else {
+        return;
+    };
+    let height = console::Term::stderr().size_checked().map_or(usize::MAX, |(rows, _)| usize::from(rows));
+    let text = lines.iter().map(|l| format!("{}\n", l)).collect::<String>();
+    if lines.len() >= height && page(&text).is_ok() {
+        return;
+    }
+    io::stderr().write_all(text.as_bytes()).ok();
+}
+
+/// Run `$PAGER` (default `less`) on the terminal, the way git does
+fn page(text: &str) -> io::Result<()> {
+    let pager = std::env::var("PAGER").ok().filter(|p| !p.trim().is_empty()).unwrap_or_else(|| "less".to_string());
+    let mut command = Command::new("sh");
+    command.args(["-c", &pager]).stdin(Stdio::piped()).stdout(io::stderr());
+    // Keep colors, and quit straight away if it fits after all
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = command.spawn()?;
+    // A pager quit early closes the pipe; that's not an error worth reporting
+    child.stdin.take().expect("pager stdin is piped").write_all(text.as_bytes()).ok();
+    child.wait()?;
+    Ok(())
+}
@@ -0,0 +1,237 @@
+//! Building a `Profile` from user input. Every command that creates or
+//! changes a profile goes through `ProfileBuilder`, so the rules for what
+//! makes a usable profile live in one place.
+
+use crate::credentials::{self, Backend, EnvStore};
+use crate::{is_valid_hostname, mask, AuthMode, Profile};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ValidationError {
+    EmptyName,
+    EmptyEmail,
+    InvalidEmail(String),
+    EmptyToken,
+    WhitespaceInToken,
+    /// A token was given for a profile whose secret comes from this variable
+    TokenWithEnvBackend(String),
+    InvalidZone(String),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::EmptyName => write!(f, "the profile name can't be empty."),
+            ValidationError::EmptyEmail => write!(f, "--email is required for a global API key."),
+            ValidationError::InvalidEmail(email) => write!(f, "'{}' doesn't look like an email address.", email),
+            ValidationError::EmptyToken => write!(f, "--token can't be empty."),
+            ValidationError::WhitespaceInToken => write!(f, "--token contains whitespace; check it was pasted in one piece."),
+            ValidationError::TokenWithEnvBackend(var) => write!(f, "--token can't be used with --backend env; set {} instead.", var),
+            ValidationError::InvalidZone(zone) => write!(f, "'{}' is not a valid zone name.", zone),
+        }
+    }
+}
+
+/// Trimmed email, or why it would make a broken profile
+pub fn validated_email(email: &str) -> Result<String, ValidationError> {
+    let email = email.trim();
+    if email.is_empty() {
+        return Err(ValidationError::EmptyEmail);
+    }
+    if !email.contains('@') {
+        return Err(ValidationError::InvalidEmail(email.to_string()));
+    }
+    Ok(email.to_string())
+}
+
+/// Trimmed token, or why it would make a broken profile
+pub fn validated_token(token: &str) -> Result<String, ValidationError> {
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(ValidationError::EmptyToken);
+    }
+    if token.chars().any(char::is_whitespace) {
+        return Err(ValidationError::WhitespaceInToken);
+    }
+    Ok(token.to_string())
+}
+
+pub struct ProfileBuilder {
+    name: String,
+    profile: Profile,
+    /// A new secret, checked by `build`; without one the profile keeps its own
+    token: Option<String>,
+}
+
+impl ProfileBuilder {
+    pub fn new(name: &str) -> Self {
+        ProfileBuilder::from_profile(name, Profile::default())
+    }
+
+    /// Start from an existing profile, to edit or copy it
+    pub fn from_profile(name: &str, profile: Profile) -> Self {
+        ProfileBuilder { name: name.to_string(), profile, token: None }
+    }
+
+    pub fn email(mut self, email: &str) -> Self {
+        self.profile.email = email.to_string();
+        self
+    }
+
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.profile.backend = backend;
+        self
+    }
+
+    pub fn auth(mut self, auth: Option<AuthMode>) -> Self {
+        self.profile.auth = auth;
+        self
+    }
+
+    pub fn zone(mut self, zone: Option<String>) -> Self {
+        self.profile.zone = zone;
+        self
+    }
+
+    pub fn description(mut self, description: Option<String>) -> Self {
+        self.profile.description = description;
+        self
+    }
+
+    pub fn account_id(mut self, account_id: Option<String>) -> Self {
+        self.profile.account_id = account_id;
+        self
+    }
+
+    pub fn tags(mut self, tags: Vec<String>) -> Self {
+        self.profile.tags = tags;
+        self
+    }
+
+    pub fn expires_at(mut self, expires_at: Option<u64>) -> Self {
+        self.profile.expires_at = expires_at;
+        self
+    }
+
+    /// Allowed is the default, so it's stored only when turned off
+    pub fn allow_purge_everything(mut self, allow: bool) -> Self {
+        self.profile.allow_purge_everything = (!allow).then_some(false);
+        self
+    }
+
+    /// The profile, with email and token trimmed, or the first rule it breaks.
+    /// The email is required only for global API key auth.
+    /// A new token is left in `token` whatever the backend; callers storing it
+    /// elsewhere take it out from there.
+    pub fn build(self) -> Result<Profile, ValidationError> {
+        let ProfileBuilder { name, mut profile, token } = self;
+        if name.trim().is_empty() {
+            return Err(ValidationError::EmptyName);
+        }
+        if let Some(token) = token {
+            if profile.backend == Backend::Env {
+                return Err(ValidationError::TokenWithEnvBackend(EnvStore::var_name(&name)));
+            }
+            // A 1Password reference names a secret rather than being one, and
+            // its item names may contain spaces
+            if credentials::is_op_reference(token.trim()) {
+                profile.token = token.trim().to_string();
+            } else {
+                profile.token = validated_token(&token)?;
+                mask::register(&profile.token);
+            }
+        }
+        // Only the global key is sent with an email; for a token it's optional
+        profile.email = if !profile.email.trim().is_empty() || profile.auth_mode() == AuthMode::Key {
+            validated_email(&profile.email)?
+        } else {
+            String::new()
+        };
+        if let Some(zone) = profile.zone.as_deref().filter(|z| !is_valid_hostname(z)) {
+            return Err(ValidationError::InvalidZone(zone.to_string()));
+        }
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A global API key: 37 hex characters
+    const GLOBAL_KEY: &str = "0123456789abcdef0123456789abcdef01234";
+
+    fn token_profile(name: &str) -> ProfileBuilder {
+        ProfileBuilder::new(name).email("ops@example.com").token("scoped-token")
+    }
+
+    #[test]
+    fn rejects_an_empty_name() {
+        assert!(matches!(token_profile("  ").build(), Err(ValidationError::EmptyName)));
+    }
+
+    #[test]
+    fn rejects_empty_and_broken_tokens() {
+        assert!(matches!(token_profile("p").token("   ").build(), Err(ValidationError::EmptyToken)));
+        assert!(matches!(token_profile("p").token("abc def").build(), Err(ValidationError::WhitespaceInToken)));
+    }
+
+    #[test]
+    fn rejects_a_token_for_the_env_backend() {
+        let result = token_profile("prod").backend(Backend::Env).build();
+        assert!(matches!(result, Err(ValidationError::TokenWithEnvBackend(var)) if var == EnvStore::var_name("prod")));
+    }
+
+    #[test]
+    fn key_auth_requires_an_email() {
+        let missing = ProfileBuilder::new("p").token(GLOBAL_KEY).build();
+        assert!(matches!(missing, Err(ValidationError::EmptyEmail)));
+        let explicit = ProfileBuilder::new("p").token("scoped-token").auth(Some(AuthMode::Key)).build();
+        assert!(matches!(explicit, Err(ValidationError::EmptyEmail)));
+    }
+
+    #[test]
+    fn rejects_an_email_without_at() {
+        let result = token_profile("p").email("ops.example.com").build();
+        assert!(matches!(result, Err(ValidationError::InvalidEmail(email)) if email == "ops.example.com"));
+    }
+
+    #[test]
+    fn rejects_an_invalid_zone() {
+        let result = token_profile("p").zone(Some("not a zone".to_string())).build();
+        assert!(matches!(result, Err(ValidationError::InvalidZone(zone)) if zone == "not a zone"));
+    }
+
+    #[test]
+    fn token_auth_needs_no_email() {
+        let profile = ProfileBuilder::new("p").token("scoped-token").build().unwrap();
+        assert_eq!(profile.email, "");
+        assert!(profile.auth_mode() == AuthMode::Token);
+    }
+
+    #[test]
+    fn trims_email_and_token() {
+        let profile = ProfileBuilder::new("p").email(" ops@example.com ").token(&format!(" {} ", GLOBAL_KEY)).build().unwrap();
+        assert_eq!(profile.email, "ops@example.com");
+        assert_eq!(profile.token, GLOBAL_KEY);
+    }
+
+    #[test]
+    fn keeps_a_1password_reference_with_spaces() {
+        let profile = token_profile("p").token("op://Work Vault/Cloudflare/token").build().unwrap();
+        assert_eq!(profile.token, "op://Work Vault/Cloudflare/token");
+    }
+
+    #[test]
+    fn editing_without_a_token_keeps_the_old_one() {
+        let existing = token_profile("p").build().unwrap();
+        let edited = ProfileBuilder::from_profile("p", existing).zone(Some("example.com".to_string())).build().unwrap();
+        assert_eq!(edited.token, "scoped-token");
+        assert_eq!(edited.zone.as_deref(), Some("example.com"));
+    }
+}
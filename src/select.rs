@@ -0,0 +1,120 @@
+//! `--select`: pull one value out of JSON output with a jq-like path, so
+//! scripts don't need jq. Supports `.` (the whole value), `.field` and
+//! `[index]`, chained: `.zone`, `.[0].name`, `.tags[1]`.
+
+use serde_json::Value;
+
+enum Step {
+    Field(String),
+    Index(usize),
+}
+
+pub struct Query(Vec<Step>);
+
+impl Query {
+    pub fn parse(path: &str) -> Result<Query, String> {
+        let path = path.trim();
+        if !path.starts_with('.') && !path.starts_with('[') {
+            return Err(format!("'{}' should start with '.' or '[', e.g. .zone", path));
+        }
+        let mut steps = Vec::new();
+        let mut rest = path;
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('[') {
+                let (index, after) = after
+                    .split_once(']')
+                    .ok_or_else(|| format!("unclosed '[' in '{}'", path))?;
+                let index = index
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("'{}' isn't a list index in '{}'", index, path))?;
+                steps.push(Step::Index(index));
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                let field = &after[..end];
+                if !field.is_empty() {
+                    if !field.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+                        return Err(format!("'{}' isn't a field name in '{}'", field, path));
+                    }
+                    steps.push(Step::Field(field.to_string()));
+                } else if after.starts_with('.') {
+                    return Err(format!("empty field name in '{}'", path));
+                }
+                rest = &after[end..];
+            } else {
+                return Err(format!("unexpected '{}' in '{}'", rest, path));
+            }
+        }
+        Ok(Query(steps))
+    }
+
+    /// The selected value, or None if the path doesn't exist in `value`
+    pub fn select<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.0.iter().try_fold(value, |current, step| match step {
+            Step::Field(name) => current.get(name.as_str()),
+            Step::Index(index) => current.get(*index),
+        })
+    }
+}
+
+/// How a selected value is printed: strings bare (like `jq -r`), anything else
+/// as JSON, indented if `pretty`
+pub fn render(value: &Value, pretty: bool) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other if pretty => serde_json::to_string_pretty(other).expect("Failed to serialize JSON"),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn select(path: &str, value: &Value) -> Option<Value> {
+        Query::parse(path).expect("query parses").select(value).cloned()
+    }
+
+    #[test]
+    fn selects_fields_and_indexes() {
+        let value = json!({ "zone": "example.com", "tags": ["a", "b"], "profiles": [{ "name": "prod" }], "my-field": 1 });
+        assert_eq!(select(".", &value), Some(value.clone()));
+        assert_eq!(select(".zone", &value), Some(json!("example.com")));
+        assert_eq!(select(".tags[1]", &value), Some(json!("b")));
+        assert_eq!(select(".tags.[0]", &value), Some(json!("a")));
+        assert_eq!(select(".profiles[0].name", &value), Some(json!("prod")));
+        assert_eq!(select(".my-field", &value), Some(json!(1)));
+        assert_eq!(select("[0]", &json!(["x"])), Some(json!("x")));
+        assert_eq!(select(".[ 0 ]", &json!(["x"])), Some(json!("x")));
+    }
+
+    #[test]
+    fn missing_paths_select_nothing() {
+        let value = json!({ "tags": ["a"] });
+        assert_eq!(select(".zone", &value), None);
+        assert_eq!(select(".tags[5]", &value), None);
+        assert_eq!(select(".tags.name", &value), None);
+        assert_eq!(select("[0]", &value), None);
+    }
+
+    #[test]
+    fn rejects_malformed_paths() {
+        let error = |path: &str| Query::parse(path).err().unwrap_or_else(|| panic!("'{}' should be rejected", path));
+        assert!(error("zone").contains("should start with"));
+        assert!(error(".tags[0").contains("unclosed '['"));
+        assert!(error(".tags[x]").contains("isn't a list index"));
+        assert!(error(".tags[-1]").contains("isn't a list index"));
+        assert!(error("..zone").contains("empty field name"));
+        assert!(error(".zo$ne").contains("isn't a field name"));
+        assert!(error(".tags[0]x").contains("unexpected 'x'"));
+    }
+
+    #[test]
+    fn renders_strings_bare() {
+        assert_eq!(render(&json!("example.com"), false), "example.com");
+        assert_eq!(render(&json!({ "a": 1 }), false), r#"{"a":1}"#);
+        assert_eq!(render(&json!([1]), true), "[\n  1\n]");
+    }
+}
@@ -0,0 +1,87 @@
+//! `purge --sitemap`: the page URLs in a sitemap.xml. Only what the
+//! sitemaps.org format needs is understood: the root element says whether
+//! it's a list of pages (`urlset`) or of further sitemaps (`sitemapindex`),
+//! and each entry's `<loc>` is the URL. Comments, CDATA, namespace prefixes
+//! and the five predefined entities are handled; anything else is ignored.
+
+pub enum Sitemap {
+    /// Page URLs
+    Urls(Vec<String>),
+    /// URLs of further sitemaps, from a sitemap index
+    Index(Vec<String>),
+}
+
+/// Local name of the element tag starting at `tag` (just past `<`), without
+/// any namespace prefix, and whether it's a closing tag
+fn tag_name(tag: &str) -> (&str, bool) {
+    let (tag, closing) = match tag.strip_prefix('/') {
+        Some(rest) => (rest, true),
+        None => (tag, false),
+    };
+    let name = tag.split(|c: char| c.is_whitespace() || c == '>' || c == '/').next().unwrap_or_default();
+    (name.rsplit(':').next().unwrap_or(name), closing)
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Text of a `<loc>` element, which may be wrapped in CDATA
+fn loc_text(raw: &str) -> String {
+    let raw = raw.trim();
+    match raw.strip_prefix("<![CDATA[").and_then(|r| r.strip_suffix("]]>")) {
+        Some(inner) => inner.trim().to_string(),
+        None => unescape(raw),
+    }
+}
+
+pub fn parse(xml: &str) -> Result<Sitemap, String> {
+    let mut root: Option<bool> = None;
+    let mut locs = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.split_once("-->").map_or("", |(_, after)| after);
+            continue;
+        }
+        if rest.starts_with('?') || rest.starts_with('!') {
+            continue;
+        }
+        let (name, closing) = tag_name(rest);
+        if closing {
+            continue;
+        }
+        match (root, name) {
+            (None, "urlset") => root = Some(false),
+            (None, "sitemapindex") => root = Some(true),
+            (None, other) => return Err(format!("expected <urlset> or <sitemapindex>, found <{}>", other)),
+            (Some(_), "loc") => {
+                let Some(open_end) = rest.find('>') else {
+                    break;
+                };
+                let body = &rest[open_end + 1..];
+                // CDATA may itself contain `<`, so skip over it before looking for the close tag
+                let search_from = if body.trim_start().starts_with("<![CDATA[") { body.find("]]>").map_or(0, |i| i + 3) } else { 0 };
+                let Some(end) = body[search_from..].find("</").map(|i| i + search_from) else {
+                    return Err("a <loc> is never closed".to_string());
+                };
+                let url = loc_text(&body[..end]);
+                if !url.is_empty() {
+                    locs.push(url);
+                }
+                rest = &body[end..];
+            }
+            _ => {}
+        }
+    }
+    match root {
+        Some(true) => Ok(Sitemap::Index(locs)),
+        Some(false) => Ok(Sitemap::Urls(locs)),
+        None => Err("no <urlset> or <sitemapindex> element".to_string()),
+    }
+}
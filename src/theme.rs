@@ -0,0 +1,28 @@
+//! The symbols status messages are marked with. `--ascii` (or
+//! `CF_SWITCH_ASCII`) swaps them for plain ASCII, for terminals and CI logs
+//! that show the Unicode ones as boxes. The choice travels in `Ctx::symbols`.
+
+pub struct Symbols {
+    /// Something worked
+    pub ok: &'static str,
+    /// One item of several failed
+    pub fail: &'static str,
+    /// Something is starting, or a value changes to another
+    pub arrow: &'static str,
+    /// A response came back
+    pub back: &'static str,
+    /// The active profile
+    pub on: &'static str,
+}
+
+const UNICODE: Symbols = Symbols { ok: "✓", fail: "✗", arrow: "→", back: "←", on: "ON" };
+const ASCII: Symbols = Symbols { ok: "[ok]", fail: "[x]", arrow: "->", back: "<-", on: "[on]" };
+
+/// The symbols to use
+pub fn symbols(ascii: bool) -> &'static Symbols {
+    if ascii {
+        &ASCII
+    } else {
+        &UNICODE
+    }
+}
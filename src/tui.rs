@@ -0,0 +1,110 @@
+//! `cf-switch tui`: a full-screen list of the profiles to act on from the
+//! keyboard. This module only draws and reads keys; each action goes back to
+//! the caller, which carries it out the same way the matching command does.
+//! The screen is drawn on stderr, like the other prompts, so stdout stays
+//! free for the `source` line the shell wrapper runs afterwards.
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::Terminal;
+use std::io::{self, Stderr};
+
+pub struct Row {
+    pub name: String,
+    /// Email, zone and aliases, shown dimmed after the name
+    pub detail: String,
+    pub active: bool,
+}
+
+pub enum Action {
+    Switch(String),
+    Add,
+    Remove(String),
+    Purge(String),
+    Quit,
+}
+
+const KEYS: &str = "enter switch  a add  d remove  p purge  q quit";
+
+/// Show `rows` until a key picks an action, keeping the cursor in
+/// `selected` between calls. `status` is the last action's outcome. The
+/// terminal is restored before returning, so the action can print and prompt.
+pub fn choose(rows: &[Row], selected: &mut usize, status: &str) -> io::Result<Action> {
+    enable_raw_mode()?;
+    let mut stderr = io::stderr();
+    if let Err(e) = execute!(stderr, EnterAlternateScreen) {
+        disable_raw_mode().ok();
+        return Err(e);
+    }
+    let result = Terminal::new(CrosstermBackend::new(io::stderr())).and_then(|mut terminal| event_loop(&mut terminal, rows, selected, status));
+    execute!(io::stderr(), LeaveAlternateScreen).ok();
+    disable_raw_mode().ok();
+    result
+}
+
+fn event_loop(terminal: &mut Terminal<CrosstermBackend<Stderr>>, rows: &[Row], selected: &mut usize, status: &str) -> io::Result<Action> {
+    let mut state = ListState::default();
+    *selected = (*selected).min(rows.len().saturating_sub(1));
+    loop {
+        state.select((!rows.is_empty()).then_some(*selected));
+        terminal.draw(|frame| {
+            let [list_area, status_area, keys_area] =
+                Layout::vertical([Constraint::Min(1), Constraint::Length(1), Constraint::Length(1)]).areas(frame.area());
+            let items: Vec<ListItem> = rows
+                .iter()
+                .map(|row| {
+                    let marker = if row.active { Span::styled("ON ", Style::new().fg(Color::Green).add_modifier(Modifier::BOLD)) } else { Span::raw("   ") };
+                    let name = Span::styled(row.name.clone(), Style::new().fg(Color::Cyan));
+                    let detail = Span::styled(format!("  {}", row.detail), Style::new().add_modifier(Modifier::DIM));
+                    ListItem::new(Line::from(vec![marker, name, detail]))
+                })
+                .collect();
+            let title = if rows.is_empty() { " Cloudflare Profiles (none yet: press a) " } else { " Cloudflare Profiles " };
+            let list = List::new(items)
+                .block(Block::bordered().title(title))
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+            frame.render_stateful_widget(list, list_area, &mut state);
+            frame.render_widget(Paragraph::new(status.to_string()), status_area);
+            frame.render_widget(Paragraph::new(KEYS).style(Style::new().add_modifier(Modifier::DIM)), keys_area);
+        })?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let current = rows.get(*selected).map(|row| row.name.clone());
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => *selected = selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => *selected = (*selected + 1).min(rows.len().saturating_sub(1)),
+            KeyCode::Home | KeyCode::Char('g') => *selected = 0,
+            KeyCode::End | KeyCode::Char('G') => *selected = rows.len().saturating_sub(1),
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(Action::Quit),
+            KeyCode::Char('c') if key.modifiers.contains(event::KeyModifiers::CONTROL) => return Ok(Action::Quit),
+            KeyCode::Char('a') => return Ok(Action::Add),
+            KeyCode::Enter | KeyCode::Char('u') => {
+                if let Some(name) = current {
+                    return Ok(Action::Switch(name));
+                }
+            }
+            KeyCode::Char('d') | KeyCode::Delete => {
+                if let Some(name) = current {
+                    return Ok(Action::Remove(name));
+                }
+            }
+            KeyCode::Char('p') => {
+                if let Some(name) = current {
+                    return Ok(Action::Purge(name));
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! `cf-switch encrypt`: the global config kept on disk as an encrypted
+//! envelope (XChaCha20-Poly1305, with the key derived from a passphrase by
+//! Argon2id) and decrypted in memory. Once unlocked, the passphrase is kept
+//! (zeroized on drop) for the rest of the process: one command can read and
+//! save the file several times, and each save has to re-encrypt it without
+//! asking again, whichever `Config` copy it's saving.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+/// Read instead of prompting, for scripts and CI
+pub const PASSPHRASE_VAR: &str = "CF_SWITCH_PASSPHRASE";
+/// Envelope format version; anything else is refused rather than guessed at
+const VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+static PASSPHRASE: Mutex<Option<Zeroizing<String>>> = Mutex::new(None);
+
+/// What an encrypted config file holds. Byte fields are hex.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    cf_switch_encrypted: u32,
+    /// Argon2id memory (KiB), iterations and lanes, so they can be raised later
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Wrong passphrase, or the file was changed
+    Decrypt,
+    Malformed(String),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Decrypt => write!(f, "wrong passphrase, or the file has been modified"),
+            Error::Malformed(why) => write!(f, "not a valid encrypted config ({})", why),
+            Error::UnsupportedVersion(v) => write!(f, "encrypted with format version {}, which this cf-switch can't read", v),
+        }
+    }
+}
+
+/// Whether `content` is an encrypted config rather than plain JSON
+pub fn is_encrypted(content: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(content).is_ok_and(|v| v.get("cf_switch_encrypted").is_some())
+}
+
+/// Whether saves are being encrypted
+pub fn is_active() -> bool {
+    PASSPHRASE.lock().expect("passphrase lock").is_some()
+}
+
+/// Encrypt saves with `passphrase` from now on; `None` goes back to plain JSON
+pub fn set_passphrase(passphrase: Option<Zeroizing<String>>) {
+    *PASSPHRASE.lock().expect("passphrase lock") = passphrase;
+}
+
+/// Decrypt `content` with `passphrase`, keeping the passphrase for saves if it works
+pub fn unlock(content: &str, passphrase: Zeroizing<String>) -> Result<String, Error> {
+    let plaintext = open(content, &passphrase)?;
+    set_passphrase(Some(passphrase));
+    Ok(plaintext)
+}
+
+fn open(content: &str, passphrase: &str) -> Result<String, Error> {
+    let envelope: Envelope = serde_json::from_str(content).map_err(|e| Error::Malformed(e.to_string()))?;
+    if envelope.cf_switch_encrypted != VERSION {
+        return Err(Error::UnsupportedVersion(envelope.cf_switch_encrypted));
+    }
+    let params = Params::new(envelope.m_cost, envelope.t_cost, envelope.p_cost, Some(KEY_LEN))
+        .map_err(|e| Error::Malformed(e.to_string()))?;
+    let salt = from_hex(&envelope.salt).ok_or_else(|| Error::Malformed("bad salt".to_string()))?;
+    let nonce = from_hex(&envelope.nonce)
+        .filter(|n| n.len() == 24)
+        .ok_or_else(|| Error::Malformed("bad nonce".to_string()))?;
+    let ciphertext = from_hex(&envelope.ciphertext).ok_or_else(|| Error::Malformed("bad ciphertext".to_string()))?;
+    let cipher = cipher(passphrase, &salt, params)?;
+    let plaintext = cipher.decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice()).map_err(|_| Error::Decrypt)?;
+    String::from_utf8(plaintext).map_err(|_| Error::Decrypt)
+}
+
+/// `content` as it should be written: encrypted under a fresh salt and
+/// nonce if a passphrase is set, unchanged otherwise
+pub fn seal(content: &str) -> String {
+    let guard = PASSPHRASE.lock().expect("passphrase lock");
+    match guard.as_ref() {
+        Some(passphrase) => seal_with(content, passphrase),
+        None => content.to_string(),
+    }
+}
+
+fn seal_with(content: &str, passphrase: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let params = Params::default();
+    let cipher = cipher(passphrase, &salt, params.clone()).expect("default Argon2 parameters are valid");
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, content.as_bytes()).expect("Failed to encrypt config");
+    let envelope = Envelope {
+        cf_switch_encrypted: VERSION,
+        m_cost: params.m_cost(),
+        t_cost: params.t_cost(),
+        p_cost: params.p_cost(),
+        salt: to_hex(&salt),
+        nonce: to_hex(&nonce),
+        ciphertext: to_hex(&ciphertext),
+    };
+    serde_json::to_string_pretty(&envelope).expect("Failed to serialize encrypted config")
+}
+
+fn cipher(passphrase: &str, salt: &[u8], params: Params) -> Result<XChaCha20Poly1305, Error> {
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| Error::Malformed(e.to_string()))?;
+    Ok(XChaCha20Poly1305::new(key.as_ref().into()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"{"profiles":{"prod":{"email":"ops@example.com","token":"secret-token"}}}"#;
+
+    /// `sealed` with one field of its envelope changed
+    fn with_field(sealed: &str, field: &str, value: serde_json::Value) -> String {
+        let mut envelope: serde_json::Value = serde_json::from_str(sealed).unwrap();
+        envelope[field] = value;
+        envelope.to_string()
+    }
+
+    #[test]
+    fn round_trips() {
+        let sealed = seal_with(CONFIG, "correct horse");
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.contains("secret-token"));
+        assert_eq!(open(&sealed, "correct horse").unwrap(), CONFIG);
+        // A fresh salt and nonce every time
+        assert_ne!(seal_with(CONFIG, "correct horse"), sealed);
+    }
+
+    #[test]
+    fn wrong_passphrase_fails_to_decrypt() {
+        let sealed = seal_with(CONFIG, "correct horse");
+        assert!(matches!(open(&sealed, "battery staple"), Err(Error::Decrypt)));
+    }
+
+    #[test]
+    fn tampered_ciphertext_fails_to_decrypt() {
+        let sealed = seal_with(CONFIG, "correct horse");
+        let envelope: serde_json::Value = serde_json::from_str(&sealed).unwrap();
+        let mut ciphertext = envelope["ciphertext"].as_str().unwrap().to_string();
+        let flipped = if ciphertext.starts_with('0') { "1" } else { "0" };
+        ciphertext.replace_range(..1, flipped);
+        let tampered = with_field(&sealed, "ciphertext", ciphertext.into());
+        assert!(matches!(open(&tampered, "correct horse"), Err(Error::Decrypt)));
+    }
+
+    #[test]
+    fn unsupported_version_is_refused() {
+        let sealed = with_field(&seal_with(CONFIG, "correct horse"), "cf_switch_encrypted", 2.into());
+        assert!(matches!(open(&sealed, "correct horse"), Err(Error::UnsupportedVersion(2))));
+    }
+
+    #[test]
+    fn malformed_envelopes_are_reported() {
+        let sealed = seal_with(CONFIG, "correct horse");
+        assert!(matches!(open(&with_field(&sealed, "nonce", "abcd".into()), "correct horse"), Err(Error::Malformed(_))));
+        assert!(matches!(open(&with_field(&sealed, "salt", "xyz".into()), "correct horse"), Err(Error::Malformed(_))));
+        assert!(matches!(open(r#"{"cf_switch_encrypted":1}"#, "correct horse"), Err(Error::Malformed(_))));
+    }
+
+    #[test]
+    fn plain_json_is_not_encrypted() {
+        assert!(!is_encrypted(CONFIG));
+        assert!(!is_encrypted("not json"));
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(to_hex(&[0, 15, 255]), "000fff");
+        assert_eq!(from_hex("000fff"), Some(vec![0, 15, 255]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
@@ -0,0 +1,36 @@
+//! A config that doesn't parse is reported with its location and never
+//! overwritten: commands carry on as if it were empty but refuse to save.
+
+mod common;
+
+use common::{stderr, Sandbox};
+
+/// A trailing comma on line 3
+const BROKEN: &str = "{\n  \"profiles\": {\n    \"prod\": {\"email\": \"ops@example.com\", \"token\": \"prod-token-0000\",}\n  }\n}\n";
+
+#[test]
+fn parse_error_is_reported_with_its_location() {
+    let output = Sandbox::new().with_config(BROKEN).run(&["list"]);
+    let stderr = stderr(&output);
+    assert!(stderr.contains("Invalid config file"), "{}", stderr);
+    assert!(stderr.contains("line 3"), "{}", stderr);
+    assert!(stderr.contains("nothing will be saved"), "{}", stderr);
+}
+
+#[test]
+fn saving_over_a_broken_config_is_refused() {
+    let sandbox = Sandbox::new().with_config(BROKEN);
+    let output = sandbox.run(&["add", "staging", "-e", "dev@example.com", "-t", "staging-token-0000"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("couldn't be parsed"), "{}", stderr(&output));
+    assert_eq!(std::fs::read_to_string(sandbox.config_path()).unwrap(), BROKEN);
+}
+
+#[test]
+fn strict_makes_a_parse_error_fatal() {
+    let sandbox = Sandbox::new().with_config(BROKEN);
+    let output = sandbox.run(&["--strict", "list"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("line 3"), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("nothing will be saved"), "{}", stderr(&output));
+}
@@ -0,0 +1,60 @@
+//! Running the built binary against a throwaway home and config, so tests
+//! never see (or write) the real ~/.cf-switch.json and env files.
+
+// Each test binary uses only some of these helpers
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+pub struct Sandbox {
+    pub home: tempfile::TempDir,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        Sandbox { home: tempfile::tempdir().expect("create temp home") }
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.home.path().join("cf-switch.json")
+    }
+
+    /// Write `json` as the config `run` points at
+    pub fn with_config(self, json: &str) -> Self {
+        std::fs::write(self.config_path(), json).expect("write config");
+        self
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.run_with_config(&self.config_path(), args)
+    }
+
+    /// Run cf-switch with `--config <config>`, HOME in the sandbox and no
+    /// Cloudflare or cf-switch variables inherited from the test's environment
+    pub fn run_with_config(&self, config: &Path, args: &[&str]) -> Output {
+        let mut command = self.command();
+        command.arg("--config").arg(config).args(args).output().expect("run cf-switch")
+    }
+
+    /// Run cf-switch from `dir` with no `--config`, so the global config is
+    /// `$HOME/.cf-switch.json` and project configs are discovered
+    pub fn run_in(&self, dir: &Path, args: &[&str]) -> Output {
+        self.command().current_dir(dir).args(args).output().expect("run cf-switch")
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_cf-switch"));
+        for (key, _) in std::env::vars() {
+            if key.starts_with("CF_") || key.starts_with("CLOUDFLARE_") || key == "XDG_CONFIG_HOME" {
+                command.env_remove(key);
+            }
+        }
+        command.env("HOME", self.home.path()).env("NO_COLOR", "1");
+        command
+    }
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
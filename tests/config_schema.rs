@@ -0,0 +1,22 @@
+//! Configs from other schema versions, as the binary loads them.
+
+mod common;
+
+use common::{stderr, Sandbox};
+
+#[test]
+fn newer_schema_is_refused_untouched() {
+    let config = r#"{"schema_version":999,"profiles":{"prod":{"email":"","token":"t"}},"current":"prod"}"#;
+    let sandbox = Sandbox::new().with_config(config);
+    let output = sandbox.run(&["list"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("newer than this cf-switch understands"), "{}", stderr(&output));
+    assert_eq!(std::fs::read_to_string(sandbox.config_path()).unwrap(), config);
+}
+
+#[test]
+fn unversioned_config_loads() {
+    let sandbox = Sandbox::new().with_config(r#"{"profiles":{"prod":{"email":"","token":"t"}},"current":"prod"}"#);
+    let output = sandbox.run(&["current"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+}
@@ -0,0 +1,51 @@
+//! `current`'s exit status contract: 0 with an active profile, 1 with none,
+//! 2 when the config names a profile that no longer exists, on every output path.
+
+mod common;
+
+use common::{stderr, Sandbox};
+
+const ACTIVE: &str = r#"{"profiles":{"prod":{"email":"ops@example.com","token":"tok"}},"current":"prod"}"#;
+const NONE_ACTIVE: &str = r#"{"profiles":{"prod":{"email":"ops@example.com","token":"tok"}}}"#;
+const MISSING: &str = r#"{"profiles":{"prod":{"email":"ops@example.com","token":"tok"}},"current":"gone"}"#;
+
+fn exit_code(config: &str, args: &[&str]) -> Option<i32> {
+    Sandbox::new().with_config(config).run(args).status.code()
+}
+
+#[test]
+fn active_profile_exits_0() {
+    assert_eq!(exit_code(ACTIVE, &["current"]), Some(0));
+    assert_eq!(exit_code(ACTIVE, &["current", "--json"]), Some(0));
+}
+
+#[test]
+fn no_active_profile_exits_1() {
+    assert_eq!(exit_code(NONE_ACTIVE, &["current"]), Some(1));
+    assert_eq!(exit_code(NONE_ACTIVE, &["current", "--json"]), Some(1));
+    assert_eq!(exit_code(NONE_ACTIVE, &["current", "--verify-env"]), Some(1));
+    assert_eq!(exit_code(NONE_ACTIVE, &["current", "--json", "--verify-env"]), Some(1));
+}
+
+#[test]
+fn missing_active_profile_exits_2() {
+    let output = Sandbox::new().with_config(MISSING).run(&["current"]);
+    assert!(stderr(&output).contains("no longer exists"), "{}", stderr(&output));
+    assert_eq!(exit_code(MISSING, &["current"]), Some(2));
+    assert_eq!(exit_code(MISSING, &["current", "--json"]), Some(2));
+    assert_eq!(exit_code(MISSING, &["current", "--verify-env"]), Some(2));
+    assert_eq!(exit_code(MISSING, &["current", "--json", "--verify-env"]), Some(2));
+}
+
+#[test]
+fn stale_shell_exits_1_with_verify_env() {
+    // The sandbox exports none of the active profile's variables
+    assert_eq!(exit_code(ACTIVE, &["current", "--verify-env"]), Some(1));
+    assert_eq!(exit_code(ACTIVE, &["current", "--json", "--verify-env"]), Some(1));
+}
+
+#[test]
+fn json_still_printed_when_inactive() {
+    let output = Sandbox::new().with_config(NONE_ACTIVE).run(&["current", "--json"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "null");
+}
@@ -0,0 +1,42 @@
+//! The `--json` outcome codes for names that don't resolve to one profile:
+//! `ambiguous` for a prefix of several, `not_found` for none.
+
+mod common;
+
+use common::Sandbox;
+
+const PROFILES: &str = r#"{"profiles":{
+  "prod-eu":{"email":"eu@example.com","token":"prod-eu-token-0000"},
+  "prod-us":{"email":"us@example.com","token":"prod-us-token-0000"}
+}}"#;
+
+fn outcome(args: &[&str]) -> serde_json::Value {
+    let output = Sandbox::new().with_config(PROFILES).run(args);
+    assert_eq!(output.status.code(), Some(1), "{}", String::from_utf8_lossy(&output.stderr));
+    serde_json::from_slice(&output.stdout).expect("one JSON object on stdout")
+}
+
+#[test]
+fn ambiguous_prefix_is_reported_as_ambiguous() {
+    for args in [
+        &["edit", "prod", "--desc", "x", "--json"][..],
+        &["remove", "prod", "--yes", "--json"],
+        &["use", "prod", "--json"],
+    ] {
+        let result = outcome(args);
+        assert_eq!(result["ok"], false, "{:?}", args);
+        assert_eq!(result["code"], "ambiguous", "{:?}", args);
+        assert_eq!(result["error"], "'prod' matches several profiles", "{:?}", args);
+    }
+}
+
+#[test]
+fn unknown_name_is_still_not_found() {
+    for args in [
+        &["edit", "staging", "--desc", "x", "--json"][..],
+        &["remove", "staging", "--yes", "--json"],
+        &["use", "staging", "--json"],
+    ] {
+        assert_eq!(outcome(args)["code"], "not_found", "{:?}", args);
+    }
+}
@@ -0,0 +1,89 @@
+//! A project `.cf-switch.json` merged over the global config: project
+//! profiles win by name, only `profiles` is read from the project file, and
+//! edits go back to the file the profile came from.
+
+mod common;
+
+use common::Sandbox;
+use std::path::{Path, PathBuf};
+
+const GLOBAL: &str = r#"{
+  "profiles": {
+    "prod": {"email": "ops@example.com", "token": "global-prod-token"},
+    "staging": {"email": "global@example.com", "token": "global-staging-token"}
+  },
+  "current": "prod"
+}"#;
+
+const PROJECT: &str = r#"{
+  "profiles": {
+    "staging": {"email": "project@example.com", "token": "project-staging-token"},
+    "preview": {"email": "preview@example.com", "token": "project-preview-token"}
+  },
+  "current": "preview"
+}"#;
+
+/// A sandbox with the global config in $HOME and a project config two
+/// directories below it; returns the directory to run from
+fn sandbox() -> (Sandbox, PathBuf) {
+    let sandbox = Sandbox::new();
+    std::fs::write(sandbox.home.path().join(".cf-switch.json"), GLOBAL).unwrap();
+    let project = sandbox.home.path().join("repo");
+    let nested = project.join("src");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::write(project.join(".cf-switch.json"), PROJECT).unwrap();
+    (sandbox, nested)
+}
+
+fn json(sandbox: &Sandbox, dir: &Path, args: &[&str]) -> serde_json::Value {
+    let output = sandbox.run_in(dir, args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+fn email(profiles: &serde_json::Value, name: &str) -> String {
+    let profile = profiles.as_array().unwrap().iter().find(|p| p["name"] == name).unwrap_or_else(|| panic!("no profile {}", name));
+    profile["email"].as_str().unwrap().to_string()
+}
+
+fn read(path: &Path) -> serde_json::Value {
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn project_profiles_shadow_global_ones() {
+    let (sandbox, dir) = sandbox();
+    let profiles = json(&sandbox, &dir, &["list", "--json"]);
+    assert_eq!(email(&profiles, "staging"), "project@example.com");
+    assert_eq!(email(&profiles, "preview"), "preview@example.com");
+    assert_eq!(email(&profiles, "prod"), "ops@example.com");
+}
+
+#[test]
+fn only_profiles_are_read_from_the_project_file() {
+    let (sandbox, dir) = sandbox();
+    assert_eq!(json(&sandbox, &dir, &["current", "--json"])["name"], "prod");
+}
+
+#[test]
+fn global_flag_ignores_the_project_file() {
+    let (sandbox, dir) = sandbox();
+    let profiles = json(&sandbox, &dir, &["--global", "list", "--json"]);
+    assert_eq!(email(&profiles, "staging"), "global@example.com");
+    assert!(profiles.as_array().unwrap().iter().all(|p| p["name"] != "preview"));
+}
+
+#[test]
+fn edits_are_saved_to_the_file_the_profile_came_from() {
+    let (sandbox, dir) = sandbox();
+    let output = sandbox.run_in(&dir, &["edit", "staging", "--desc", "shadowing"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let project = read(&sandbox.home.path().join("repo/.cf-switch.json"));
+    assert_eq!(project["profiles"]["staging"]["description"], "shadowing");
+    assert_eq!(project["profiles"]["staging"]["email"], "project@example.com");
+    let global = read(&sandbox.home.path().join(".cf-switch.json"));
+    assert_eq!(global["profiles"]["staging"]["email"], "global@example.com");
+    assert!(global["profiles"]["staging"].get("description").is_none());
+    assert!(global["profiles"].get("preview").is_none());
+}
@@ -0,0 +1,77 @@
+//! A profile's `allow_purge_everything`: when false, purging a whole zone is
+//! refused without --force and a typed confirmation, and narrower purges are
+//! unaffected. Allowed purges are stopped before any network call by a
+//! `last_purge` newer than `--if-newer`, which reports the zone up to date.
+
+mod common;
+
+use common::{stderr, Sandbox};
+
+fn sandbox(allow: Option<bool>) -> Sandbox {
+    let allow = allow.map(|a| format!(r#","allow_purge_everything":{}"#, a)).unwrap_or_default();
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    Sandbox::new().with_config(&format!(
+        r#"{{"profiles":{{"prod":{{"email":"ops@example.com","token":"prod-token-0000","zone":"example.com"{}}}}},
+            "current":"prod","last_purge":{{"example.com":{}}}}}"#,
+        allow, now
+    ))
+}
+
+/// The `purge_skipped` reasons from a `--output json` event stream
+fn skipped_reasons(stdout: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|event| event["event"] == "purge_skipped")
+        .map(|event| event["reason"].as_str().unwrap_or_default().to_string())
+        .collect()
+}
+
+#[test]
+fn purge_everything_is_allowed_by_default() {
+    for allow in [None, Some(true)] {
+        let output = sandbox(allow).run(&["purge", "example.com", "--if-newer", "1", "--output", "json"]);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+        assert_eq!(skipped_reasons(&output.stdout), ["up_to_date"]);
+    }
+}
+
+#[test]
+fn purge_everything_is_refused_without_force() {
+    let output = sandbox(Some(false)).run(&["purge", "example.com", "--if-newer", "1", "--output", "json"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("doesn't allow purging everything on example.com"), "{}", stderr(&output));
+    assert_eq!(skipped_reasons(&output.stdout), ["not_allowed"]);
+}
+
+#[test]
+fn forced_purge_everything_still_needs_the_zone_typed_in() {
+    // No terminal on stdin in tests, so there's nowhere to type it
+    let output = sandbox(Some(false)).run(&["purge", "example.com", "--force", "--if-newer", "1", "--output", "json"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("there's no terminal to ask on"), "{}", stderr(&output));
+    assert_eq!(skipped_reasons(&output.stdout), ["not_allowed"]);
+}
+
+#[test]
+fn default_zone_is_guarded_too() {
+    let output = sandbox(Some(false)).run(&["purge", "--if-newer", "1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("doesn't allow purging everything on example.com"), "{}", stderr(&output));
+}
+
+#[test]
+fn narrower_purges_are_not_guarded() {
+    let output = sandbox(Some(false)).run(&["purge", "example.com", "--api", "--hosts", "www.example.com", "--if-newer", "1", "--output", "json"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(skipped_reasons(&output.stdout), ["up_to_date"]);
+}
+
+#[test]
+fn edit_turns_the_guard_on_and_off() {
+    let sandbox = sandbox(None);
+    assert!(sandbox.run(&["edit", "prod", "--allow-purge-everything", "false"]).status.success());
+    assert_eq!(sandbox.run(&["purge", "example.com", "--if-newer", "1"]).status.code(), Some(1));
+    assert!(sandbox.run(&["edit", "prod", "--allow-purge-everything", "true"]).status.success());
+    assert_eq!(sandbox.run(&["purge", "example.com", "--if-newer", "1"]).status.code(), Some(0));
+}
@@ -0,0 +1,102 @@
+//! `remove` keeps the last few profiles in the trash and `restore` brings
+//! them back, without ever overwriting a profile that exists.
+
+mod common;
+
+use common::{stderr, Sandbox};
+
+/// Profiles `p1`..`p6`; one more than the trash holds
+const SIX: &str = r#"{"profiles":{
+  "p1":{"email":"p1@example.com","token":"token-p1-0000"},
+  "p2":{"email":"p2@example.com","token":"token-p2-0000"},
+  "p3":{"email":"p3@example.com","token":"token-p3-0000"},
+  "p4":{"email":"p4@example.com","token":"token-p4-0000"},
+  "p5":{"email":"p5@example.com","token":"token-p5-0000"},
+  "p6":{"email":"p6@example.com","token":"token-p6-0000","zone":"example.com","tags":["web"]}
+}}"#;
+
+fn config(sandbox: &Sandbox) -> serde_json::Value {
+    serde_json::from_str(&std::fs::read_to_string(sandbox.config_path()).unwrap()).unwrap()
+}
+
+fn trashed(sandbox: &Sandbox) -> Vec<String> {
+    let config = config(sandbox);
+    let Some(trash) = config["trash"].as_array() else {
+        return Vec::new();
+    };
+    trash.iter().map(|entry| entry[0].as_str().unwrap().to_string()).collect()
+}
+
+fn remove(sandbox: &Sandbox, name: &str) {
+    let output = sandbox.run(&["remove", name, "--yes"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn restore_brings_back_the_whole_profile() {
+    let sandbox = Sandbox::new().with_config(SIX);
+    let before = config(&sandbox)["profiles"]["p6"].clone();
+    remove(&sandbox, "p6");
+    assert!(config(&sandbox)["profiles"].get("p6").is_none());
+    assert_eq!(trashed(&sandbox), ["p6"]);
+
+    let output = sandbox.run(&["restore", "p6"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(config(&sandbox)["profiles"]["p6"], before);
+    assert!(trashed(&sandbox).is_empty());
+}
+
+#[test]
+fn restore_without_a_name_takes_the_most_recent() {
+    let sandbox = Sandbox::new().with_config(SIX);
+    remove(&sandbox, "p1");
+    remove(&sandbox, "p2");
+    assert!(sandbox.run(&["restore"]).status.success());
+    assert!(config(&sandbox)["profiles"].get("p2").is_some());
+    assert_eq!(trashed(&sandbox), ["p1"]);
+}
+
+#[test]
+fn restore_from_an_empty_trash_fails() {
+    let output = Sandbox::new().with_config(SIX).run(&["restore"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("The trash is empty"), "{}", stderr(&output));
+}
+
+#[test]
+fn oldest_profiles_are_evicted_past_the_trash_size() {
+    let sandbox = Sandbox::new().with_config(SIX);
+    for name in ["p1", "p2", "p3", "p4", "p5", "p6"] {
+        remove(&sandbox, name);
+    }
+    assert_eq!(trashed(&sandbox), ["p2", "p3", "p4", "p5", "p6"]);
+
+    let output = sandbox.run(&["restore", "p1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("No removed profile named 'p1'"), "{}", stderr(&output));
+}
+
+#[test]
+fn removing_a_name_twice_keeps_only_the_latest() {
+    let sandbox = Sandbox::new().with_config(SIX);
+    remove(&sandbox, "p1");
+    assert!(sandbox.run(&["add", "p1", "-e", "new@example.com", "-t", "token-new-0000"]).status.success());
+    remove(&sandbox, "p1");
+    assert_eq!(trashed(&sandbox), ["p1"]);
+    assert!(sandbox.run(&["restore", "p1"]).status.success());
+    assert_eq!(config(&sandbox)["profiles"]["p1"]["email"], "new@example.com");
+}
+
+#[test]
+fn restore_never_overwrites_an_existing_profile() {
+    let sandbox = Sandbox::new().with_config(SIX);
+    remove(&sandbox, "p1");
+    assert!(sandbox.run(&["add", "p1", "-e", "new@example.com", "-t", "token-new-0000"]).status.success());
+
+    let output = sandbox.run(&["restore", "p1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("already exists"), "{}", stderr(&output));
+    assert_eq!(config(&sandbox)["profiles"]["p1"]["email"], "new@example.com");
+    // Still in the trash, to restore once the name is free
+    assert_eq!(trashed(&sandbox), ["p1"]);
+}
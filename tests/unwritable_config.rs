@@ -0,0 +1,32 @@
+//! A config in a directory cf-switch can't write to fails cleanly, with the
+//! hint about pointing --config elsewhere, rather than panicking.
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::fs;
+
+#[cfg(unix)]
+#[test]
+fn add_to_a_read_only_config_dir_fails_with_a_hint() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let sandbox = Sandbox::new();
+    let dir = sandbox.home.path().join("locked");
+    fs::create_dir(&dir).unwrap();
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o500)).unwrap();
+    // Permissions don't bind root; there's nothing to test if the directory is still writable
+    if fs::write(dir.join("probe"), "").is_ok() {
+        eprintln!("skipping: {} is writable despite mode 0500 (running as root?)", dir.display());
+        return;
+    }
+
+    let output = sandbox.run_with_config(&dir.join("cf-switch.json"), &["add", "prod", "-e", "ops@example.com", "-t", "scoped-token"]);
+    let stderr = stderr(&output);
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("Failed to save the config"), "{}", stderr);
+    assert!(stderr.contains("--config <path> or CF_SWITCH_CONFIG"), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}